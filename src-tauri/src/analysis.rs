@@ -0,0 +1,166 @@
+// Single-position engine searches with a fixed limit, shared by batch analysis and game review.
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+use crate::engine::{EngineEvent, EngineProcess, DEFAULT_HANDSHAKE_TIMEOUT_MS};
+use crate::match_runner::EngineSpec;
+use crate::uci::{self, InfoLine, Score, UciMessage, Wdl};
+
+// Extra time an engine gets to answer `stop` once the search should have finished
+const STOP_GRACE: Duration = Duration::from_millis(1000);
+
+/// How far to search each position, e.g. `{"depth": 20}` or `{"movetime": 1000}`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchLimit {
+    Depth(u32),
+    Nodes(u64),
+    Movetime(u64),
+}
+
+impl SearchLimit {
+    pub fn go_command(self) -> String {
+        match self {
+            SearchLimit::Depth(depth) => format!("go depth {}", depth),
+            SearchLimit::Nodes(nodes) => format!("go nodes {}", nodes),
+            SearchLimit::Movetime(ms) => format!("go movetime {}", ms),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SearchResult {
+    pub best_move: String,
+    pub ponder: Option<String>,
+    pub depth: Option<u32>,
+    pub score: Option<Score>,
+    pub wdl: Option<Wdl>,
+    pub nodes: Option<u64>,
+    pub pv: Vec<String>,
+    pub time_ms: u64,
+}
+
+/// A UCI engine kept running between searches.
+pub struct Analyser {
+    spec: EngineSpec,
+    process: Option<EngineProcess>,
+}
+
+impl Analyser {
+    pub fn new(spec: EngineSpec) -> Self {
+        Analyser { spec, process: None }
+    }
+
+    /// Start the engine if it is not running (first search, or after a crash) and wait until it is ready.
+    fn ensure_started(&mut self) -> Result<&mut EngineProcess, String> {
+        let timeout = Duration::from_millis(DEFAULT_HANDSHAKE_TIMEOUT_MS);
+        if !self.process.as_mut().is_some_and(|p| p.is_running()) {
+            self.process = None;
+            let spec = &self.spec;
+            let mut process = EngineProcess::spawn_with(&spec.path, &spec.args, &spec.resources, spec.encoding)?;
+            process.set_protocol(spec.protocol);
+            process.send("uci")?;
+            process.read_until(timeout, |l| l.trim() == "uciok")?;
+            for (name, value) in &self.spec.options {
+                process.send(&format!("setoption name {} value {}", name, value))?;
+            }
+            process.send("isready")?;
+            process.read_until(timeout, |l| l.trim() == "readyok")?;
+            self.process = Some(process);
+        }
+        self.process.as_mut().ok_or_else(|| "Engine not running".to_string())
+    }
+
+    /// Tell the engine the next search belongs to a different game.
+    pub fn new_game(&mut self) -> Result<(), String> {
+        let process = self.ensure_started()?;
+        process.send("ucinewgame")?;
+        process.send("isready")?;
+        process.read_until(Duration::from_millis(DEFAULT_HANDSHAKE_TIMEOUT_MS), |l| l.trim() == "readyok")?;
+        Ok(())
+    }
+
+    /// Search `fen` until the engine reports a best move. With `max_time` the engine is told to
+    /// stop once that much time has passed; a movetime limit always implies one.
+    /// After a failed search the engine is killed, and the next search starts a fresh one.
+    pub fn search(&mut self, fen: &str, limit: SearchLimit, max_time: Option<Duration>) -> Result<SearchResult, String> {
+        let budget = match limit {
+            SearchLimit::Movetime(ms) => Some(Duration::from_millis(ms)),
+            _ => max_time,
+        };
+        let process = self.ensure_started()?;
+        let result = run_search(process, fen, limit, budget);
+        if result.is_err() {
+            self.process = None;
+        }
+        result
+    }
+
+    pub fn shutdown(&mut self) {
+        if let Some(process) = self.process.take() {
+            process.shutdown(Duration::from_millis(500));
+        }
+    }
+}
+
+impl Drop for Analyser {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+fn run_search(
+    process: &mut EngineProcess,
+    fen: &str,
+    limit: SearchLimit,
+    budget: Option<Duration>,
+) -> Result<SearchResult, String> {
+    process.send(&format!("position fen {}", fen))?;
+    let started = Instant::now();
+    process.send(&limit.go_command())?;
+
+    let mut last: Option<InfoLine> = None;
+    let mut stop_sent = false;
+    loop {
+        let wait = match budget {
+            Some(budget) if stop_sent => (budget + STOP_GRACE).saturating_sub(started.elapsed()),
+            Some(budget) => budget.saturating_sub(started.elapsed()),
+            None => Duration::from_millis(100),
+        };
+        if wait.is_zero() {
+            if stop_sent {
+                return Err("Engine did not answer stop".into());
+            }
+            process.send("stop")?;
+            stop_sent = true;
+            continue;
+        }
+        match process.recv(wait) {
+            Some(EngineEvent::Stdout(line)) => match uci::parse_line(&line) {
+                Some(UciMessage::Info(info)) if info.multipv.unwrap_or(1) == 1 && info.score.is_some() => {
+                    last = Some(info);
+                }
+                Some(UciMessage::BestMove { best_move, ponder }) => {
+                    let info = last.unwrap_or_default();
+                    return Ok(SearchResult {
+                        best_move,
+                        ponder,
+                        depth: info.depth,
+                        score: info.score,
+                        wdl: info.wdl,
+                        nodes: info.nodes,
+                        pv: info.pv,
+                        time_ms: started.elapsed().as_millis() as u64,
+                    });
+                }
+                _ => {}
+            },
+            Some(EngineEvent::Stderr(_)) => {}
+            Some(EngineEvent::Exited { code, signal }) => {
+                return Err(format!("Engine exited (code {:?}, signal {:?})", code, signal));
+            }
+            None if !process.is_running() => return Err("Engine is not running".into()),
+            None => {}
+        }
+    }
+}
@@ -0,0 +1,264 @@
+// Engine analysis cache. Positions are keyed like the opening book, so mirrored and
+// colour-swapped positions share one entry; PVs are stored in the normalized orientation.
+use rusqlite::{Connection, OptionalExtension, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::opening_book::{compute_key_and_transform, transform_uci_move};
+use crate::uci::{Score, Wdl};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoreAnalysisRequest {
+    pub fen: String,
+    pub depth: u32,
+    /// Score from the point of view of the side to move, as the engine reports it.
+    pub score: Score,
+    #[serde(default)]
+    pub wdl: Option<Wdl>,
+    #[serde(default)]
+    pub pv: Vec<String>,
+    pub engine: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedAnalysis {
+    pub depth: u32,
+    pub score: Score,
+    pub wdl: Option<Wdl>,
+    pub pv: Vec<String>,
+    pub engine: String,
+    /// Unix time in seconds when the entry was stored.
+    pub timestamp: i64,
+}
+
+/// Entries matching any of the given criteria are removed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PruneCriteria {
+    /// Remove entries stored more than this many days ago.
+    #[serde(default)]
+    pub older_than_days: Option<u32>,
+    /// Remove entries searched to less than this depth.
+    #[serde(default)]
+    pub min_depth: Option<u32>,
+    /// Remove entries produced by this engine.
+    #[serde(default)]
+    pub engine: Option<String>,
+}
+
+pub struct AnalysisCache {
+    conn: Connection,
+}
+
+impl AnalysisCache {
+    pub fn new<P: AsRef<Path>>(db_path: P) -> Result<Self> {
+        let conn = Connection::open(db_path)?;
+        let cache = AnalysisCache { conn };
+        cache.initialize_database()?;
+        Ok(cache)
+    }
+
+    fn initialize_database(&self) -> Result<()> {
+        self.conn.execute(
+            r#"
+            CREATE TABLE IF NOT EXISTS analysis (
+                key        BLOB PRIMARY KEY,
+                depth      INTEGER NOT NULL,
+                score_kind TEXT NOT NULL,
+                score      INTEGER NOT NULL,
+                wdl_win    INTEGER,
+                wdl_draw   INTEGER,
+                wdl_loss   INTEGER,
+                pv         TEXT NOT NULL,
+                engine     TEXT NOT NULL,
+                created_at INTEGER NOT NULL
+            );
+            "#,
+            [],
+        )?;
+        Ok(())
+    }
+
+    /// Store a result unless the cache already holds a deeper one for the position.
+    /// Returns true if the entry was written.
+    pub fn insert(&self, request: &StoreAnalysisRequest) -> Result<bool> {
+        let (key_blob, transform_idx) = compute_key_and_transform(&request.fen);
+        let pv: Vec<String> = request.pv.iter().map(|m| transform_uci_move(m, transform_idx)).collect();
+        let (score_kind, score) = match request.score {
+            Score::Cp(cp) => ("cp", cp),
+            Score::Mate(n) => ("mate", n),
+        };
+        let wdl = request.wdl.as_ref();
+
+        let affected_rows = self.conn.execute(
+            r#"
+            INSERT INTO analysis (key, depth, score_kind, score, wdl_win, wdl_draw, wdl_loss, pv, engine, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+            ON CONFLICT(key) DO UPDATE SET
+                depth=excluded.depth,
+                score_kind=excluded.score_kind,
+                score=excluded.score,
+                wdl_win=excluded.wdl_win,
+                wdl_draw=excluded.wdl_draw,
+                wdl_loss=excluded.wdl_loss,
+                pv=excluded.pv,
+                engine=excluded.engine,
+                created_at=excluded.created_at
+            WHERE excluded.depth >= analysis.depth;
+            "#,
+            rusqlite::params![
+                &key_blob,
+                request.depth,
+                score_kind,
+                score,
+                wdl.map(|w| w.win),
+                wdl.map(|w| w.draw),
+                wdl.map(|w| w.loss),
+                pv.join(" "),
+                &request.engine,
+                chrono::Utc::now().timestamp(),
+            ],
+        )?;
+
+        Ok(affected_rows > 0)
+    }
+
+    /// Cached result for `fen`, with the PV in the caller's orientation.
+    /// Entries shallower than `min_depth` are treated as missing.
+    pub fn query(&self, fen: &str, min_depth: Option<u32>) -> Result<Option<CachedAnalysis>> {
+        let (key_blob, transform_idx) = compute_key_and_transform(fen);
+
+        let cached = self
+            .conn
+            .query_row(
+                "SELECT depth, score_kind, score, wdl_win, wdl_draw, wdl_loss, pv, engine, created_at FROM analysis WHERE key = ?1 AND depth >= ?2",
+                rusqlite::params![key_blob, min_depth.unwrap_or(0)],
+                |row| {
+                    let score_kind: String = row.get(1)?;
+                    let score: i32 = row.get(2)?;
+                    let wdl = match (row.get(3)?, row.get(4)?, row.get(5)?) {
+                        (Some(win), Some(draw), Some(loss)) => Some(Wdl { win, draw, loss }),
+                        _ => None,
+                    };
+                    let pv: String = row.get(6)?;
+                    Ok(CachedAnalysis {
+                        depth: row.get(0)?,
+                        score: if score_kind == "mate" { Score::Mate(score) } else { Score::Cp(score) },
+                        wdl,
+                        pv: pv.split_whitespace().map(str::to_string).collect(),
+                        engine: row.get(7)?,
+                        timestamp: row.get(8)?,
+                    })
+                },
+            )
+            .optional()?;
+
+        // Restore the PV from the normalized coordinate system to the user's FEN coordinate system
+        Ok(cached.map(|mut analysis| {
+            analysis.pv = analysis.pv.iter().map(|m| transform_uci_move(m, transform_idx)).collect();
+            analysis
+        }))
+    }
+
+    /// Remove entries matching `criteria`. Returns the number of entries removed.
+    pub fn prune(&self, criteria: &PruneCriteria) -> Result<usize> {
+        let mut removed = 0;
+        if let Some(days) = criteria.older_than_days {
+            let cutoff = chrono::Utc::now().timestamp() - days as i64 * 86_400;
+            removed += self.conn.execute("DELETE FROM analysis WHERE created_at < ?1", [cutoff])?;
+        }
+        if let Some(depth) = criteria.min_depth {
+            removed += self.conn.execute("DELETE FROM analysis WHERE depth < ?1", [depth])?;
+        }
+        if let Some(engine) = &criteria.engine {
+            removed += self.conn.execute("DELETE FROM analysis WHERE engine = ?1", [engine])?;
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A cannon revealed on b2, so the position is not symmetric
+    const FEN: &str = "xxxxkxxxx/9/1x5x1/x1x1x1x1x/9/9/X1X1X1X1X/1C5X1/9/XXXXKXXXX w A2B2N2R2C1P5a2b2n2r2c2p5 - 0 1";
+    const MIRRORED: &str = "xxxxkxxxx/9/1x5x1/x1x1x1x1x/9/9/X1X1X1X1X/1X5C1/9/XXXXKXXXX w A2B2N2R2C1P5a2b2n2r2c2p5 - 0 1";
+    const SWAPPED: &str = "xxxxkxxxx/9/1c5x1/x1x1x1x1x/9/9/X1X1X1X1X/1X5X1/9/XXXXKXXXX b A2B2N2R2C2P5a2b2n2r2c1p5 - 0 1";
+
+    fn request(fen: &str, depth: u32, engine: &str) -> StoreAnalysisRequest {
+        StoreAnalysisRequest {
+            fen: fen.into(),
+            depth,
+            score: Score::Cp(depth as i32),
+            wdl: None,
+            pv: vec!["b2e2".into(), "h9g7".into()],
+            engine: engine.into(),
+        }
+    }
+
+    fn cache() -> AnalysisCache {
+        AnalysisCache::new(":memory:").unwrap()
+    }
+
+    #[test]
+    fn transformed_positions_share_an_entry() {
+        let cache = cache();
+        let mut stored = request(FEN, 20, "Pikafish");
+        stored.wdl = Some(Wdl { win: 500, draw: 400, loss: 100 });
+        assert!(cache.insert(&stored).unwrap());
+
+        let same = cache.query(FEN, None).unwrap().unwrap();
+        assert_eq!(same.pv, ["b2e2", "h9g7"]);
+        assert_eq!(same.wdl, stored.wdl);
+        assert_eq!(cache.query(MIRRORED, None).unwrap().unwrap().pv, ["h2e2", "b9c7"]);
+        let swapped = cache.query(SWAPPED, None).unwrap().unwrap();
+        assert_eq!(swapped.pv, ["b7e7", "h0g2"]);
+        assert_eq!((swapped.depth, swapped.score), (20, Score::Cp(20)));
+    }
+
+    #[test]
+    fn the_deepest_result_is_kept() {
+        let cache = cache();
+        assert!(cache.insert(&request(FEN, 10, "A")).unwrap());
+        assert!(!cache.insert(&request(MIRRORED, 8, "B")).unwrap());
+        assert_eq!(cache.query(FEN, None).unwrap().unwrap().engine, "A");
+        assert!(cache.insert(&request(SWAPPED, 12, "B")).unwrap());
+        let cached = cache.query(FEN, None).unwrap().unwrap();
+        assert_eq!((cached.depth, cached.engine.as_str()), (12, "B"));
+        assert!(cache.query(FEN, Some(13)).unwrap().is_none());
+        assert!(cache.query(FEN, Some(12)).unwrap().is_some());
+    }
+
+    #[test]
+    fn prune_removes_matching_entries() {
+        let cache = cache();
+        let start = crate::jieqi::START_FEN;
+        cache.insert(&request(FEN, 5, "A")).unwrap();
+        cache.insert(&request(start, 20, "B")).unwrap();
+        let criteria = PruneCriteria {
+            min_depth: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(cache.prune(&criteria).unwrap(), 1);
+        assert!(cache.query(FEN, None).unwrap().is_none());
+
+        let criteria = PruneCriteria {
+            older_than_days: Some(30),
+            ..Default::default()
+        };
+        assert_eq!(cache.prune(&criteria).unwrap(), 0);
+        cache
+            .conn
+            .execute("UPDATE analysis SET created_at = created_at - 31 * 86400", [])
+            .unwrap();
+        assert_eq!(cache.prune(&criteria).unwrap(), 1);
+
+        cache.insert(&request(start, 20, "B")).unwrap();
+        let criteria = PruneCriteria {
+            engine: Some("B".into()),
+            ..Default::default()
+        };
+        assert_eq!(cache.prune(&criteria).unwrap(), 1);
+        assert!(cache.query(start, None).unwrap().is_none());
+    }
+}
@@ -0,0 +1,346 @@
+// Batch analysis: run one engine over a list of positions, optionally with several engine
+// processes in parallel, and collect bestmove, score and PV for each.
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use crate::analysis::{Analyser, SearchLimit, SearchResult};
+use crate::match_runner::EngineSpec;
+use crate::uci::Score;
+
+fn default_workers() -> u32 {
+    1
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchConfig {
+    pub engine: EngineSpec,
+    /// FENs to analyse, in order. Positions from `file` are appended after these.
+    #[serde(default)]
+    pub positions: Vec<String>,
+    /// EPD-like file: one FEN per line, optionally followed by `;`-separated operations such as `id "name"`.
+    #[serde(default)]
+    pub file: Option<String>,
+    pub limit: SearchLimit,
+    /// Number of engine processes searching at the same time.
+    #[serde(default = "default_workers")]
+    pub workers: u32,
+    /// Give up on a position after this long, for depth and node limits that take too long.
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    /// Where to write the results: `.epd` writes EPD lines, anything else JSON.
+    #[serde(default)]
+    pub output: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchPosition {
+    pub fen: String,
+    pub id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchResult {
+    pub index: usize,
+    pub id: Option<String>,
+    pub fen: String,
+    #[serde(flatten)]
+    pub search: Option<SearchResult>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchSummary {
+    pub results: Vec<BatchResult>,
+    pub completed: usize,
+    pub failed: usize,
+    pub aborted: bool,
+    pub output: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BatchEvent {
+    Progress {
+        completed: usize,
+        total: usize,
+        result: BatchResult,
+    },
+    Finished {
+        completed: usize,
+        total: usize,
+        failed: usize,
+        aborted: bool,
+        output: Option<String>,
+    },
+}
+
+/// Parse an EPD-like file. Blank lines and lines starting with `#` are skipped.
+/// The FEN is the leading fields of the line (new or legacy format), followed by optional
+/// `opcode operand;` operations of which only `id` is used.
+pub fn parse_epd(text: &str) -> Vec<BatchPosition> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(parse_epd_line)
+        .collect()
+}
+
+fn parse_epd_line(line: &str) -> BatchPosition {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    // New format: board side pool captured [half full]; legacy: board pool side - - [half full]
+    let fixed = if tokens.get(1).is_some_and(|t| *t == "w" || *t == "b") { 4 } else { 5 };
+    let mut fen_len = fixed.min(tokens.len());
+    while fen_len < tokens.len() && fen_len < fixed + 2 && tokens[fen_len].parse::<u32>().is_ok() {
+        fen_len += 1;
+    }
+    let fen = tokens[..fen_len].join(" ");
+
+    let operations = tokens[fen_len..].join(" ");
+    let id = operations.split(';').find_map(|op| {
+        let op = op.trim();
+        op.strip_prefix("id ").map(|value| value.trim().trim_matches('"').to_string())
+    });
+    BatchPosition { fen, id }
+}
+
+/// Analyse every position in `config`. Results come back in input order whatever the number of workers.
+/// `stop` aborts between positions; `on_event` receives each result as it completes.
+pub fn run_batch<F>(config: &BatchConfig, stop: &AtomicBool, mut on_event: F) -> Result<BatchSummary, String>
+where
+    F: FnMut(&BatchEvent),
+{
+    let mut positions: Vec<BatchPosition> = config
+        .positions
+        .iter()
+        .map(|fen| BatchPosition {
+            fen: fen.trim().to_string(),
+            id: None,
+        })
+        .collect();
+    if let Some(file) = &config.file {
+        let text = fs::read_to_string(file).map_err(|e| format!("Failed to read {}: {}", file, e))?;
+        positions.extend(parse_epd(&text));
+    }
+    if positions.is_empty() {
+        return Err("No positions to analyse".into());
+    }
+
+    let total = positions.len();
+    let workers = (config.workers.max(1) as usize).min(total);
+    let timeout = config.timeout_ms.map(Duration::from_millis);
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<Option<BatchResult>> = vec![None; total];
+    let mut completed = 0;
+    let mut failed = 0;
+
+    thread::scope(|scope| {
+        let (tx, rx) = mpsc::channel();
+        for _ in 0..workers {
+            let tx = tx.clone();
+            let (positions, next) = (&positions, &next);
+            scope.spawn(move || {
+                let mut analyser = Analyser::new(config.engine.clone());
+                loop {
+                    if stop.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    let index = next.fetch_add(1, Ordering::SeqCst);
+                    let Some(position) = positions.get(index) else { break };
+                    let (search, error) = match analyser.search(&position.fen, config.limit, timeout) {
+                        Ok(search) => (Some(search), None),
+                        Err(e) => (None, Some(e)),
+                    };
+                    let result = BatchResult {
+                        index,
+                        id: position.id.clone(),
+                        fen: position.fen.clone(),
+                        search,
+                        error,
+                    };
+                    if tx.send(result).is_err() {
+                        break;
+                    }
+                }
+                analyser.shutdown();
+            });
+        }
+        drop(tx);
+
+        for result in rx {
+            completed += 1;
+            if result.error.is_some() {
+                failed += 1;
+            }
+            on_event(&BatchEvent::Progress {
+                completed,
+                total,
+                result: result.clone(),
+            });
+            let index = result.index;
+            results[index] = Some(result);
+        }
+    });
+
+    let results: Vec<BatchResult> = results.into_iter().flatten().collect();
+    let aborted = completed < total;
+    let output = match &config.output {
+        Some(path) => {
+            write_results(Path::new(path), &results)?;
+            Some(path.clone())
+        }
+        None => None,
+    };
+
+    on_event(&BatchEvent::Finished {
+        completed,
+        total,
+        failed,
+        aborted,
+        output: output.clone(),
+    });
+    Ok(BatchSummary {
+        results,
+        completed,
+        failed,
+        aborted,
+        output,
+    })
+}
+
+fn write_results(path: &Path, results: &[BatchResult]) -> Result<(), String> {
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    let is_epd = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("epd"));
+    let contents = if is_epd {
+        results.iter().map(format_epd).collect::<Vec<_>>().join("\n") + "\n"
+    } else {
+        serde_json::to_string_pretty(results).map_err(|e| e.to_string())?
+    };
+    fs::write(path, contents).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+// `<fen> bm <move>; ce <cp>; dm <n>; acd <depth>; pv <moves>; id "<id>";`
+fn format_epd(result: &BatchResult) -> String {
+    let mut line = result.fen.clone();
+    if let Some(search) = &result.search {
+        line.push_str(&format!(" bm {};", search.best_move));
+        match search.score {
+            Some(Score::Cp(cp)) => line.push_str(&format!(" ce {};", cp)),
+            Some(Score::Mate(n)) => line.push_str(&format!(" dm {};", n)),
+            None => {}
+        }
+        if let Some(depth) = search.depth {
+            line.push_str(&format!(" acd {};", depth));
+        }
+        if !search.pv.is_empty() {
+            line.push_str(&format!(" pv {};", search.pv.join(" ")));
+        }
+    }
+    if let Some(error) = &result.error {
+        line.push_str(&format!(" c0 \"{}\";", error.replace('"', "'")));
+    }
+    if let Some(id) = &result.id {
+        line.push_str(&format!(" id \"{}\";", id));
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOARD: &str = "xxxxkxxxx/9/1x5x1/x1x1x1x1x/9/9/X1X1X1X1X/1X5X1/9/XXXXKXXXX";
+    const POOL: &str = "A2B2N2R2C2P5a2b2n2r2c2p5";
+
+    fn result(search: Option<SearchResult>, error: Option<&str>, id: Option<&str>) -> BatchResult {
+        BatchResult {
+            index: 0,
+            id: id.map(str::to_string),
+            fen: format!("{} w {} - 0 1", BOARD, POOL),
+            search,
+            error: error.map(str::to_string),
+        }
+    }
+
+    fn search(score: Score) -> SearchResult {
+        SearchResult {
+            best_move: "h2e2".into(),
+            depth: Some(12),
+            score: Some(score),
+            pv: vec!["h2e2".into(), "h9g7".into()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn epd_lines_split_into_fen_and_id() {
+        let text = format!(
+            "# suite\n\n{b} w {p} - 0 1 id \"start position\";\n{b} b {p} - bm h2e2; id \"black\";\n{b} {p} w - - 3 7\n  {b} w {p} -  \n",
+            b = BOARD,
+            p = POOL
+        );
+        let positions = parse_epd(&text);
+        let parsed: Vec<(String, Option<&str>)> =
+            positions.iter().map(|p| (p.fen.replace(BOARD, "B").replace(POOL, "P"), p.id.as_deref())).collect();
+        assert_eq!(
+            parsed,
+            [
+                ("B w P - 0 1".to_string(), Some("start position")),
+                ("B b P -".to_string(), Some("black")),
+                ("B P w - - 3 7".to_string(), None),
+                ("B w P -".to_string(), None),
+            ]
+        );
+        assert!(parse_epd("# nothing\n\n").is_empty());
+    }
+
+    #[test]
+    fn epd_results_carry_score_pv_error_and_id() {
+        let fen = format!("{} w {} - 0 1", BOARD, POOL);
+        assert_eq!(
+            format_epd(&result(Some(search(Score::Cp(35))), None, Some("start"))),
+            format!("{} bm h2e2; ce 35; acd 12; pv h2e2 h9g7; id \"start\";", fen)
+        );
+        assert_eq!(
+            format_epd(&result(Some(search(Score::Mate(-3))), None, None)),
+            format!("{} bm h2e2; dm -3; acd 12; pv h2e2 h9g7;", fen)
+        );
+        assert_eq!(
+            format_epd(&result(None, Some("engine said \"no\""), None)),
+            format!("{} c0 \"engine said 'no'\";", fen)
+        );
+    }
+
+    #[test]
+    fn results_are_written_as_epd_or_json() {
+        let dir = std::env::temp_dir().join(format!("jieqibox-batch-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let results = [
+            result(Some(search(Score::Cp(35))), None, Some("start")),
+            result(None, Some("timed out"), None),
+        ];
+
+        let epd = dir.join("nested/results.EPD");
+        write_results(&epd, &results).unwrap();
+        let text = fs::read_to_string(&epd).unwrap();
+        assert_eq!(text.lines().count(), 2);
+        assert!(text.lines().next().unwrap().ends_with("id \"start\";"));
+        assert!(text.ends_with("c0 \"timed out\";\n"));
+
+        let json = dir.join("results.json");
+        write_results(&json, &results).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&fs::read_to_string(&json).unwrap()).unwrap();
+        // The search is flattened into each result
+        assert_eq!(value[0]["best_move"], "h2e2");
+        assert_eq!(value[0]["score"], serde_json::json!({ "kind": "cp", "value": 35 }));
+        assert_eq!(value[1]["error"], "timed out");
+        assert!(value[1].get("best_move").is_none());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
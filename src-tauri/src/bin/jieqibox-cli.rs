@@ -0,0 +1,801 @@
+// Headless front end to the JieqiBox library: engine analysis, engine matches, serving an
+// engine over TCP and opening book maintenance, for scripts and build servers without a display.
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::str::FromStr;
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant};
+
+use jieqibox_lib::book_builder::{self, BookBuildOptions};
+use jieqibox_lib::clock::TimeControl;
+use jieqibox_lib::engine::{EngineEncoding, EngineEvent, EngineProcess, EngineProtocol, DEFAULT_HANDSHAKE_TIMEOUT_MS};
+use jieqibox_lib::jieqi::START_FEN;
+use jieqibox_lib::match_runner::{self, EngineSpec, MatchConfig, MatchEvent};
+use jieqibox_lib::opening_book::{AddEntryRequest, ImportMode, JieqiOpeningBook, MergeOptions, MoveData};
+use jieqibox_lib::remote::{EngineServer, EngineServerConfig};
+use jieqibox_lib::resources::EngineResources;
+use jieqibox_lib::stats::{MatchStatistics, SprtConfig};
+use jieqibox_lib::transcript::Transcript;
+use jieqibox_lib::uci::{self, InfoLine, Score, UciMessage};
+
+const DEFAULT_BOOK: &str = "jieqi_openings.jb";
+const BOOK_ACTIONS: &[&str] = &["stats", "query", "add", "delete", "import", "export", "merge", "build", "missing-fens", "backfill"];
+// How long an engine may take to answer `stop` once the search time is up
+const STOP_GRACE: Duration = Duration::from_millis(1000);
+
+// Flags that never take a value
+const SWITCHES: &[&str] = &["json", "help", "disallowed", "no-backup", "dry-run", "player-moves-only"];
+
+const USAGE: &str = "\
+Usage: jieqibox-cli <command> [options]
+
+Commands:
+  analyse --engine PATH [--fen FEN] (--depth N [--max-time MS] | --movetime MS)
+          [--arg ARG]... [--option NAME=VALUE]... [--encoding NAME]
+          [--protocol uci|ucci] [--transcript FILE] [--json]
+  match   [--config FILE.json] [--engine-a PATH --engine-b PATH] [--games N]
+          [--tc [MOVES/]BASE_MS[+INC_MS] [--byoyomi PERIOD_MS[xN]] | --movetime MS]
+          [--fen FEN] [--seed N]
+          [--out DIR] [--sprt ELO0,ELO1[,ALPHA,BETA]] [--json]
+  serve   --engine PATH --token TOKEN [--bind ADDR] [--arg ARG]...
+          [--max-connections N]
+  book    [--db FILE] stats
+  book    [--db FILE] query FEN|KEY
+  book    [--db FILE] add FEN MOVE [--priority N] [--wins N] [--draws N]
+          [--losses N] [--comment TEXT] [--disallowed]
+  book    [--db FILE] delete FEN MOVE
+  book    [--db FILE] import FILE.json|FILE.jsonl
+          [--mode replace|skip-existing|merge-statistics]
+  book    [--db FILE] export [FILE.json]
+  book    [--db FILE] merge FILE.jb [--dry-run] [--rows keep-local|take-incoming]
+          [--counts row|add] [--priority row|max] [--allowed row|or|and]
+  book    [--db FILE] build GAME.json|DIR... [--max-ply N] [--min-games N]
+          [--player NAME]... [--player-moves-only] [--result red-wins|black-wins|draw]...
+          [--mode replace|skip-existing|merge-statistics]
+  book    [--db FILE] missing-fens
+  book    [--db FILE] backfill FILE   (one FEN per line)
+
+The book defaults to jieqi_openings.jb in the current directory. Books from older versions are
+upgraded when opened, after a backup copy is made next to them unless --no-backup is given.
+Engine encodings: auto (the default), utf-8, gbk, gb18030 and big5.";
+
+fn main() -> ExitCode {
+    let raw: Vec<String> = std::env::args().skip(1).collect();
+    let Some((command, rest)) = raw.split_first() else {
+        eprintln!("{}", USAGE);
+        return ExitCode::from(2);
+    };
+    let args = match Args::parse(rest) {
+        Ok(args) => args,
+        Err(e) => return usage_error(&e),
+    };
+    if args.switch("help") {
+        println!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+
+    let result = match command.as_str() {
+        "analyse" | "analyze" => analyse(&args),
+        "match" => run_match(&args),
+        "serve" => serve(&args),
+        "book" => book(&args),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        other => return usage_error(&format!("unknown command '{}'", other)),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(CliError::Usage(e)) => usage_error(&e),
+        Err(CliError::Failed(e)) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn usage_error(message: &str) -> ExitCode {
+    eprintln!("error: {}\n\n{}", message, USAGE);
+    ExitCode::from(2)
+}
+
+enum CliError {
+    Usage(String),
+    Failed(String),
+}
+
+impl From<String> for CliError {
+    fn from(e: String) -> Self {
+        CliError::Failed(e)
+    }
+}
+
+impl From<rusqlite::Error> for CliError {
+    fn from(e: rusqlite::Error) -> Self {
+        CliError::Failed(e.to_string())
+    }
+}
+
+type CliResult = Result<(), CliError>;
+
+/// `--name value`, `--name=value` and bare switches, plus positional arguments in order.
+struct Args {
+    positional: Vec<String>,
+    options: Vec<(String, String)>,
+    switches: HashSet<String>,
+}
+
+impl Args {
+    fn parse(raw: &[String]) -> Result<Self, String> {
+        let mut args = Args {
+            positional: Vec::new(),
+            options: Vec::new(),
+            switches: HashSet::new(),
+        };
+        let mut iter = raw.iter();
+        while let Some(arg) = iter.next() {
+            let Some(flag) = arg.strip_prefix("--") else {
+                args.positional.push(arg.clone());
+                continue;
+            };
+            if let Some((name, value)) = flag.split_once('=') {
+                args.options.push((name.to_string(), value.to_string()));
+            } else if SWITCHES.contains(&flag) {
+                args.switches.insert(flag.to_string());
+            } else {
+                let value = iter.next().ok_or_else(|| format!("--{} needs a value", flag))?;
+                args.options.push((flag.to_string(), value.clone()));
+            }
+        }
+        Ok(args)
+    }
+
+    fn value(&self, name: &str) -> Option<&str> {
+        self.options.iter().rev().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    fn values(&self, name: &str) -> Vec<String> {
+        self.options.iter().filter(|(n, _)| n == name).map(|(_, v)| v.clone()).collect()
+    }
+
+    fn parsed<T: FromStr>(&self, name: &str) -> Result<Option<T>, CliError> {
+        self.value(name)
+            .map(|v| v.parse().map_err(|_| CliError::Usage(format!("invalid value for --{}: {}", name, v))))
+            .transpose()
+    }
+
+    /// A value named like the serialized form of `T`, with dashes for underscores.
+    fn named<T: serde::de::DeserializeOwned>(&self, name: &str) -> Result<Option<T>, CliError> {
+        self.value(name)
+            .map(|v| {
+                serde_json::from_value(serde_json::Value::String(v.replace('-', "_")))
+                    .map_err(|_| CliError::Usage(format!("invalid value for --{}: {}", name, v)))
+            })
+            .transpose()
+    }
+
+    fn switch(&self, name: &str) -> bool {
+        self.switches.contains(name)
+    }
+
+    fn positional(&self, index: usize, what: &str) -> Result<&str, CliError> {
+        self.positional
+            .get(index)
+            .map(String::as_str)
+            .ok_or_else(|| CliError::Usage(format!("missing {}", what)))
+    }
+}
+
+fn print_json<T: serde::Serialize>(value: &T) -> CliResult {
+    let json = serde_json::to_string(value).map_err(|e| e.to_string())?;
+    println!("{}", json);
+    Ok(())
+}
+
+// --- analyse ---
+
+#[derive(serde::Serialize)]
+struct AnalysisResult {
+    fen: String,
+    best_move: String,
+    ponder: Option<String>,
+    depth: Option<u32>,
+    score: Option<Score>,
+    nodes: Option<u64>,
+    time_ms: u64,
+    pv: Vec<String>,
+}
+
+fn analyse(args: &Args) -> CliResult {
+    let path = args.value("engine").ok_or_else(|| CliError::Usage("--engine is required".into()))?;
+    let fen = args.value("fen").unwrap_or(START_FEN).to_string();
+    // The engine is told to stop once the budget is used up, as in analysis.rs
+    let (go, budget) = match (args.parsed::<u32>("depth")?, args.parsed::<u64>("movetime")?) {
+        (Some(depth), None) => (format!("go depth {}", depth), args.parsed::<u64>("max-time")?.map(Duration::from_millis)),
+        (None, Some(movetime)) => (format!("go movetime {}", movetime), Some(Duration::from_millis(movetime))),
+        _ => return Err(CliError::Usage("give exactly one of --depth or --movetime".into())),
+    };
+    let encoding = args.parsed::<EngineEncoding>("encoding")?.unwrap_or_default();
+    let protocol = args.parsed::<EngineProtocol>("protocol")?.unwrap_or_default();
+    let json = args.switch("json");
+
+    let engine_args = args.values("arg");
+    let mut engine = EngineProcess::spawn_with(path, &engine_args, &EngineResources::default(), encoding)?;
+    engine.set_protocol(protocol);
+    if let Some(file) = args.value("transcript") {
+        engine.set_transcript(Some(Transcript::create(file, path, &engine_args)?));
+    }
+    let timeout = Duration::from_millis(DEFAULT_HANDSHAKE_TIMEOUT_MS);
+    engine.send("uci")?;
+    engine.read_until(timeout, |l| l.trim() == "uciok")?;
+    for option in args.values("option") {
+        let (name, value) = option
+            .split_once('=')
+            .ok_or_else(|| CliError::Usage(format!("--option expects NAME=VALUE, got {}", option)))?;
+        engine.send(&format!("setoption name {} value {}", name, value))?;
+    }
+    engine.send("isready")?;
+    engine.read_until(timeout, |l| l.trim() == "readyok")?;
+    engine.send(&format!("position fen {}", fen))?;
+    let started = Instant::now();
+    engine.send(&go)?;
+
+    let mut last: Option<InfoLine> = None;
+    let mut stop_sent = false;
+    let (best_move, ponder) = loop {
+        let wait = match budget {
+            Some(budget) if stop_sent => (budget + STOP_GRACE).saturating_sub(started.elapsed()),
+            Some(budget) => budget.saturating_sub(started.elapsed()),
+            None => Duration::from_millis(100),
+        };
+        if wait.is_zero() {
+            if stop_sent {
+                return Err(CliError::Failed("Engine did not answer stop".into()));
+            }
+            engine.send("stop")?;
+            stop_sent = true;
+            continue;
+        }
+        match engine.recv(wait) {
+            Some(EngineEvent::Stdout(line)) => match uci::parse_line(&line) {
+                Some(UciMessage::Info(info)) if info.multipv.unwrap_or(1) == 1 && info.score.is_some() => {
+                    if !json {
+                        println!("{}", format_info(&info));
+                    }
+                    last = Some(info);
+                }
+                Some(UciMessage::BestMove { best_move, ponder }) => break (best_move, ponder),
+                _ => {}
+            },
+            Some(EngineEvent::Stderr(_)) | None => {}
+            Some(EngineEvent::Exited { code, signal }) => {
+                return Err(format!("Engine exited during search (code {:?}, signal {:?})", code, signal).into());
+            }
+        }
+    };
+    let time_ms = started.elapsed().as_millis() as u64;
+    engine.shutdown(Duration::from_millis(500));
+
+    let last = last.unwrap_or_default();
+    if json {
+        print_json(&AnalysisResult {
+            fen,
+            best_move,
+            ponder,
+            depth: last.depth,
+            score: last.score,
+            nodes: last.nodes,
+            time_ms,
+            pv: last.pv,
+        })
+    } else {
+        match ponder {
+            Some(ponder) => println!("bestmove {} ponder {}", best_move, ponder),
+            None => println!("bestmove {}", best_move),
+        }
+        Ok(())
+    }
+}
+
+fn format_info(info: &InfoLine) -> String {
+    let mut parts = Vec::new();
+    if let Some(depth) = info.depth {
+        parts.push(format!("depth {}", depth));
+    }
+    match info.score {
+        Some(Score::Cp(cp)) => parts.push(format!("score cp {}", cp)),
+        Some(Score::Mate(n)) => parts.push(format!("score mate {}", n)),
+        None => {}
+    }
+    if let Some(nodes) = info.nodes {
+        parts.push(format!("nodes {}", nodes));
+    }
+    if let Some(time) = info.time {
+        parts.push(format!("time {}", time));
+    }
+    if !info.pv.is_empty() {
+        parts.push(format!("pv {}", info.pv.join(" ")));
+    }
+    parts.join(" ")
+}
+
+// --- match ---
+
+fn run_match(args: &Args) -> CliResult {
+    let mut config = match args.value("config") {
+        Some(file) => {
+            let json = fs::read_to_string(file).map_err(|e| format!("Failed to read {}: {}", file, e))?;
+            serde_json::from_str::<MatchConfig>(&json).map_err(|e| format!("Invalid match config {}: {}", file, e))?
+        }
+        None => {
+            let engine_a = args.value("engine-a");
+            let engine_b = args.value("engine-b");
+            let (Some(engine_a), Some(engine_b)) = (engine_a, engine_b) else {
+                return Err(CliError::Usage("give --config or both --engine-a and --engine-b".into()));
+            };
+            MatchConfig {
+                engine_a: engine_spec(engine_a),
+                engine_b: engine_spec(engine_b),
+                games: 2,
+                time_control: TimeControl::default(),
+                start_fen: None,
+                max_plies: 300,
+                no_capture_plies: 120,
+                seed: None,
+                output_dir: None,
+                sprt: None,
+            }
+        }
+    };
+
+    if let Some(path) = args.value("engine-a") {
+        config.engine_a = engine_spec(path);
+    }
+    if let Some(path) = args.value("engine-b") {
+        config.engine_b = engine_spec(path);
+    }
+    if let Some(games) = args.parsed("games")? {
+        config.games = games;
+    }
+    if let Some(tc) = args.value("tc") {
+        config.time_control = parse_time_control(tc, args.value("byoyomi"))?;
+    }
+    if let Some(movetime_ms) = args.parsed("movetime")? {
+        config.time_control = TimeControl::Movetime { movetime_ms };
+    }
+    if let Some(fen) = args.value("fen") {
+        config.start_fen = Some(fen.to_string());
+    }
+    if let Some(seed) = args.parsed("seed")? {
+        config.seed = Some(seed);
+    }
+    if let Some(dir) = args.value("out") {
+        config.output_dir = Some(dir.to_string());
+    }
+    if let Some(sprt) = args.value("sprt") {
+        config.sprt = Some(parse_sprt(sprt)?);
+    }
+
+    let json = args.switch("json");
+    let stop = AtomicBool::new(false);
+    let summary = match_runner::run_match(&config, &stop, |event| {
+        if json {
+            if let Ok(line) = serde_json::to_string(event) {
+                println!("{}", line);
+            }
+            return;
+        }
+        match event {
+            MatchEvent::GameStarted { game, total, red, black, .. } => {
+                println!("Game {}/{}: {} (red) vs {} (black)", game, total, red, black);
+            }
+            MatchEvent::GameFinished {
+                game,
+                result,
+                reason,
+                score,
+                ..
+            } => {
+                println!(
+                    "Game {} finished {} ({}), score +{} ={} -{}",
+                    game, result, reason, score.wins, score.draws, score.losses
+                );
+            }
+            _ => {}
+        }
+    })?;
+
+    if !json {
+        let score = summary.score;
+        println!(
+            "{} vs {}: +{} ={} -{}{}",
+            summary.engine_a,
+            summary.engine_b,
+            score.wins,
+            score.draws,
+            score.losses,
+            if summary.aborted { " (aborted)" } else { "" }
+        );
+        if let Some(statistics) = &summary.statistics {
+            println!("{}", format_statistics(statistics));
+        }
+    }
+    Ok(())
+}
+
+fn engine_spec(path: &str) -> EngineSpec {
+    EngineSpec {
+        path: path.to_string(),
+        ..EngineSpec::default()
+    }
+}
+
+// `60000` (sudden death), `60000+1000` (Fischer) or `40/60000[+1000]` (moves per session), all in ms.
+// With `byoyomi` (`30000` or `30000x3`) the base becomes main time before byo-yomi periods.
+fn parse_time_control(tc: &str, byoyomi: Option<&str>) -> Result<TimeControl, CliError> {
+    let invalid = || CliError::Usage(format!("invalid --tc {}, expected [MOVES/]BASE_MS[+INC_MS]", tc));
+    let (moves, clock) = match tc.split_once('/') {
+        Some((moves, clock)) => (Some(moves.trim().parse::<u32>().map_err(|_| invalid())?), clock),
+        None => (None, tc),
+    };
+    let (base, increment) = clock.split_once('+').unwrap_or((clock, "0"));
+    let base_ms: u64 = base.trim().parse().map_err(|_| invalid())?;
+    let increment_ms: u64 = increment.trim().parse().map_err(|_| invalid())?;
+
+    if let Some(byoyomi) = byoyomi {
+        let invalid = || CliError::Usage(format!("invalid --byoyomi {}, expected PERIOD_MS[xN]", byoyomi));
+        let (period, periods) = byoyomi.split_once('x').unwrap_or((byoyomi, "1"));
+        return Ok(TimeControl::ByoYomi {
+            base_ms,
+            period_ms: period.trim().parse().map_err(|_| invalid())?,
+            periods: periods.trim().parse().map_err(|_| invalid())?,
+        });
+    }
+    Ok(match moves {
+        Some(moves) => TimeControl::MovesPerSession {
+            base_ms,
+            moves,
+            increment_ms,
+        },
+        None if increment_ms > 0 => TimeControl::Fischer { base_ms, increment_ms },
+        None => TimeControl::SuddenDeath { base_ms },
+    })
+}
+
+// `elo0,elo1` with the usual 5% error rates, or `elo0,elo1,alpha,beta`
+fn parse_sprt(sprt: &str) -> Result<SprtConfig, CliError> {
+    let values: Result<Vec<f64>, _> = sprt.split(',').map(|v| v.trim().parse::<f64>()).collect();
+    match values.as_deref() {
+        Ok([elo0, elo1]) => Ok(SprtConfig {
+            elo0: *elo0,
+            elo1: *elo1,
+            alpha: 0.05,
+            beta: 0.05,
+        }),
+        Ok([elo0, elo1, alpha, beta]) => Ok(SprtConfig {
+            elo0: *elo0,
+            elo1: *elo1,
+            alpha: *alpha,
+            beta: *beta,
+        }),
+        _ => Err(CliError::Usage(format!("invalid --sprt {}, expected ELO0,ELO1[,ALPHA,BETA]", sprt))),
+    }
+}
+
+fn format_statistics(statistics: &MatchStatistics) -> String {
+    let mut line = format!(
+        "Elo {:+.1} +/- {:.1}, LOS {:.1}%, {} games",
+        statistics.elo,
+        statistics.elo_error,
+        statistics.los * 100.0,
+        statistics.games
+    );
+    if let Some(sprt) = &statistics.sprt {
+        line.push_str(&format!(
+            ", SPRT LLR {:.2} ({:.2}, {:.2}) {:?}",
+            sprt.llr, sprt.lower_bound, sprt.upper_bound, sprt.decision
+        ));
+    }
+    line
+}
+
+// --- serve ---
+
+// Runs until the process is killed; every client gets its own engine process
+fn serve(args: &Args) -> CliResult {
+    let path = args.value("engine").ok_or_else(|| CliError::Usage("--engine is required".into()))?;
+    let token = args.value("token").ok_or_else(|| CliError::Usage("--token is required".into()))?;
+    let config = EngineServerConfig {
+        bind: args.value("bind").unwrap_or("127.0.0.1:0").to_string(),
+        token: token.to_string(),
+        path: path.to_string(),
+        args: args.values("arg"),
+        resources: EngineResources::default(),
+        max_connections: args.parsed("max-connections")?.unwrap_or(4),
+    };
+    let server = EngineServer::start(config)?;
+    println!("serving {} on {}", path, server.local_addr());
+    loop {
+        std::thread::park();
+    }
+}
+
+// --- book ---
+
+fn book(args: &Args) -> CliResult {
+    let action = args.positional(0, "book action (stats, query, add, delete, import, export, merge, build, missing-fens or backfill)")?;
+    // Opening creates and migrates the book, which a mistyped action should not do
+    if !BOOK_ACTIONS.contains(&action) {
+        return Err(CliError::Usage(format!("unknown book action '{}'", action)));
+    }
+    let db_path = args.value("db").unwrap_or(DEFAULT_BOOK);
+    let book = JieqiOpeningBook::open(db_path, !args.switch("no-backup"))?;
+
+    match action {
+        "stats" => {
+            let stats = book.get_stats()?;
+            if args.switch("json") {
+                return print_json(&stats);
+            }
+            println!("positions: {}", stats.total_positions);
+            println!("moves:     {}", stats.total_moves);
+            println!("allowed:   {}", stats.allowed_moves);
+            println!("blocked:   {}", stats.disallowed_moves);
+            if stats.positions_without_fen > 0 {
+                println!("no FEN:    {} (see missing-fens)", stats.positions_without_fen);
+            }
+        }
+        "query" => {
+            let fen = args.positional(1, "FEN or key")?;
+            // A bare key looks up a stored position; its moves are in the stored FEN's coordinates
+            let moves = if fen.len() == 24 && fen.chars().all(|c| c.is_ascii_hexdigit()) {
+                let Some(entry) = book.get_position(fen)? else {
+                    return Err(CliError::Failed(format!("{} is not in the book", fen)));
+                };
+                if args.switch("json") {
+                    return print_json(&entry);
+                }
+                println!("fen {}", if entry.fen.is_empty() { "unknown" } else { &entry.fen });
+                entry.moves
+            } else {
+                let moves = book.query_moves(fen)?;
+                if args.switch("json") {
+                    return print_json(&moves);
+                }
+                moves
+            };
+            for m in moves {
+                println!(
+                    "{} priority {} +{} ={} -{}{}{}",
+                    m.uci_move,
+                    m.priority,
+                    m.wins,
+                    m.draws,
+                    m.losses,
+                    if m.allowed { "" } else { " (disallowed)" },
+                    if m.comment.is_empty() { String::new() } else { format!(" # {}", m.comment) }
+                );
+            }
+        }
+        "add" => {
+            let request = AddEntryRequest {
+                fen: args.positional(1, "FEN")?.to_string(),
+                uci_move: args.positional(2, "move")?.to_string(),
+                priority: args.parsed("priority")?.unwrap_or(0),
+                wins: args.parsed("wins")?.unwrap_or(0),
+                draws: args.parsed("draws")?.unwrap_or(0),
+                losses: args.parsed("losses")?.unwrap_or(0),
+                allowed: !args.switch("disallowed"),
+                comment: args.value("comment").unwrap_or_default().to_string(),
+            };
+            book.add_entry(&request)?;
+            println!("added {}", request.uci_move);
+        }
+        "delete" => {
+            let fen = args.positional(1, "FEN")?;
+            let uci_move = args.positional(2, "move")?;
+            if !book.delete_entry(fen, uci_move)? {
+                return Err(CliError::Failed(format!("{} is not in the book for this position", uci_move)));
+            }
+            println!("deleted {}", uci_move);
+        }
+        "import" => {
+            let file = args.positional(1, "file to import")?;
+            let mode = args.parsed::<ImportMode>("mode")?.unwrap_or_default();
+            let data = fs::read_to_string(file).map_err(|e| format!("Failed to read {}: {}", file, e))?;
+            let report = book.import_data(&data, mode)?;
+            if args.switch("json") {
+                return print_json(&report);
+            }
+            for error in &report.errors {
+                eprintln!("{}: {}", file, error);
+            }
+            println!("imported {} moves, skipped {}, {} errors", report.imported, report.skipped, report.errors.len());
+        }
+        "export" => {
+            let entries = book.export_all()?;
+            let json = serde_json::to_string(&entries).map_err(|e| e.to_string())?;
+            match args.positional.get(1) {
+                Some(file) => {
+                    fs::write(file, json).map_err(|e| format!("Failed to write {}: {}", file, e))?;
+                    eprintln!("exported {} positions to {}", entries.len(), file);
+                }
+                None => println!("{}", json),
+            }
+        }
+        "merge" => {
+            let file = args.positional(1, "book to merge")?;
+            let options = MergeOptions {
+                rows: args.named("rows")?.unwrap_or_default(),
+                counts: args.named("counts")?.unwrap_or_default(),
+                priority: args.named("priority")?.unwrap_or_default(),
+                allowed: args.named("allowed")?.unwrap_or_default(),
+            };
+            let report = book.merge_database(file, options, args.switch("dry-run"))?;
+            if args.switch("json") {
+                return print_json(&report);
+            }
+            let describe = |m: &MoveData| {
+                format!("priority {} +{} ={} -{}{}", m.priority, m.wins, m.draws, m.losses, if m.allowed { "" } else { " (disallowed)" })
+            };
+            for conflict in &report.conflicts {
+                println!("{} {}", conflict.key, conflict.local.uci_move);
+                println!("  local:    {}", describe(&conflict.local));
+                println!("  incoming: {}", describe(&conflict.incoming));
+                println!("  merged:   {}", describe(&conflict.merged));
+            }
+            println!(
+                "{} {} new moves, {} conflicts, {} identical, {} FENs",
+                if report.committed { "merged" } else { "would merge (dry run)" },
+                report.added,
+                report.conflicts.len(),
+                report.unchanged,
+                report.fens_added
+            );
+        }
+        "build" => {
+            let paths: Vec<PathBuf> = args.positional[1..].iter().map(PathBuf::from).collect();
+            if paths.is_empty() {
+                return Err(CliError::Usage("missing game files or directories".into()));
+            }
+            let defaults = BookBuildOptions::default();
+            let results = args
+                .values("result")
+                .iter()
+                .map(|v| {
+                    serde_json::from_value(serde_json::Value::String(v.replace('-', "_")))
+                        .map_err(|_| CliError::Usage(format!("invalid value for --result: {}", v)))
+                })
+                .collect::<Result<_, _>>()?;
+            let options = BookBuildOptions {
+                max_ply: args.parsed("max-ply")?.unwrap_or(defaults.max_ply),
+                min_games: args.parsed("min-games")?.unwrap_or(defaults.min_games),
+                players: args.values("player"),
+                player_moves_only: args.switch("player-moves-only"),
+                results,
+                mode: args.parsed("mode")?.unwrap_or(defaults.mode),
+            };
+            let report = book_builder::build_book(&book, &paths, &options, |_| {})?;
+            if args.switch("json") {
+                return print_json(&report);
+            }
+            for reason in report.skipped.iter().chain(&report.import.errors) {
+                eprintln!("skipped {}", reason);
+            }
+            println!(
+                "{} of {} games used: {} positions, {} moves ({} written, {} already in the book)",
+                report.games_used,
+                report.games_read,
+                report.positions,
+                report.moves,
+                report.import.imported,
+                report.import.skipped
+            );
+        }
+        "missing-fens" => {
+            let keys = book.keys_without_fen()?;
+            if args.switch("json") {
+                return print_json(&keys);
+            }
+            for key in &keys {
+                println!("{}", key);
+            }
+            eprintln!("{} positions without a FEN", keys.len());
+        }
+        "backfill" => {
+            let file = args.positional(1, "file of FENs")?;
+            let text = fs::read_to_string(file).map_err(|e| format!("Failed to read {}: {}", file, e))?;
+            let fens: Vec<&str> = text.lines().map(str::trim).filter(|l| !l.is_empty()).collect();
+            let filled = book.backfill_fens(&fens)?;
+            println!("filled in {} positions, {} still without a FEN", filled, book.keys_without_fen()?.len());
+        }
+        _ => unreachable!("book actions are checked against BOOK_ACTIONS"),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(raw: &[&str]) -> Args {
+        let raw: Vec<String> = raw.iter().map(|s| s.to_string()).collect();
+        Args::parse(&raw).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn tc(tc: &str, byoyomi: Option<&str>) -> Option<TimeControl> {
+        parse_time_control(tc, byoyomi).ok()
+    }
+
+    fn is_usage<T>(result: Result<T, CliError>) -> bool {
+        matches!(result, Err(CliError::Usage(_)))
+    }
+
+    #[test]
+    fn options_switches_and_positionals() {
+        let parsed = args(&[
+            "match", "--games", "10", "--tc=60000+1000", "--json", "a.exe", "--games", "12", "b.exe",
+        ]);
+        assert_eq!(parsed.positional, ["match", "a.exe", "b.exe"]);
+        assert_eq!(parsed.value("tc"), Some("60000+1000"));
+        // The last occurrence wins for single values; all of them are kept for repeated ones
+        assert_eq!(parsed.value("games"), Some("12"));
+        assert_eq!(parsed.values("games"), ["10", "12"]);
+        assert!(parsed.switch("json") && !parsed.switch("dry-run"));
+        assert!(matches!(parsed.parsed::<u32>("games"), Ok(Some(12))));
+        assert!(matches!(parsed.parsed::<u32>("depth"), Ok(None)));
+        assert!(is_usage(parsed.parsed::<u32>("tc")));
+
+        let raw = vec!["analyse".to_string(), "--depth".to_string()];
+        assert_eq!(Args::parse(&raw).err().as_deref(), Some("--depth needs a value"));
+    }
+
+    #[test]
+    fn time_controls() {
+        assert_eq!(tc("60000", None), Some(TimeControl::SuddenDeath { base_ms: 60000 }));
+        assert_eq!(
+            tc("60000+1000", None),
+            Some(TimeControl::Fischer { base_ms: 60000, increment_ms: 1000 })
+        );
+        assert_eq!(
+            tc("40/60000", None),
+            Some(TimeControl::MovesPerSession { base_ms: 60000, moves: 40, increment_ms: 0 })
+        );
+        assert_eq!(
+            tc("40/60000+500", None),
+            Some(TimeControl::MovesPerSession { base_ms: 60000, moves: 40, increment_ms: 500 })
+        );
+        assert_eq!(
+            tc("60000", Some("30000x3")),
+            Some(TimeControl::ByoYomi { base_ms: 60000, period_ms: 30000, periods: 3 })
+        );
+        assert_eq!(
+            tc("0", Some("5000")),
+            Some(TimeControl::ByoYomi { base_ms: 0, period_ms: 5000, periods: 1 })
+        );
+    }
+
+    #[test]
+    fn malformed_time_controls_are_usage_errors() {
+        for bad in ["", "abc", "-5", "60000+", "60000+x", "40/", "/60000", "x/60000", "60s", "1.5+1"] {
+            assert!(is_usage(parse_time_control(bad, None)), "--tc {:?}", bad);
+        }
+        for bad in ["", "x3", "30000x", "30000x-1", "30000y3"] {
+            assert!(is_usage(parse_time_control("60000", Some(bad))), "--byoyomi {:?}", bad);
+        }
+    }
+
+    #[test]
+    fn sprt_bounds() {
+        let Ok(sprt) = parse_sprt("0, 5") else { panic!() };
+        assert_eq!((sprt.elo0, sprt.elo1, sprt.alpha, sprt.beta), (0.0, 5.0, 0.05, 0.05));
+        let Ok(sprt) = parse_sprt("-2.5,2.5,0.01,0.1") else { panic!() };
+        assert_eq!((sprt.elo0, sprt.elo1, sprt.alpha, sprt.beta), (-2.5, 2.5, 0.01, 0.1));
+        for bad in ["", "5", "0,5,0.05", "0,5,0.05,0.05,1", "a,b", "0;5"] {
+            assert!(is_usage(parse_sprt(bad)), "--sprt {:?}", bad);
+        }
+    }
+}
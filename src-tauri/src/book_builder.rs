@@ -0,0 +1,460 @@
+// Opening book building from saved games in the notation JSON format (see NOTATION_FORMAT.md).
+// Every game is walked up to a ply limit; each position-move played is credited with the game's
+// result from the mover's side, and moves get a priority from how often they were chosen in
+// their position and how well they scored. When the results are merged into moves already in
+// the book, their priority is worked out again from the merged results.
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::jieqi::{Position, Side};
+use crate::notation::GameNotation;
+use crate::opening_book::{self, ImportMode, ImportReport, JieqiOpeningBook, MoveData, OpeningBookEntry};
+
+fn default_max_ply() -> usize {
+    20
+}
+
+fn default_min_games() -> u32 {
+    1
+}
+
+fn default_mode() -> ImportMode {
+    ImportMode::MergeStatistics
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GameOutcome {
+    RedWins,
+    BlackWins,
+    Draw,
+}
+
+impl GameOutcome {
+    /// The outcome recorded in a game's metadata; `None` for unfinished games.
+    pub fn from_notation(result: &str) -> Option<Self> {
+        match result.trim() {
+            "1-0" => Some(GameOutcome::RedWins),
+            "0-1" => Some(GameOutcome::BlackWins),
+            "1/2-1/2" | "½-½" => Some(GameOutcome::Draw),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BookBuildOptions {
+    /// Moves are taken from each game's first `max_ply` plies.
+    #[serde(default = "default_max_ply")]
+    pub max_ply: usize,
+    /// A move is only added once it was played in at least this many games.
+    #[serde(default = "default_min_games")]
+    pub min_games: u32,
+    /// Only games with one of these players or engines, matched case-insensitively against part
+    /// of the red or black name. Empty for every game.
+    #[serde(default)]
+    pub players: Vec<String>,
+    /// With `players`, only learn the moves those players made, not their opponents'.
+    #[serde(default)]
+    pub player_moves_only: bool,
+    /// Only games with one of these results. Empty for every finished game; unfinished games
+    /// are always left out.
+    #[serde(default)]
+    pub results: Vec<GameOutcome>,
+    /// How built moves combine with moves already in the book. Statistics are merged by default,
+    /// so running the builder on new games extends the book, and merged moves get a priority
+    /// from their merged results.
+    #[serde(default = "default_mode")]
+    pub mode: ImportMode,
+}
+
+impl Default for BookBuildOptions {
+    fn default() -> Self {
+        BookBuildOptions {
+            max_ply: default_max_ply(),
+            min_games: default_min_games(),
+            players: Vec::new(),
+            player_moves_only: false,
+            results: Vec::new(),
+            mode: default_mode(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BookBuildReport {
+    pub games_read: usize,
+    pub games_used: usize,
+    /// One message per game or file that was left out, with the reason.
+    pub skipped: Vec<String>,
+    /// Positions and moves written to the book, after the minimum game count.
+    pub positions: usize,
+    pub moves: usize,
+    pub import: ImportReport,
+}
+
+/// Sent after each game file is read while a book is built.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BookBuildProgress {
+    pub files_done: usize,
+    pub files_total: usize,
+    pub games_used: usize,
+}
+
+#[derive(Debug, Default)]
+struct MoveStats {
+    games: u32,
+    wins: i32,
+    draws: i32,
+    losses: i32,
+}
+
+#[derive(Debug)]
+struct PositionStats {
+    // Canonical FEN, the coordinate system of the moves below
+    fen: String,
+    games: u32,
+    moves: HashMap<String, MoveStats>,
+}
+
+/// Collects statistics from games; `entries` turns them into book entries.
+pub struct BookBuilder {
+    options: BookBuildOptions,
+    positions: HashMap<Vec<u8>, PositionStats>,
+    games_used: usize,
+}
+
+impl BookBuilder {
+    pub fn new(options: BookBuildOptions) -> Self {
+        BookBuilder {
+            options,
+            positions: HashMap::new(),
+            games_used: 0,
+        }
+    }
+
+    pub fn games_used(&self) -> usize {
+        self.games_used
+    }
+
+    /// Learn from one game, or say why it was left out. A game with an invalid position or move
+    /// contributes nothing.
+    pub fn add_game(&mut self, game: &GameNotation) -> Result<(), String> {
+        let result = game.metadata.result.trim();
+        let outcome = GameOutcome::from_notation(result)
+            .ok_or_else(|| format!("no result ({})", if result.is_empty() { "empty" } else { result }))?;
+        if !self.options.results.is_empty() && !self.options.results.contains(&outcome) {
+            return Err(format!("result {} is filtered out", result));
+        }
+        let red_selected = self.is_selected_player(&game.metadata.white);
+        let black_selected = self.is_selected_player(&game.metadata.black);
+        if !red_selected && !black_selected {
+            return Err("none of the selected players took part".into());
+        }
+        if game.metadata.initial_fen.is_empty() {
+            return Err("the game has no initial FEN".into());
+        }
+
+        // Collect the plies first so a bad one leaves the statistics untouched
+        let mut plies = Vec::new();
+        let mut fen_before = game.metadata.initial_fen.as_str();
+        for (ply, record) in game.moves.iter().take(self.options.max_ply).enumerate() {
+            // Adjustments edit the position by hand; what follows is not an opening line
+            if record.kind != "move" {
+                break;
+            }
+            let position = Position::from_fen(fen_before).map_err(|e| format!("ply {}: {}", ply + 1, e))?;
+            let uci = record
+                .data
+                .get(..4)
+                .filter(|uci| opening_book::is_valid_uci(uci))
+                .ok_or_else(|| format!("ply {}: invalid move '{}'", ply + 1, record.data))?;
+            let mover = position.side_to_move();
+            let selected = if mover == Side::Red { red_selected } else { black_selected };
+            if !self.options.player_moves_only || self.options.players.is_empty() || selected {
+                plies.push((position.to_fen(), uci.to_string(), mover));
+            }
+            fen_before = &record.fen;
+        }
+
+        // A position or move repeated within one game counts once
+        let mut seen_positions = HashSet::new();
+        let mut seen_moves = HashSet::new();
+        for (fen, uci, mover) in plies {
+            let (key, transform_idx, canonical) = opening_book::canonical_position(&fen);
+            let uci = opening_book::transform_uci_move(&uci, transform_idx);
+            let position = self.positions.entry(key.clone()).or_insert_with(|| PositionStats {
+                fen: canonical,
+                games: 0,
+                moves: HashMap::new(),
+            });
+            if seen_positions.insert(key.clone()) {
+                position.games += 1;
+            }
+            if !seen_moves.insert((key, uci.clone())) {
+                continue;
+            }
+            let stats = position.moves.entry(uci).or_default();
+            stats.games += 1;
+            match (outcome, mover) {
+                (GameOutcome::Draw, _) => stats.draws += 1,
+                (GameOutcome::RedWins, Side::Red) | (GameOutcome::BlackWins, Side::Black) => stats.wins += 1,
+                _ => stats.losses += 1,
+            }
+        }
+        self.games_used += 1;
+        Ok(())
+    }
+
+    fn is_selected_player(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        self.options.players.is_empty()
+            || self.options.players.iter().any(|p| !p.trim().is_empty() && name.contains(&p.trim().to_lowercase()))
+    }
+
+    /// Book entries for the moves played in at least `min_games` games. A move's priority is
+    /// `100 × frequency × score`: the share of the position's games it was played in, times the
+    /// mover's score with it (a win 1, a draw ½). Keys, FENs and moves are canonical.
+    pub fn entries(&self) -> Vec<OpeningBookEntry> {
+        let mut entries: Vec<OpeningBookEntry> = self
+            .positions
+            .iter()
+            .filter_map(|(key, position)| {
+                let mut moves: Vec<MoveData> = position
+                    .moves
+                    .iter()
+                    .filter(|(_, stats)| stats.games >= self.options.min_games.max(1))
+                    .map(|(uci, stats)| {
+                        let frequency = stats.games as f64 / position.games.max(1) as f64;
+                        let score = (stats.wins as f64 + 0.5 * stats.draws as f64) / stats.games as f64;
+                        MoveData {
+                            uci_move: uci.clone(),
+                            priority: (100.0 * frequency * score).round() as i32,
+                            wins: stats.wins,
+                            draws: stats.draws,
+                            losses: stats.losses,
+                            allowed: true,
+                            comment: String::new(),
+                        }
+                    })
+                    .collect();
+                if moves.is_empty() {
+                    return None;
+                }
+                moves.sort_by(|a, b| b.priority.cmp(&a.priority).then_with(|| a.uci_move.cmp(&b.uci_move)));
+                Some(OpeningBookEntry {
+                    key: hex::encode(key),
+                    fen: position.fen.clone(),
+                    moves,
+                })
+            })
+            .collect();
+        entries.sort_by(|a, b| a.key.cmp(&b.key));
+        entries
+    }
+}
+
+/// Build from game files and write the result to `book` in one transaction. Directories are
+/// searched for `.json` files; files that are not games are reported and skipped. With
+/// `ImportMode::MergeStatistics`, the priorities of the moves built are then recomputed from
+/// their merged results (see `JieqiOpeningBook::rescore_from_results`), so a move's priority
+/// follows all the games it was played in rather than only the latest batch.
+pub fn build_book<F>(
+    book: &JieqiOpeningBook,
+    paths: &[PathBuf],
+    options: &BookBuildOptions,
+    mut on_progress: F,
+) -> Result<BookBuildReport, String>
+where
+    F: FnMut(BookBuildProgress),
+{
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            collect_game_files(path, &mut files);
+        } else {
+            files.push(path.clone());
+        }
+    }
+
+    let mut builder = BookBuilder::new(options.clone());
+    let mut report = BookBuildReport::default();
+    for (done, file) in files.iter().enumerate() {
+        on_progress(BookBuildProgress {
+            files_done: done,
+            files_total: files.len(),
+            games_used: builder.games_used(),
+        });
+        let game = fs::read_to_string(file)
+            .map_err(|e| e.to_string())
+            .and_then(|json| GameNotation::from_json(&json));
+        let game = match game {
+            Ok(game) => game,
+            Err(e) => {
+                report.skipped.push(format!("{}: {}", file.display(), e));
+                continue;
+            }
+        };
+        report.games_read += 1;
+        if let Err(reason) = builder.add_game(&game) {
+            report.skipped.push(format!("{}: {}", file.display(), reason));
+        }
+    }
+
+    let entries = builder.entries();
+    report.games_used = builder.games_used();
+    report.positions = entries.len();
+    report.moves = entries.iter().map(|e| e.moves.len()).sum();
+    on_progress(BookBuildProgress {
+        files_done: files.len(),
+        files_total: files.len(),
+        games_used: report.games_used,
+    });
+    report.import = book.import_entries(&entries, options.mode).map_err(|e| e.to_string())?;
+    if options.mode == ImportMode::MergeStatistics {
+        let moves: Vec<(String, String)> = entries
+            .iter()
+            .flat_map(|entry| entry.moves.iter().map(|m| (entry.key.clone(), m.uci_move.clone())))
+            .collect();
+        book.rescore_from_results(&moves).map_err(|e| e.to_string())?;
+    }
+    Ok(report)
+}
+
+fn collect_game_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else { return };
+    let mut paths: Vec<PathBuf> = entries.flatten().map(|e| e.path()).collect();
+    paths.sort();
+    for path in paths {
+        if path.is_dir() {
+            collect_game_files(&path, files);
+        } else if path.extension().and_then(|e| e.to_str()).is_some_and(|e| e.eq_ignore_ascii_case("json")) {
+            files.push(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jieqi::{Rng, START_FEN};
+    use crate::notation::{NotationMetadata, NotationMove};
+
+    fn game(moves: &[&str], result: &str, red: &str, black: &str) -> GameNotation {
+        let mut position = Position::from_fen(START_FEN).unwrap();
+        let mut rng = Rng::new(1);
+        let moves = moves
+            .iter()
+            .map(|m| {
+                let applied = position.apply_move(m, &mut rng).unwrap();
+                NotationMove {
+                    kind: "move".into(),
+                    data: applied.notation,
+                    fen: position.to_fen(),
+                    ..Default::default()
+                }
+            })
+            .collect();
+        GameNotation {
+            metadata: NotationMetadata {
+                white: red.into(),
+                black: black.into(),
+                result: result.into(),
+                initial_fen: START_FEN.into(),
+                ..Default::default()
+            },
+            moves,
+        }
+    }
+
+    // The built move `uci` in the position `fen`, looked up through its canonical form
+    fn built(builder: &BookBuilder, fen: &str, uci: &str) -> Option<MoveData> {
+        let (key, transform_idx, _) = opening_book::canonical_position(fen);
+        let uci = opening_book::transform_uci_move(uci, transform_idx);
+        let entries = builder.entries();
+        let entry = entries.into_iter().find(|e| e.key == hex::encode(&key))?;
+        entry.moves.into_iter().find(|m| m.uci_move == uci)
+    }
+
+    fn builder_with(options: BookBuildOptions, games: &[GameNotation]) -> BookBuilder {
+        let mut builder = BookBuilder::new(options);
+        for game in games {
+            builder.add_game(game).unwrap();
+        }
+        builder
+    }
+
+    #[test]
+    fn games_without_a_result_or_outside_the_filters_are_skipped() {
+        let mut builder = BookBuilder::new(BookBuildOptions {
+            players: vec!["pika".into()],
+            results: vec![GameOutcome::RedWins, GameOutcome::Draw],
+            ..Default::default()
+        });
+        let unfinished = builder.add_game(&game(&["h2e2"], "*", "Pikafish", "Human"));
+        assert!(unfinished.unwrap_err().starts_with("no result"));
+        let lost = builder.add_game(&game(&["h2e2"], "0-1", "Pikafish", "Human"));
+        assert!(lost.unwrap_err().contains("filtered out"));
+        let others = builder.add_game(&game(&["h2e2"], "1-0", "Human", "Other"));
+        assert!(others.unwrap_err().contains("selected players"));
+        assert_eq!(builder.games_used(), 0);
+        assert!(builder.entries().is_empty());
+
+        builder.add_game(&game(&["h2e2"], "1/2-1/2", "Human", "PIKAFISH 2024")).unwrap();
+        assert_eq!(builder.games_used(), 1);
+        let h2e2 = built(&builder, START_FEN, "h2e2").unwrap();
+        assert_eq!((h2e2.wins, h2e2.draws, h2e2.losses), (0, 1, 0));
+    }
+
+    #[test]
+    fn player_moves_only_skips_the_opponents_moves() {
+        let options = BookBuildOptions {
+            players: vec!["pika".into()],
+            player_moves_only: true,
+            ..Default::default()
+        };
+        let played = game(&["h2e2", "h7e7"], "0-1", "Human", "Pikafish");
+        let builder = builder_with(options, std::slice::from_ref(&played));
+        assert!(built(&builder, START_FEN, "h2e2").is_none());
+        let h7e7 = built(&builder, &played.moves[0].fen, "h7e7").unwrap();
+        assert_eq!((h7e7.wins, h7e7.losses), (1, 0));
+        assert_eq!(builder.entries().len(), 1);
+    }
+
+    #[test]
+    fn a_repeated_position_counts_once_per_game() {
+        // The kings step out and back, so the start position and e0e1 come round again
+        let builder = builder_with(
+            BookBuildOptions::default(),
+            &[game(&["e0e1", "e9e8", "e1e0", "e8e9", "e0e1"], "1-0", "A", "B")],
+        );
+        let e0e1 = built(&builder, START_FEN, "e0e1").unwrap();
+        assert_eq!((e0e1.wins, e0e1.draws, e0e1.losses, e0e1.priority), (1, 0, 0, 100));
+        // Four distinct positions for five plies
+        assert_eq!(builder.entries().len(), 4);
+    }
+
+    #[test]
+    fn priority_is_frequency_times_score() {
+        let games = [
+            game(&["h2e2"], "1-0", "A", "B"),
+            game(&["h2e2"], "0-1", "A", "B"),
+            game(&["b2e2"], "1/2-1/2", "A", "B"),
+        ];
+        let builder = builder_with(BookBuildOptions::default(), &games);
+        // Played in 2 of 3 games scoring ½, and in 1 of 3 scoring ½
+        assert_eq!(built(&builder, START_FEN, "h2e2").unwrap().priority, 33);
+        assert_eq!(built(&builder, START_FEN, "b2e2").unwrap().priority, 17);
+
+        let options = BookBuildOptions {
+            min_games: 2,
+            ..Default::default()
+        };
+        let builder = builder_with(options, &games);
+        assert!(built(&builder, START_FEN, "h2e2").is_some());
+        assert!(built(&builder, START_FEN, "b2e2").is_none());
+        assert_eq!(builder.entries().len(), 1);
+    }
+}
@@ -0,0 +1,476 @@
+// Game clock shared by human-vs-AI games and the match runner. Time is measured with
+// `Instant` on the backend, so it keeps running correctly when the webview is throttled.
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+use crate::jieqi::Side;
+
+// How far a fixed-movetime search may overrun before it counts as a time loss
+const MOVETIME_TOLERANCE: Duration = Duration::from_millis(1000);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum TimeControl {
+    /// All moves in `base_ms`.
+    SuddenDeath { base_ms: u64 },
+    /// `base_ms` plus `increment_ms` after every move.
+    Fischer { base_ms: u64, increment_ms: u64 },
+    /// `base_ms` for every `moves` moves, the unused time carrying over to the next session.
+    MovesPerSession {
+        base_ms: u64,
+        moves: u32,
+        #[serde(default)]
+        increment_ms: u64,
+    },
+    /// Main time, then `periods` periods of `period_ms`. A move finished inside a period keeps it;
+    /// every period fully used up is lost.
+    ByoYomi { base_ms: u64, period_ms: u64, periods: u32 },
+    /// Every move gets `movetime_ms`, nothing carries over.
+    Movetime { movetime_ms: u64 },
+}
+
+impl Default for TimeControl {
+    fn default() -> Self {
+        TimeControl::Fischer {
+            base_ms: 60_000,
+            increment_ms: 1_000,
+        }
+    }
+}
+
+impl TimeControl {
+    fn base_ms(&self) -> u64 {
+        match *self {
+            TimeControl::SuddenDeath { base_ms }
+            | TimeControl::Fischer { base_ms, .. }
+            | TimeControl::MovesPerSession { base_ms, .. }
+            | TimeControl::ByoYomi { base_ms, .. } => base_ms,
+            TimeControl::Movetime { movetime_ms } => movetime_ms,
+        }
+    }
+
+    fn increment_ms(&self) -> u64 {
+        match *self {
+            TimeControl::Fischer { increment_ms, .. } | TimeControl::MovesPerSession { increment_ms, .. } => {
+                increment_ms
+            }
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct SideClock {
+    control: TimeControl,
+    // Main time left, in ms; can go negative only at the moment of flagging
+    remaining_ms: i64,
+    periods_left: u32,
+    moves_in_session: u32,
+}
+
+impl SideClock {
+    fn new(control: TimeControl) -> Self {
+        let periods_left = match control {
+            TimeControl::ByoYomi { periods, .. } => periods,
+            _ => 0,
+        };
+        SideClock {
+            remaining_ms: control.base_ms() as i64,
+            control,
+            periods_left,
+            moves_in_session: 0,
+        }
+    }
+
+    /// Total time this side can still spend on the current move before it flags.
+    fn available_ms(&self, elapsed_ms: i64) -> i64 {
+        match self.control {
+            TimeControl::Movetime { movetime_ms } => movetime_ms as i64 - elapsed_ms,
+            TimeControl::ByoYomi { period_ms, .. } => {
+                self.remaining_ms + period_ms as i64 * self.periods_left as i64 - elapsed_ms
+            }
+            _ => self.remaining_ms - elapsed_ms,
+        }
+    }
+
+    /// Time to report to an engine in `wtime`/`btime`. In byo-yomi only one period is counted,
+    /// so the engine does not plan to burn through several periods on a single move.
+    fn go_time_ms(&self, elapsed_ms: i64) -> i64 {
+        match self.control {
+            TimeControl::ByoYomi { period_ms, .. } if self.periods_left > 0 => {
+                let main = (self.remaining_ms - elapsed_ms).max(0);
+                if main > 0 {
+                    main + period_ms as i64
+                } else {
+                    self.display_ms(elapsed_ms)
+                }
+            }
+            _ => self.available_ms(elapsed_ms),
+        }
+    }
+
+    /// Time shown on the clock while `elapsed_ms` of the current move has gone: main time,
+    /// or what is left of the current byo-yomi period.
+    fn display_ms(&self, elapsed_ms: i64) -> i64 {
+        match self.control {
+            TimeControl::Movetime { movetime_ms } => movetime_ms as i64 - elapsed_ms,
+            TimeControl::ByoYomi { period_ms, .. } => {
+                let main = self.remaining_ms - elapsed_ms;
+                let period = period_ms as i64;
+                if main > 0 || period == 0 {
+                    main
+                } else if main == 0 {
+                    period
+                } else {
+                    period - (-main - 1) % period - 1
+                }
+            }
+            _ => self.remaining_ms - elapsed_ms,
+        }
+    }
+
+    /// Charge a finished move. Returns false when the side ran out of time on it.
+    fn charge(&mut self, elapsed: Duration) -> bool {
+        let elapsed_ms = elapsed.as_millis() as i64;
+        match self.control {
+            TimeControl::Movetime { movetime_ms } => {
+                return elapsed <= Duration::from_millis(movetime_ms) + MOVETIME_TOLERANCE;
+            }
+            TimeControl::ByoYomi { period_ms, .. } => {
+                let overflow = elapsed_ms - self.remaining_ms;
+                self.remaining_ms = (self.remaining_ms - elapsed_ms).max(0);
+                if overflow > 0 {
+                    let touched = if period_ms == 0 {
+                        u32::MAX
+                    } else {
+                        (overflow as u64).div_ceil(period_ms).min(u32::MAX as u64) as u32
+                    };
+                    if touched > self.periods_left {
+                        self.periods_left = 0;
+                        return false;
+                    }
+                    self.periods_left -= touched - 1;
+                }
+                return true;
+            }
+            _ => {}
+        }
+
+        self.remaining_ms -= elapsed_ms;
+        if self.remaining_ms < 0 {
+            return false;
+        }
+        self.remaining_ms += self.control.increment_ms() as i64;
+        if let TimeControl::MovesPerSession { base_ms, moves, .. } = self.control {
+            self.moves_in_session += 1;
+            if moves > 0 && self.moves_in_session >= moves {
+                self.moves_in_session = 0;
+                self.remaining_ms += base_ms as i64;
+            }
+        }
+        true
+    }
+
+    fn moves_to_go(&self) -> Option<u32> {
+        match self.control {
+            TimeControl::MovesPerSession { moves, .. } if moves > 0 => Some(moves - self.moves_in_session),
+            TimeControl::ByoYomi { .. } if self.remaining_ms <= 0 => Some(1),
+            _ => None,
+        }
+    }
+
+    fn snapshot(&self, elapsed_ms: i64) -> SideSnapshot {
+        let in_byoyomi = matches!(self.control, TimeControl::ByoYomi { .. }) && self.remaining_ms - elapsed_ms <= 0;
+        SideSnapshot {
+            remaining_ms: self.display_ms(elapsed_ms).max(0) as u64,
+            available_ms: self.available_ms(elapsed_ms).max(0) as u64,
+            periods_left: matches!(self.control, TimeControl::ByoYomi { .. }).then_some(self.periods_left),
+            moves_to_go: self.moves_to_go(),
+            in_byoyomi,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SideSnapshot {
+    /// What the clock face shows: main time, or the current byo-yomi period.
+    pub remaining_ms: u64,
+    /// Everything left for the current move, byo-yomi periods included.
+    pub available_ms: u64,
+    pub periods_left: Option<u32>,
+    pub moves_to_go: Option<u32>,
+    pub in_byoyomi: bool,
+}
+
+/// State of both clocks at one instant; this is the payload of `clock-tick` events.
+#[derive(Debug, Clone, Serialize)]
+pub struct ClockSnapshot {
+    pub red: SideSnapshot,
+    pub black: SideSnapshot,
+    pub running: Option<Side>,
+    pub paused: bool,
+    pub flagged: Option<Side>,
+}
+
+/// Both sides' clocks. One side's clock runs at a time; `press` stops it and starts the other.
+#[derive(Debug, Clone)]
+pub struct GameClock {
+    red: SideClock,
+    black: SideClock,
+    to_move: Side,
+    // When the side to move started thinking; None while stopped or paused
+    turn_started: Option<Instant>,
+    // Time already used on the current move before a pause
+    banked: Duration,
+    flagged: Option<Side>,
+}
+
+impl GameClock {
+    pub fn new(control: TimeControl) -> Self {
+        Self::with_controls(control.clone(), control)
+    }
+
+    /// Separate controls per side, e.g. for a time handicap against the engine.
+    pub fn with_controls(red: TimeControl, black: TimeControl) -> Self {
+        GameClock {
+            red: SideClock::new(red),
+            black: SideClock::new(black),
+            to_move: Side::Red,
+            turn_started: None,
+            banked: Duration::ZERO,
+            flagged: None,
+        }
+    }
+
+    fn side(&self, side: Side) -> &SideClock {
+        match side {
+            Side::Red => &self.red,
+            Side::Black => &self.black,
+        }
+    }
+
+    fn side_mut(&mut self, side: Side) -> &mut SideClock {
+        match side {
+            Side::Red => &mut self.red,
+            Side::Black => &mut self.black,
+        }
+    }
+
+    pub fn control(&self, side: Side) -> &TimeControl {
+        &self.side(side).control
+    }
+
+    pub fn side_to_move(&self) -> Side {
+        self.to_move
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.turn_started.is_some()
+    }
+
+    pub fn flagged(&self) -> Option<Side> {
+        self.flagged
+    }
+
+    /// Start `side`'s clock for a new move, discarding any time from an unfinished one.
+    pub fn start(&mut self, side: Side) {
+        self.to_move = side;
+        self.banked = Duration::ZERO;
+        if self.flagged.is_none() {
+            self.turn_started = Some(Instant::now());
+        }
+    }
+
+    pub fn pause(&mut self) {
+        if let Some(started) = self.turn_started.take() {
+            self.banked += started.elapsed();
+        }
+    }
+
+    pub fn resume(&mut self) {
+        if self.turn_started.is_none() && self.flagged.is_none() {
+            self.turn_started = Some(Instant::now());
+        }
+    }
+
+    /// Time spent on the current move so far.
+    pub fn elapsed(&self) -> Duration {
+        self.banked + self.turn_started.map(|s| s.elapsed()).unwrap_or_default()
+    }
+
+    /// End the current move: charge it to the side to move and start the opponent's clock.
+    /// Returns the time the move took, or `Err` with the side that lost on time.
+    pub fn press(&mut self) -> Result<Duration, Side> {
+        let elapsed = self.elapsed();
+        let side = self.to_move;
+        if self.flagged.is_some() || !self.side_mut(side).charge(elapsed) {
+            self.turn_started = None;
+            self.flagged = Some(side);
+            return Err(side);
+        }
+        self.start(side.opponent());
+        Ok(elapsed)
+    }
+
+    /// Check the running clock without ending the move; flags the side to move if it is out of time.
+    pub fn check_flag(&mut self) -> Option<Side> {
+        if self.flagged.is_none() && self.is_running() {
+            let elapsed = self.elapsed();
+            let limit = match self.control(self.to_move) {
+                TimeControl::Movetime { .. } => MOVETIME_TOLERANCE.as_millis() as i64,
+                _ => 0,
+            };
+            if self.side(self.to_move).available_ms(elapsed.as_millis() as i64) < -limit {
+                self.turn_started = None;
+                self.flagged = Some(self.to_move);
+            }
+        }
+        self.flagged
+    }
+
+    /// How long the side to move may think before it loses on time.
+    pub fn budget(&self) -> Duration {
+        let elapsed = self.elapsed().as_millis() as i64;
+        Duration::from_millis(self.side(self.to_move).available_ms(elapsed).max(0) as u64)
+    }
+
+    /// The `go` command for an engine moving now, with both sides' remaining time.
+    pub fn go_command(&self) -> String {
+        let mover = self.side(self.to_move);
+        if let TimeControl::Movetime { movetime_ms } = mover.control {
+            return format!("go movetime {}", movetime_ms);
+        }
+        let (red_elapsed, black_elapsed) = self.elapsed_by_side();
+        let mut go = format!(
+            "go wtime {} btime {}",
+            self.red.go_time_ms(red_elapsed).max(0),
+            self.black.go_time_ms(black_elapsed).max(0)
+        );
+        let (winc, binc) = (self.red.control.increment_ms(), self.black.control.increment_ms());
+        if winc > 0 || binc > 0 {
+            go.push_str(&format!(" winc {} binc {}", winc, binc));
+        }
+        if let Some(moves_to_go) = mover.moves_to_go() {
+            go.push_str(&format!(" movestogo {}", moves_to_go));
+        }
+        go
+    }
+
+    fn elapsed_by_side(&self) -> (i64, i64) {
+        let elapsed = self.elapsed().as_millis() as i64;
+        match self.to_move {
+            Side::Red => (elapsed, 0),
+            Side::Black => (0, elapsed),
+        }
+    }
+
+    pub fn snapshot(&self) -> ClockSnapshot {
+        let (red_elapsed, black_elapsed) = self.elapsed_by_side();
+        ClockSnapshot {
+            red: self.red.snapshot(red_elapsed),
+            black: self.black.snapshot(black_elapsed),
+            running: self.is_running().then_some(self.to_move),
+            paused: !self.is_running() && self.banked > Duration::ZERO,
+            flagged: self.flagged,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn byo_yomi_keeps_periods_that_are_not_used_up() {
+        let mut clock = SideClock::new(TimeControl::ByoYomi { base_ms: 1000, period_ms: 500, periods: 3 });
+        assert!(clock.charge(ms(800)));
+        assert_eq!((clock.remaining_ms, clock.periods_left), (200, 3));
+        assert_eq!(clock.go_time_ms(0), 700);
+        // Main time runs out and the move ends inside the first period
+        assert!(clock.charge(ms(600)));
+        assert_eq!((clock.remaining_ms, clock.periods_left), (0, 3));
+        assert_eq!(clock.moves_to_go(), Some(1));
+        assert!(clock.charge(ms(500)));
+        assert_eq!(clock.periods_left, 3);
+        // Two periods used up, the move ends in the third
+        assert!(clock.charge(ms(1200)));
+        assert_eq!(clock.periods_left, 1);
+        assert!(!clock.charge(ms(501)));
+        assert_eq!(clock.periods_left, 0);
+    }
+
+    #[test]
+    fn byo_yomi_display_counts_down_each_period() {
+        let clock = SideClock::new(TimeControl::ByoYomi { base_ms: 1000, period_ms: 500, periods: 2 });
+        assert_eq!(clock.display_ms(400), 600);
+        assert_eq!(clock.display_ms(1000), 500);
+        assert_eq!(clock.display_ms(1100), 400);
+        assert_eq!(clock.display_ms(1600), 400);
+        assert_eq!(clock.available_ms(1100), 900);
+        let snapshot = clock.snapshot(1100);
+        assert!(snapshot.in_byoyomi);
+        assert_eq!((snapshot.remaining_ms, snapshot.available_ms, snapshot.periods_left), (400, 900, Some(2)));
+    }
+
+    #[test]
+    fn fischer_adds_the_increment_after_each_move() {
+        let mut clock = SideClock::new(TimeControl::Fischer { base_ms: 1000, increment_ms: 100 });
+        assert!(clock.charge(ms(300)));
+        assert_eq!(clock.remaining_ms, 800);
+        assert!(!clock.charge(ms(801)));
+        let mut clock = SideClock::new(TimeControl::SuddenDeath { base_ms: 1000 });
+        assert!(clock.charge(ms(1000)));
+        assert_eq!(clock.remaining_ms, 0);
+    }
+
+    #[test]
+    fn sessions_refill_after_their_moves() {
+        let mut clock = SideClock::new(TimeControl::MovesPerSession { base_ms: 1000, moves: 2, increment_ms: 0 });
+        assert_eq!(clock.moves_to_go(), Some(2));
+        assert!(clock.charge(ms(400)));
+        assert_eq!(clock.moves_to_go(), Some(1));
+        assert!(clock.charge(ms(400)));
+        assert_eq!((clock.remaining_ms, clock.moves_to_go()), (1200, Some(2)));
+    }
+
+    #[test]
+    fn movetime_allows_a_small_overrun() {
+        let mut clock = SideClock::new(TimeControl::Movetime { movetime_ms: 1000 });
+        assert!(clock.charge(ms(1000) + MOVETIME_TOLERANCE));
+        assert!(!clock.charge(ms(1001) + MOVETIME_TOLERANCE));
+    }
+
+    #[test]
+    fn go_command_reports_both_sides() {
+        let mut clock = GameClock::with_controls(
+            TimeControl::Fischer { base_ms: 60_000, increment_ms: 1_000 },
+            TimeControl::SuddenDeath { base_ms: 30_000 },
+        );
+        assert_eq!(clock.go_command(), "go wtime 60000 btime 30000 winc 1000 binc 0");
+        assert!(clock.press().is_ok());
+        assert_eq!(clock.side_to_move(), Side::Black);
+        assert_eq!(clock.go_command(), "go wtime 61000 btime 30000 winc 1000 binc 0");
+
+        let clock = GameClock::new(TimeControl::MovesPerSession { base_ms: 10_000, moves: 40, increment_ms: 0 });
+        assert_eq!(clock.go_command(), "go wtime 10000 btime 10000 movestogo 40");
+        let clock = GameClock::new(TimeControl::ByoYomi { base_ms: 0, period_ms: 5_000, periods: 3 });
+        assert_eq!(clock.go_command(), "go wtime 5000 btime 5000 movestogo 1");
+        assert_eq!(GameClock::new(TimeControl::Movetime { movetime_ms: 2_000 }).go_command(), "go movetime 2000");
+    }
+
+    #[test]
+    fn a_flagged_side_stays_flagged() {
+        let mut clock = GameClock::new(TimeControl::SuddenDeath { base_ms: 0 });
+        clock.start(Side::Red);
+        std::thread::sleep(ms(5));
+        assert_eq!(clock.check_flag(), Some(Side::Red));
+        assert!(!clock.is_running());
+        assert_eq!(clock.press(), Err(Side::Red));
+        clock.resume();
+        assert!(!clock.is_running());
+    }
+}
@@ -0,0 +1,207 @@
+// Batching of engine output on its way to the webview. During a MultiPV search an engine can
+// print hundreds of `info` lines a second; most are outdated by the time the GUI draws them.
+// Lines are collected for a short interval and sent together, and a pending `info` line is
+// dropped when a newer one for the same PV arrives. Lines that end a handshake or a search are
+// never held back.
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+use crate::transcript::Direction;
+
+fn default_flush_interval_ms() -> u64 {
+    50
+}
+
+fn default_max_batch() -> usize {
+    64
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutputBatching {
+    /// How long a line may wait for others to join it. 0 sends every line on its own.
+    #[serde(default = "default_flush_interval_ms")]
+    pub flush_interval_ms: u64,
+    /// A batch is sent early once it holds this many lines.
+    #[serde(default = "default_max_batch")]
+    pub max_batch: usize,
+}
+
+impl Default for OutputBatching {
+    fn default() -> Self {
+        OutputBatching {
+            flush_interval_ms: default_flush_interval_ms(),
+            max_batch: default_max_batch(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputLine {
+    pub text: String,
+    pub direction: Direction,
+}
+
+/// Lines to deliver now: the batch first, then the line that caused the flush, if any.
+#[derive(Debug, Default, PartialEq)]
+pub struct Flush {
+    pub batch: Vec<OutputLine>,
+    pub immediate: Option<OutputLine>,
+}
+
+#[derive(Debug)]
+pub struct OutputCoalescer {
+    config: OutputBatching,
+    pending: Vec<OutputLine>,
+    // When the oldest pending line arrived
+    since: Option<Instant>,
+}
+
+impl OutputCoalescer {
+    pub fn new(config: OutputBatching) -> Self {
+        OutputCoalescer {
+            config,
+            pending: Vec::new(),
+            since: None,
+        }
+    }
+
+    /// Add a line. Returns what has to be delivered right away, if anything.
+    pub fn push(&mut self, line: OutputLine) -> Option<Flush> {
+        if self.config.flush_interval_ms == 0 || is_urgent(&line) {
+            return Some(Flush {
+                batch: self.take(),
+                immediate: Some(line),
+            });
+        }
+        if let Some(key) = supersede_key(&line) {
+            self.pending.retain(|pending| supersede_key(pending) != Some(key));
+        }
+        self.pending.push(line);
+        self.since.get_or_insert_with(Instant::now);
+        (self.pending.len() >= self.config.max_batch.max(1)).then(|| Flush {
+            batch: self.take(),
+            immediate: None,
+        })
+    }
+
+    /// When the pending batch is due, or `None` if nothing is pending.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.since.map(|since| since + Duration::from_millis(self.config.flush_interval_ms))
+    }
+
+    /// Remove and return all pending lines.
+    pub fn take(&mut self) -> Vec<OutputLine> {
+        self.since = None;
+        std::mem::take(&mut self.pending)
+    }
+}
+
+// Handshake replies and search results that callers wait for
+fn is_urgent(line: &OutputLine) -> bool {
+    if line.direction != Direction::Stdout {
+        return false;
+    }
+    let keyword = line.text.split_whitespace().next().unwrap_or_default();
+    matches!(
+        keyword,
+        "bestmove" | "nobestmove" | "uciok" | "ucciok" | "jaiok" | "readyok" | "id" | "option" | "copyprotection" | "registration"
+    )
+}
+
+// Pending lines with the same key are replaced by the newest one: search lines per MultiPV
+// index, and the current-move progress lines. Everything else is always delivered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SupersedeKey {
+    Pv(u32),
+    CurrMove,
+}
+
+fn supersede_key(line: &OutputLine) -> Option<SupersedeKey> {
+    if line.direction != Direction::Stdout {
+        return None;
+    }
+    let tokens: Vec<&str> = line.text.split_whitespace().collect();
+    if tokens.first() != Some(&"info") || tokens.contains(&"string") {
+        return None;
+    }
+    let value = |name: &str| tokens.iter().position(|t| *t == name).and_then(|i| tokens.get(i + 1));
+    if tokens.contains(&"pv") {
+        let multipv = value("multipv").and_then(|v| v.parse().ok()).unwrap_or(1);
+        Some(SupersedeKey::Pv(multipv))
+    } else if tokens.contains(&"currmove") {
+        Some(SupersedeKey::CurrMove)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stdout(text: &str) -> OutputLine {
+        OutputLine {
+            text: text.into(),
+            direction: Direction::Stdout,
+        }
+    }
+
+    fn texts(lines: &[OutputLine]) -> Vec<&str> {
+        lines.iter().map(|line| line.text.as_str()).collect()
+    }
+
+    #[test]
+    fn newer_pv_lines_replace_pending_ones() {
+        let mut coalescer = OutputCoalescer::new(OutputBatching { flush_interval_ms: 50, max_batch: 10 });
+        assert!(coalescer.push(stdout("info depth 1 multipv 1 score cp 1 pv h2e2")).is_none());
+        assert!(coalescer.push(stdout("info depth 1 multipv 2 score cp 0 pv b2e2")).is_none());
+        assert!(coalescer.push(stdout("info depth 1 currmove h2e2 currmovenumber 1")).is_none());
+        assert!(coalescer.push(stdout("info string hello")).is_none());
+        // No multipv counts as the first PV
+        assert!(coalescer.push(stdout("info depth 2 score cp 2 pv h2e2 h9g7")).is_none());
+        assert!(coalescer.push(stdout("info depth 2 currmove b2e2 currmovenumber 2")).is_none());
+        assert!(coalescer.push(OutputLine { text: "info depth 3 pv x".into(), direction: Direction::Stderr }).is_none());
+        assert!(coalescer.deadline().is_some());
+        assert_eq!(
+            texts(&coalescer.take()),
+            [
+                "info depth 1 multipv 2 score cp 0 pv b2e2",
+                "info string hello",
+                "info depth 2 score cp 2 pv h2e2 h9g7",
+                "info depth 2 currmove b2e2 currmovenumber 2",
+                "info depth 3 pv x",
+            ]
+        );
+        assert!(coalescer.deadline().is_none());
+    }
+
+    #[test]
+    fn urgent_lines_flush_the_batch_first() {
+        let mut coalescer = OutputCoalescer::new(OutputBatching::default());
+        coalescer.push(stdout("info depth 5 pv h2e2"));
+        let flush = coalescer.push(stdout("bestmove h2e2")).unwrap();
+        assert_eq!(texts(&flush.batch), ["info depth 5 pv h2e2"]);
+        assert_eq!(flush.immediate, Some(stdout("bestmove h2e2")));
+        assert!(coalescer.deadline().is_none());
+        // Urgency only applies to engine output
+        let echoed = OutputLine { text: "readyok".into(), direction: Direction::Sent };
+        assert!(coalescer.push(echoed).is_none());
+    }
+
+    #[test]
+    fn full_batches_and_disabled_batching() {
+        let mut coalescer = OutputCoalescer::new(OutputBatching { flush_interval_ms: 50, max_batch: 3 });
+        assert!(coalescer.push(stdout("info nodes 1")).is_none());
+        assert!(coalescer.push(stdout("info nodes 2")).is_none());
+        let flush = coalescer.push(stdout("info nodes 3")).unwrap();
+        assert_eq!((flush.batch.len(), flush.immediate), (3, None));
+
+        let mut coalescer = OutputCoalescer::new(OutputBatching { flush_interval_ms: 0, max_batch: 3 });
+        let flush = coalescer.push(stdout("info depth 1 pv h2e2")).unwrap();
+        assert_eq!(flush.immediate, Some(stdout("info depth 1 pv h2e2")));
+        assert!(flush.batch.is_empty());
+
+        let config: OutputBatching = serde_json::from_str("{}").unwrap();
+        assert_eq!(config, OutputBatching::default());
+    }
+}
@@ -0,0 +1,344 @@
+// Engine discovery: finds engine binaries in the given directories and on $PATH, checks that
+// they can run on this machine, and probes the ones that can for their protocol and id.
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::engine::{self, EngineEncoding, EngineProtocol};
+
+pub const DEFAULT_PROBE_TIMEOUT_MS: u64 = 2000;
+
+// Everything on $PATH would include tools like `rm`, so only these names are probed there
+const KNOWN_ENGINE_NAMES: &[&str] = &[
+    "pikafish",
+    "fairy-stockfish",
+    "fairystockfish",
+    "stockfish",
+    "eleeye",
+    "elephanteye",
+    "xqms",
+    "cyclone",
+    "jieqi",
+];
+
+// Files that sit next to engines but are never engines themselves
+const SKIPPED_EXTENSIONS: &[&str] = &[
+    "nnue", "bin", "dll", "so", "dylib", "txt", "md", "ini", "json", "cfg", "conf", "png", "jpg", "ico", "html", "pdf",
+    "zip", "log", "epd", "pgn",
+];
+
+// Directories below a search root are scanned this deep, e.g. `resources/engine/pikafish`
+const MAX_DEPTH: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EngineSource {
+    /// Shipped inside the application bundle.
+    Bundled,
+    /// The user's engines directory.
+    UserDir,
+    Path,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum DiscoveryResult {
+    /// Answered a handshake; ready to register.
+    Engine { protocol: EngineProtocol, name: String, author: String },
+    NotExecutable { reason: String },
+    /// Built for another operating system or CPU, e.g. a Windows `.exe` on Linux.
+    WrongArchitecture { found: String, expected: String },
+    /// Runs, but did not complete a UCI, UCCI or JAI handshake in time.
+    NoHandshake { error: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiscoveredEngine {
+    pub path: String,
+    pub source: EngineSource,
+    #[serde(flatten)]
+    pub result: DiscoveryResult,
+    /// Whether an engine profile already exists for this path; filled in by the caller.
+    #[serde(default)]
+    pub registered: bool,
+}
+
+/// Scan `dirs` (every file is a candidate) and, with `search_path`, the directories on $PATH
+/// (only files named like known engines). Candidates that can run here are probed in parallel,
+/// each with `timeout` per protocol tried.
+pub fn discover(dirs: &[(EngineSource, PathBuf)], search_path: bool, timeout: Duration) -> Vec<DiscoveredEngine> {
+    let mut seen = HashSet::new();
+    let mut candidates = Vec::new();
+    for (source, dir) in dirs {
+        collect_files(dir, MAX_DEPTH, &mut |path| {
+            if !has_skipped_extension(path) {
+                candidates.push((*source, path.to_path_buf()));
+            }
+        });
+    }
+    if search_path {
+        let path_var = std::env::var_os("PATH").unwrap_or_default();
+        for dir in std::env::split_paths(&path_var) {
+            collect_files(&dir, 0, &mut |path| {
+                if is_known_engine_name(path) {
+                    candidates.push((EngineSource::Path, path.to_path_buf()));
+                }
+            });
+        }
+    }
+    candidates.retain(|(_, path)| seen.insert(fs::canonicalize(path).unwrap_or_else(|_| path.clone())));
+
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = candidates
+            .iter()
+            .map(|(source, path)| scope.spawn(move || examine(*source, path, timeout)))
+            .collect();
+        handles.into_iter().filter_map(|h| h.join().ok()).collect()
+    })
+}
+
+fn examine(source: EngineSource, path: &Path, timeout: Duration) -> DiscoveredEngine {
+    let result = match check_runnable(path) {
+        Err(result) => result,
+        Ok(()) => {
+            let path_str = path.to_string_lossy();
+            match engine::probe_engine(&path_str, &[], EngineEncoding::Auto, timeout) {
+                Ok(info) => DiscoveryResult::Engine {
+                    protocol: info.protocol,
+                    name: info.name,
+                    author: info.author,
+                },
+                Err(error) => DiscoveryResult::NoHandshake { error },
+            }
+        }
+    };
+    DiscoveredEngine {
+        path: path.to_string_lossy().into_owned(),
+        source,
+        result,
+        registered: false,
+    }
+}
+
+fn collect_files(dir: &Path, depth: usize, found: &mut dyn FnMut(&Path)) {
+    let Ok(entries) = fs::read_dir(dir) else { return };
+    let mut paths: Vec<PathBuf> = entries.flatten().map(|e| e.path()).collect();
+    paths.sort();
+    for path in paths {
+        if path.is_dir() {
+            if depth > 0 {
+                collect_files(&path, depth - 1, found);
+            }
+        } else if path.is_file() {
+            found(&path);
+        }
+    }
+}
+
+fn has_skipped_extension(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| SKIPPED_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+}
+
+fn is_known_engine_name(path: &Path) -> bool {
+    let Some(stem) = path.file_stem().and_then(|s| s.to_str()) else { return false };
+    let stem = stem.to_ascii_lowercase();
+    KNOWN_ENGINE_NAMES.iter().any(|name| stem.starts_with(name))
+}
+
+/// Whether the file can be started on this machine. The error is the result to report.
+pub fn check_runnable(path: &Path) -> Result<(), DiscoveryResult> {
+    let mut header = [0u8; 64];
+    let read = File::open(path).and_then(|mut f| f.read(&mut header)).map_err(|e| DiscoveryResult::NotExecutable {
+        reason: e.to_string(),
+    })?;
+    let header = &header[..read];
+    // A binary's format and CPU are checked before the permission bit, since that is the more useful message
+    if let Some(found) = binary_target(path, header) {
+        let expected = format!("{} {}", std::env::consts::OS, std::env::consts::ARCH);
+        if !target_matches(&found) {
+            return Err(DiscoveryResult::WrongArchitecture { found, expected });
+        }
+    }
+    if !is_executable(path, header) {
+        return Err(DiscoveryResult::NotExecutable {
+            reason: "the file is not marked executable".into(),
+        });
+    }
+    Ok(())
+}
+
+// "<os> <arch>" of a native executable, in the same terms as std::env::consts.
+// None for scripts and anything unrecognised, which are left to the handshake to judge.
+fn binary_target(path: &Path, header: &[u8]) -> Option<String> {
+    let u16_le = |at: usize| header.get(at..at + 2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+    let u32_le = |at: usize| header.get(at..at + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+
+    if header.starts_with(b"\x7fELF") {
+        let machine = if header.get(5) == Some(&2) {
+            header.get(18..20).map(|b| u16::from_be_bytes([b[0], b[1]]))
+        } else {
+            u16_le(18)
+        }?;
+        let arch = match machine {
+            0x03 => "x86",
+            0x3e => "x86_64",
+            0x28 => "arm",
+            0xb7 => "aarch64",
+            0xf3 => "riscv64",
+            0x08 => "mips",
+            0x15 => "powerpc64",
+            other => return Some(format!("linux machine 0x{:x}", other)),
+        };
+        // ELF is also used by Android, which reports itself as its own OS
+        let os = if cfg!(target_os = "android") { "android" } else { "linux" };
+        return Some(format!("{} {}", os, arch));
+    }
+    if header.starts_with(b"MZ") {
+        // The PE header offset sits at 0x3c, past the part read so far for small headers
+        let offset = u32_le(0x3c)? as u64;
+        let mut pe = [0u8; 6];
+        let mut file = File::open(path).ok()?;
+        file.seek(SeekFrom::Start(offset)).ok()?;
+        file.read_exact(&mut pe).ok()?;
+        if &pe[..4] != b"PE\0\0" {
+            return None;
+        }
+        let arch = match u16::from_le_bytes([pe[4], pe[5]]) {
+            0x14c => "x86",
+            0x8664 => "x86_64",
+            0xaa64 => "aarch64",
+            0x1c4 => "arm",
+            other => return Some(format!("windows machine 0x{:x}", other)),
+        };
+        return Some(format!("windows {}", arch));
+    }
+    match u32_le(0)? {
+        // 64-bit Mach-O, little endian
+        0xfeed_facf => {
+            let arch = match u32_le(4)? {
+                0x0100_0007 => "x86_64",
+                0x0100_000c => "aarch64",
+                other => return Some(format!("macos cpu 0x{:x}", other)),
+            };
+            Some(format!("macos {}", arch))
+        }
+        // Universal binaries carry several architectures; like scripts, they are left to the handshake
+        _ => None,
+    }
+}
+
+fn target_matches(found: &str) -> bool {
+    let (os, arch) = found.split_once(' ').unwrap_or((found, ""));
+    os == std::env::consts::OS && arch == std::env::consts::ARCH
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path, _header: &[u8]) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path).is_ok_and(|m| m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path, header: &[u8]) -> bool {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_ascii_lowercase();
+    header.starts_with(b"MZ") || matches!(extension.as_str(), "exe" | "bat" | "cmd" | "com")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str, contents: &[u8]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("jieqibox-discovery-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn elf(big_endian: bool, machine: u16) -> Vec<u8> {
+        let mut header = vec![0u8; 64];
+        header[..4].copy_from_slice(b"\x7fELF");
+        header[4] = 2;
+        header[5] = if big_endian { 2 } else { 1 };
+        let machine = if big_endian { machine.to_be_bytes() } else { machine.to_le_bytes() };
+        header[18..20].copy_from_slice(&machine);
+        header
+    }
+
+    // The PE header is placed past the first 64 bytes, as real linkers do
+    fn pe(machine: u16, signature: &[u8; 4]) -> Vec<u8> {
+        let mut file = vec![0u8; 0x90];
+        file[..2].copy_from_slice(b"MZ");
+        file[0x3c..0x40].copy_from_slice(&0x80u32.to_le_bytes());
+        file[0x80..0x84].copy_from_slice(signature);
+        file[0x84..0x86].copy_from_slice(&machine.to_le_bytes());
+        file
+    }
+
+    fn mach_o(cpu: u32) -> Vec<u8> {
+        let mut header = 0xfeed_facfu32.to_le_bytes().to_vec();
+        header.extend(cpu.to_le_bytes());
+        header.extend([0u8; 24]);
+        header
+    }
+
+    fn target(name: &str, contents: &[u8]) -> Option<String> {
+        let path = fixture(name, contents);
+        binary_target(&path, &contents[..contents.len().min(64)])
+    }
+
+    #[test]
+    fn headers_name_their_os_and_cpu() {
+        let linux = if cfg!(target_os = "android") { "android" } else { "linux" };
+        assert_eq!(target("elf-x64", &elf(false, 0x3e)), Some(format!("{} x86_64", linux)));
+        assert_eq!(target("elf-arm64", &elf(false, 0xb7)), Some(format!("{} aarch64", linux)));
+        assert_eq!(target("elf-ppc", &elf(true, 0x15)), Some(format!("{} powerpc64", linux)));
+        assert_eq!(target("elf-other", &elf(false, 0x1234)), Some("linux machine 0x1234".into()));
+
+        assert_eq!(target("pe-x64.exe", &pe(0x8664, b"PE\0\0")), Some("windows x86_64".into()));
+        assert_eq!(target("pe-x86.exe", &pe(0x14c, b"PE\0\0")), Some("windows x86".into()));
+        assert_eq!(target("dos.exe", &pe(0x8664, b"NE\0\0")), None);
+        let mut truncated = pe(0x8664, b"PE\0\0");
+        truncated.truncate(0x82);
+        assert_eq!(target("truncated.exe", &truncated), None);
+
+        assert_eq!(target("macho-arm64", &mach_o(0x0100_000c)), Some("macos aarch64".into()));
+        assert_eq!(target("macho-x64", &mach_o(0x0100_0007)), Some("macos x86_64".into()));
+        assert_eq!(target("universal", &0xcafe_babeu32.to_be_bytes()), None);
+        assert_eq!(target("script", b"#!/bin/sh\necho uciok\n"), None);
+        assert_eq!(target("empty", b""), None);
+    }
+
+    #[test]
+    fn foreign_binaries_are_the_wrong_architecture() {
+        let foreign = if cfg!(windows) { mach_o(0x0100_000c) } else { pe(0x8664, b"PE\0\0") };
+        let path = fixture("foreign", &foreign);
+        match check_runnable(&path) {
+            Err(DiscoveryResult::WrongArchitecture { expected, .. }) => {
+                assert_eq!(expected, format!("{} {}", std::env::consts::OS, std::env::consts::ARCH));
+            }
+            other => panic!("{:?}", other),
+        }
+        // The test binary itself is native and executable
+        assert!(check_runnable(&std::env::current_exe().unwrap()).is_ok());
+        let missing = check_runnable(&path.with_file_name("missing"));
+        assert!(matches!(missing, Err(DiscoveryResult::NotExecutable { .. })));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn scripts_need_the_executable_bit() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = fixture("engine.sh", b"#!/bin/sh\n");
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(matches!(check_runnable(&path), Err(DiscoveryResult::NotExecutable { .. })));
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(check_runnable(&path).is_ok());
+    }
+}
@@ -0,0 +1,548 @@
+use encoding_rs::{Encoding, BIG5, GB18030, GBK, UTF_8};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::protocol::ProtocolAdapter;
+use crate::resources::{self, AppliedResources, EngineResources};
+use crate::transcript::{Direction, Transcript};
+use crate::uci::{self, UciMessage, UciOption};
+
+pub const DEFAULT_HANDSHAKE_TIMEOUT_MS: u64 = 5000;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EngineProtocol {
+    #[default]
+    Uci,
+    Ucci,
+    Jai,
+}
+
+impl FromStr for EngineProtocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "uci" => Ok(EngineProtocol::Uci),
+            "ucci" => Ok(EngineProtocol::Ucci),
+            "jai" => Ok(EngineProtocol::Jai),
+            _ => Err(format!("Unknown protocol '{}' (expected uci, ucci or jai)", s)),
+        }
+    }
+}
+
+impl EngineProtocol {
+    /// The command that starts the handshake and the line that completes it, as seen through
+    /// the `ProtocolAdapter`: UCCI's `ucci` and `ucciok` are translated from `uci` and to `uciok`.
+    fn handshake(self) -> (&'static str, &'static str) {
+        match self {
+            EngineProtocol::Uci | EngineProtocol::Ucci => ("uci", "uciok"),
+            EngineProtocol::Jai => ("jai", "jaiok"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EngineInfo {
+    pub path: String,
+    pub protocol: EngineProtocol,
+    pub name: String,
+    pub author: String,
+    pub options: Vec<UciOption>,
+}
+
+/// What to do when an engine dies without being asked to quit.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RestartPolicy {
+    pub enabled: bool,
+    pub max_restarts: u32,
+}
+
+/// The commands needed to bring a freshly started engine back to where the previous one was:
+/// the handshake, the latest value of every `setoption`, and the last `position`.
+#[derive(Debug, Clone, Default)]
+pub struct CommandHistory {
+    handshake: Option<String>,
+    options: Vec<(String, String)>,
+    position: Option<String>,
+    quit_requested: bool,
+}
+
+impl CommandHistory {
+    pub fn record(&mut self, command: &str) {
+        let command = command.trim();
+        match command.split_whitespace().next() {
+            Some("uci") | Some("ucci") | Some("jai") => self.handshake = Some(command.to_string()),
+            Some("setoption") => {
+                let name = setoption_name(command);
+                match self.options.iter_mut().find(|(n, _)| *n == name) {
+                    Some(entry) => entry.1 = command.to_string(),
+                    None => self.options.push((name, command.to_string())),
+                }
+            }
+            Some("position") => self.position = Some(command.to_string()),
+            Some("quit") => self.quit_requested = true,
+            _ => {}
+        }
+    }
+
+    pub fn quit_requested(&self) -> bool {
+        self.quit_requested
+    }
+
+    pub fn replay(&self) -> Vec<String> {
+        let mut commands: Vec<String> = self.handshake.iter().cloned().collect();
+        commands.extend(self.options.iter().map(|(_, cmd)| cmd.clone()));
+        if self.handshake.is_some() {
+            commands.push("isready".to_string());
+        }
+        commands.extend(self.position.iter().cloned());
+        commands
+    }
+}
+
+// `setoption name Hash value 128` -> `Hash`; UCCI writes `setoption hash 128`
+fn setoption_name(command: &str) -> String {
+    let rest = command.trim_start_matches("setoption").trim();
+    match rest.strip_prefix("name ") {
+        Some(named) => named.split(" value ").next().unwrap_or(named).trim().to_string(),
+        None => rest.split_whitespace().next().unwrap_or_default().to_string(),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum EngineEvent {
+    Stdout(String),
+    Stderr(String),
+    Exited { code: Option<i32>, signal: Option<i32> },
+}
+
+/// Text encoding an engine reads and writes. Many Chinese engines print option names in GBK
+/// or Big5 and expect option values and file paths in the same encoding.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EngineEncoding {
+    /// UTF-8 until the engine prints a line that is not valid UTF-8, GB18030 from then on.
+    #[default]
+    Auto,
+    #[serde(rename = "utf-8", alias = "utf8")]
+    Utf8,
+    Gbk,
+    Gb18030,
+    Big5,
+}
+
+impl FromStr for EngineEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(EngineEncoding::Auto),
+            "utf-8" | "utf8" => Ok(EngineEncoding::Utf8),
+            "gbk" => Ok(EngineEncoding::Gbk),
+            "gb18030" => Ok(EngineEncoding::Gb18030),
+            "big5" => Ok(EngineEncoding::Big5),
+            _ => Err(format!("Unknown encoding '{}' (expected auto, utf-8, gbk, gb18030 or big5)", s)),
+        }
+    }
+}
+
+/// Converts engine output to text and commands to bytes in the engine's encoding.
+/// In auto mode the encoding detected from the output is also used for input.
+#[derive(Debug, Clone)]
+pub struct EngineCodec {
+    encoding: EngineEncoding,
+    detected: Option<&'static Encoding>,
+}
+
+/// Codec shared by the output readers and the writer of one engine, so detection carries over.
+pub type SharedCodec = Arc<Mutex<EngineCodec>>;
+
+impl EngineCodec {
+    pub fn new(encoding: EngineEncoding) -> Self {
+        EngineCodec { encoding, detected: None }
+    }
+
+    pub fn shared(encoding: EngineEncoding) -> SharedCodec {
+        Arc::new(Mutex::new(Self::new(encoding)))
+    }
+
+    /// The encoding currently in use; in auto mode, UTF-8 until something else was detected.
+    pub fn encoding(&self) -> &'static Encoding {
+        match self.encoding {
+            EngineEncoding::Auto => self.detected.unwrap_or(UTF_8),
+            EngineEncoding::Utf8 => UTF_8,
+            EngineEncoding::Gbk => GBK,
+            EngineEncoding::Gb18030 => GB18030,
+            EngineEncoding::Big5 => BIG5,
+        }
+    }
+
+    pub fn decode(&mut self, buf: &[u8]) -> String {
+        if self.encoding == EngineEncoding::Auto && self.detected.is_none() {
+            match std::str::from_utf8(buf) {
+                Ok(text) => return text.to_string(),
+                // GB18030 is a superset of GBK, which is what these engines print in practice
+                Err(_) => self.detected = Some(GB18030),
+            }
+        }
+        let (text, _) = self.encoding().decode_without_bom_handling(buf);
+        text.into_owned()
+    }
+
+    /// Characters the encoding cannot represent come out as HTML numeric character references.
+    pub fn encode(&self, text: &str) -> Vec<u8> {
+        let (bytes, ..) = self.encoding().encode(text);
+        bytes.into_owned()
+    }
+}
+
+/// An engine process driven directly from Rust, without going through the webview.
+/// Output is delivered line by line through `recv`; dropping the process kills and reaps it.
+pub struct EngineProcess {
+    child: Child,
+    stdin: ChildStdin,
+    events: Receiver<EngineEvent>,
+    exited: bool,
+    applied: AppliedResources,
+    transcript: Option<Transcript>,
+    codec: SharedCodec,
+    adapter: ProtocolAdapter,
+}
+
+impl EngineProcess {
+    pub fn spawn(path: &str, args: &[String]) -> Result<Self, String> {
+        Self::spawn_with(path, args, &EngineResources::default(), EngineEncoding::Auto)
+    }
+
+    /// Spawn with environment variables, scheduling settings and a memory cap (see `applied`),
+    /// talking to the engine in `encoding`. Until `set_protocol` is called, the protocol is
+    /// detected from the `uci` handshake as in the GUI (see `ProtocolAdapter::new`).
+    pub fn spawn_with(
+        path: &str,
+        args: &[String],
+        resources: &EngineResources,
+        encoding: EngineEncoding,
+    ) -> Result<Self, String> {
+        resources.validate()?;
+        let engine_path = Path::new(path);
+        if !engine_path.is_file() {
+            return Err(format!("Engine file not found: {}", path));
+        }
+
+        let mut command = Command::new(engine_path);
+        command
+            .args(args)
+            .envs(resources.env.iter().map(|(k, v)| (k, v)))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        if let Some(parent) = engine_path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            command.current_dir(parent);
+        }
+        #[cfg(windows)]
+        {
+            use std::os::windows::process::CommandExt;
+            const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+            command.creation_flags(CREATE_NO_WINDOW);
+        }
+
+        let mut child = command.spawn().map_err(|e| format!("Failed to start {}: {}", path, e))?;
+        let stdin = child.stdin.take().ok_or("Engine stdin unavailable")?;
+        let stdout = child.stdout.take().ok_or("Engine stdout unavailable")?;
+        let stderr = child.stderr.take().ok_or("Engine stderr unavailable")?;
+
+        let applied = resources::apply(child.id(), resources);
+        let codec = EngineCodec::shared(encoding);
+        let (tx, events) = mpsc::channel();
+        spawn_reader(stdout, tx.clone(), codec.clone(), EngineEvent::Stdout);
+        spawn_reader(stderr, tx, codec.clone(), EngineEvent::Stderr);
+
+        Ok(EngineProcess {
+            child,
+            stdin,
+            events,
+            exited: false,
+            applied,
+            transcript: None,
+            codec,
+            adapter: ProtocolAdapter::new(None),
+        })
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// The resource settings the process actually got.
+    pub fn applied(&self) -> &AppliedResources {
+        &self.applied
+    }
+
+    /// Log every line sent and received from now on; `None` stops logging.
+    pub fn set_transcript(&mut self, transcript: Option<Transcript>) {
+        self.transcript = transcript;
+    }
+
+    /// Talk to the engine in `protocol`. Commands and output stay UCI-style on this side;
+    /// see `ProtocolAdapter` for how they are translated.
+    pub fn set_protocol(&mut self, protocol: EngineProtocol) {
+        self.adapter = ProtocolAdapter::new(Some(protocol));
+    }
+
+    /// The protocol the engine is talked to in, once set or detected.
+    pub fn protocol(&self) -> Option<EngineProtocol> {
+        self.adapter.protocol()
+    }
+
+    pub fn send(&mut self, command: &str) -> Result<(), String> {
+        for line in self.adapter.outgoing(command) {
+            if let Some(transcript) = &mut self.transcript {
+                transcript.record(Direction::Sent, &line);
+            }
+            let bytes = self.codec.lock().unwrap().encode(&format!("{}\n", line));
+            self.stdin
+                .write_all(&bytes)
+                .and_then(|_| self.stdin.flush())
+                .map_err(|e| format!("Failed to write to engine: {}", e))?;
+        }
+        Ok(())
+    }
+
+    /// Wait up to `timeout` for the next output event.
+    /// Once both output pipes are closed the process is reaped and `Exited` is returned.
+    pub fn recv(&mut self, timeout: Duration) -> Option<EngineEvent> {
+        if self.exited {
+            return None;
+        }
+        let event = match self.events.recv_timeout(timeout) {
+            Ok(event) => event,
+            Err(RecvTimeoutError::Timeout) => return None,
+            Err(RecvTimeoutError::Disconnected) => {
+                self.exited = true;
+                let status = self.child.wait().ok();
+                EngineEvent::Exited {
+                    code: status.and_then(|s| s.code()),
+                    signal: status.and_then(exit_signal),
+                }
+            }
+        };
+        if let Some(transcript) = &mut self.transcript {
+            match &event {
+                EngineEvent::Stdout(line) => transcript.record(Direction::Stdout, line),
+                EngineEvent::Stderr(line) => transcript.record(Direction::Stderr, line),
+                EngineEvent::Exited { code, signal } => {
+                    transcript.record(Direction::Note, &format!("exited (code {:?}, signal {:?})", code, signal))
+                }
+            }
+        }
+        match event {
+            EngineEvent::Stdout(line) => Some(EngineEvent::Stdout(self.adapter.incoming(&line))),
+            event => Some(event),
+        }
+    }
+
+    /// Read stdout lines until one satisfies `done`, returning every line seen on the way.
+    pub fn read_until<F>(&mut self, timeout: Duration, mut done: F) -> Result<Vec<String>, String>
+    where
+        F: FnMut(&str) -> bool,
+    {
+        let deadline = Instant::now() + timeout;
+        let mut lines = Vec::new();
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(format!("Timed out after {} ms", timeout.as_millis()));
+            }
+            match self.recv(remaining) {
+                Some(EngineEvent::Stdout(line)) => {
+                    let finished = done(&line);
+                    lines.push(line);
+                    if finished {
+                        return Ok(lines);
+                    }
+                }
+                Some(EngineEvent::Stderr(_)) => {}
+                Some(EngineEvent::Exited { code, .. }) => {
+                    return Err(format!("Engine exited unexpectedly (code {:?})", code));
+                }
+                None if self.exited => return Err("Engine is not running".into()),
+                None => {}
+            }
+        }
+    }
+
+    pub fn is_running(&mut self) -> bool {
+        !self.exited && matches!(self.child.try_wait(), Ok(None))
+    }
+
+    /// Ask the engine to quit, give it `grace` to comply, then kill it. The process is always reaped.
+    pub fn shutdown(mut self, grace: Duration) {
+        let _ = self.send("quit");
+        let deadline = Instant::now() + grace;
+        while Instant::now() < deadline {
+            if !matches!(self.child.try_wait(), Ok(None)) {
+                self.exited = true;
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        // Drop does the kill and wait
+    }
+}
+
+impl Drop for EngineProcess {
+    fn drop(&mut self) {
+        if matches!(self.child.try_wait(), Ok(None)) {
+            let _ = self.child.kill();
+        }
+        let _ = self.child.wait();
+    }
+}
+
+fn spawn_reader<R, F>(pipe: R, tx: Sender<EngineEvent>, codec: SharedCodec, wrap: F)
+where
+    R: Read + Send + 'static,
+    F: Fn(String) -> EngineEvent + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut reader = BufReader::new(pipe);
+        let mut buf = Vec::new();
+        loop {
+            buf.clear();
+            match reader.read_until(b'\n', &mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {
+                    while matches!(buf.last(), Some(b'\n') | Some(b'\r')) {
+                        buf.pop();
+                    }
+                    let text = codec.lock().unwrap().decode(&buf);
+                    if tx.send(wrap(text)).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+}
+
+#[cfg(unix)]
+fn exit_signal(status: std::process::ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn exit_signal(_status: std::process::ExitStatus) -> Option<i32> {
+    None
+}
+
+/// Start the engine, find out which protocol it speaks and collect its id and options.
+/// Protocols are tried in turn (UCI, UCCI, JAI), each with its own `timeout`.
+/// The process is shut down before returning, whether or not the handshake succeeded.
+pub fn probe_engine(
+    path: &str,
+    args: &[String],
+    encoding: EngineEncoding,
+    timeout: Duration,
+) -> Result<EngineInfo, String> {
+    let mut engine = EngineProcess::spawn_with(path, args, &EngineResources::default(), encoding)?;
+    let result = detect_protocol(&mut engine, path, timeout);
+    engine.shutdown(Duration::from_millis(500));
+    result
+}
+
+fn detect_protocol(engine: &mut EngineProcess, path: &str, timeout: Duration) -> Result<EngineInfo, String> {
+    let mut last_error = String::new();
+    for protocol in [EngineProtocol::Uci, EngineProtocol::Ucci, EngineProtocol::Jai] {
+        // Through the adapter for this protocol, so UCCI options come back in UCI form
+        engine.set_protocol(protocol);
+        let (command, ok) = protocol.handshake();
+        engine.send(command)?;
+        match engine.read_until(timeout, |line| line.trim() == ok) {
+            // A slow UCI engine answering the UCCI attempt switches the adapter back to UCI
+            Ok(lines) => return Ok(collect_engine_info(path, engine.protocol().unwrap_or(protocol), &lines)),
+            Err(e) if !engine.is_running() => return Err(e),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(format!("No UCI, UCCI or JAI handshake from {}: {}", path, last_error))
+}
+
+fn collect_engine_info(path: &str, protocol: EngineProtocol, lines: &[String]) -> EngineInfo {
+    let mut info = EngineInfo {
+        path: path.to_string(),
+        protocol,
+        name: String::new(),
+        author: String::new(),
+        options: Vec::new(),
+    };
+    for line in lines {
+        match uci::parse_line(line) {
+            Some(UciMessage::Id { field, value }) if field == "name" => info.name = value,
+            Some(UciMessage::Id { field, value }) if field == "author" => info.author = value,
+            Some(UciMessage::Option(option)) => info.options.push(option),
+            _ => {}
+        }
+    }
+    if info.name.is_empty() {
+        info.name = Path::new(path)
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+    }
+    info
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+
+    fn script(name: &str, body: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("jieqibox-probe-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        let script = format!("#!/bin/sh\nwhile read -r line; do\n  case \"$line\" in\n{}\n  esac\ndone\n", body);
+        fs::write(&path, script).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    fn probe(path: &Path) -> EngineInfo {
+        probe_engine(&path.to_string_lossy(), &[], EngineEncoding::Auto, Duration::from_millis(500)).unwrap()
+    }
+
+    #[test]
+    fn probe_reads_ucci_options_in_uci_form() {
+        let path = script(
+            "ucci.sh",
+            "    ucci) echo 'id name Eleeye'; echo 'option usemillisec type check default false'; \
+             echo 'option hashsize type spin min 0 max 1024 default 16'; echo ucciok ;;",
+        );
+        let info = probe(&path);
+        assert_eq!(info.protocol, EngineProtocol::Ucci);
+        assert_eq!(info.name, "Eleeye");
+        let names: Vec<&str> = info.options.iter().map(|o| o.name.as_str()).collect();
+        assert_eq!(names, ["usemillisec", "hashsize"]);
+    }
+
+    #[test]
+    fn probe_detects_uci_and_jai() {
+        let uci = script("uci.sh", "    uci) echo 'id name Pikafish'; echo 'option name Hash type spin default 16 min 1 max 256'; echo uciok ;;");
+        let info = probe(&uci);
+        assert_eq!(info.protocol, EngineProtocol::Uci);
+        assert_eq!(info.options.len(), 1);
+        let jai = script("jai.sh", "    jai) echo 'id name Jai'; echo jaiok ;;");
+        assert_eq!(probe(&jai).protocol, EngineProtocol::Jai);
+    }
+}
@@ -0,0 +1,598 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+pub const START_FEN: &str =
+    "xxxxkxxxx/9/1x5x1/x1x1x1x1x/9/9/X1X1X1X1X/1X5X1/9/XXXXKXXXX w A2B2N2R2C2P5a2b2n2r2c2p5 - 0 1";
+
+// Pool order used when writing FEN, same as the opening book normalisation
+const POOL_ORDER: &str = "RNBACPrnbacp";
+
+type Board = [[Option<char>; 9]; 10];
+type Square = (usize, usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    Red,
+    Black,
+}
+
+impl Side {
+    pub fn opponent(self) -> Side {
+        match self {
+            Side::Red => Side::Black,
+            Side::Black => Side::Red,
+        }
+    }
+
+    pub fn of_piece(piece: char) -> Side {
+        if piece.is_ascii_uppercase() {
+            Side::Red
+        } else {
+            Side::Black
+        }
+    }
+
+    fn fen_char(self) -> &'static str {
+        match self {
+            Side::Red => "w",
+            Side::Black => "b",
+        }
+    }
+
+    fn dark_piece(self) -> char {
+        match self {
+            Side::Red => 'X',
+            Side::Black => 'x',
+        }
+    }
+}
+
+impl fmt::Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Side::Red => write!(f, "red"),
+            Side::Black => write!(f, "black"),
+        }
+    }
+}
+
+/// Small deterministic PRNG (SplitMix64) so a match can be replayed from its seed.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    pub fn below(&mut self, bound: u32) -> u32 {
+        (self.next_u64() % bound.max(1) as u64) as u32
+    }
+}
+
+/// Result of applying one move: the notation string (base move plus the revealed and captured
+/// dark piece letters, as the frontend records it) and what was captured, if anything.
+#[derive(Debug, Clone)]
+pub struct AppliedMove {
+    pub notation: String,
+    pub captured: Option<char>,
+}
+
+/// Full ("god view") Jieqi position. Row 0 is rank 9, Black's back rank.
+#[derive(Debug, Clone)]
+pub struct Position {
+    board: Board,
+    side_to_move: Side,
+    hidden: BTreeMap<char, u32>,
+    // Identities dealt to dark pieces in advance by `deal_hidden`, by square. Dark pieces never
+    // leave their square without being revealed, so the square identifies the piece.
+    dealt: BTreeMap<Square, char>,
+    // Dark pieces captured so far, with the side that captured them
+    captured_dark: Vec<(char, Side)>,
+    halfmove: u32,
+    fullmove: u32,
+}
+
+impl Position {
+    /// Parse a FEN in either the new (`board side pool captured half full`) or the
+    /// legacy (`board pool side - - half full`) format.
+    pub fn from_fen(fen: &str) -> Result<Self, String> {
+        let parts: Vec<&str> = fen.split_whitespace().collect();
+        if parts.len() < 2 {
+            return Err(format!("Invalid FEN: {}", fen));
+        }
+        let new_format = parts[1] == "w" || parts[1] == "b";
+        let (side, pool, captured, counters) = if new_format {
+            (parts[1], parts.get(2), parts.get(3), parts.get(4..).unwrap_or(&[]))
+        } else {
+            (
+                parts.get(2).copied().unwrap_or("w"),
+                parts.get(1),
+                None,
+                parts.get(5..).unwrap_or(&[]),
+            )
+        };
+
+        let mut board = [[None; 9]; 10];
+        let rows: Vec<&str> = parts[0].split('/').collect();
+        if rows.len() != 10 {
+            return Err(format!("FEN board must have 10 rows: {}", fen));
+        }
+        for (r, row) in rows.iter().enumerate() {
+            let mut c = 0usize;
+            for ch in row.chars() {
+                if let Some(empty) = ch.to_digit(10) {
+                    c += empty as usize;
+                } else if "RNBAKCPXrnbakcpx".contains(ch) {
+                    if c >= 9 {
+                        return Err(format!("FEN row {} is too long: {}", r, fen));
+                    }
+                    board[r][c] = Some(ch);
+                    c += 1;
+                } else {
+                    return Err(format!("Unexpected character '{}' in FEN: {}", ch, fen));
+                }
+            }
+            if c != 9 {
+                return Err(format!("FEN row {} does not have 9 files: {}", r, fen));
+            }
+        }
+
+        let captured_dark = parse_pool(captured.copied().unwrap_or("-"))
+            .into_iter()
+            .flat_map(|(piece, count)| {
+                let capturer = Side::of_piece(piece).opponent();
+                std::iter::repeat_n((piece, capturer), count as usize)
+            })
+            .collect();
+
+        Ok(Position {
+            board,
+            side_to_move: if side == "b" { Side::Black } else { Side::Red },
+            hidden: parse_pool(pool.copied().unwrap_or("-")),
+            dealt: BTreeMap::new(),
+            captured_dark,
+            halfmove: counters.first().and_then(|v| v.parse().ok()).unwrap_or(0),
+            fullmove: counters.get(1).and_then(|v| v.parse().ok()).unwrap_or(1),
+        })
+    }
+
+    pub fn side_to_move(&self) -> Side {
+        self.side_to_move
+    }
+
+    pub fn halfmove_clock(&self) -> u32 {
+        self.halfmove
+    }
+
+    /// FEN with complete knowledge: the true hidden pool and every captured dark piece.
+    pub fn to_fen(&self) -> String {
+        let captured: Vec<char> = self.captured_dark.iter().map(|(p, _)| *p).collect();
+        self.format_fen(&self.hidden, &captured)
+    }
+
+    /// FEN as seen by `viewer`: it knows which dark pieces it captured, but its own dark pieces
+    /// taken by the opponent are still unknown to it and stay in its hidden pool.
+    pub fn fen_for(&self, viewer: Side) -> String {
+        let mut hidden = self.hidden.clone();
+        let mut captured = Vec::new();
+        for &(piece, capturer) in &self.captured_dark {
+            if capturer == viewer {
+                captured.push(piece);
+            } else {
+                *hidden.entry(piece).or_insert(0) += 1;
+            }
+        }
+        self.format_fen(&hidden, &captured)
+    }
+
+    /// Key used for repetition detection: board, side to move and hidden pool.
+    pub fn repetition_key(&self) -> String {
+        let fen = self.to_fen();
+        fen.split_whitespace().take(3).collect::<Vec<_>>().join(" ")
+    }
+
+    pub fn has_king(&self, side: Side) -> bool {
+        let king = if side == Side::Red { 'K' } else { 'k' };
+        self.board.iter().flatten().any(|&sq| sq == Some(king))
+    }
+
+    /// Decide now which hidden piece every dark piece on the board is, instead of drawing at
+    /// random when it is revealed. Two games dealt from the same seed reveal the same piece on
+    /// each square whatever the order of the moves.
+    pub fn deal_hidden(&mut self, rng: &mut Rng) {
+        self.dealt.clear();
+        for side in [Side::Red, Side::Black] {
+            let mut pool: Vec<char> = self
+                .hidden
+                .iter()
+                .filter(|(&p, _)| Side::of_piece(p) == side)
+                .flat_map(|(&p, &n)| std::iter::repeat_n(p, n as usize))
+                .collect();
+            // Fisher-Yates, so every arrangement is equally likely
+            for i in (1..pool.len()).rev() {
+                pool.swap(i, rng.below(i as u32 + 1) as usize);
+            }
+            let squares = (0..10)
+                .flat_map(|r| (0..9).map(move |c| (r, c)))
+                .filter(|&(r, c)| self.board[r][c] == Some(side.dark_piece()));
+            for (square, piece) in squares.zip(pool) {
+                self.dealt.insert(square, piece);
+            }
+        }
+    }
+
+    /// Check that `uci` is a legal move for the side to move: the piece moves the way it may,
+    /// and the mover's king is not left in check (kings facing each other count as check).
+    /// Dark pieces move as the piece whose starting square they stand on.
+    ///
+    /// As usual in Jieqi, revealed advisors and elephants are not confined to the palace or to
+    /// their own side of the river.
+    pub fn check_move(&self, uci: &str) -> Result<(), String> {
+        let (from, to) = parse_squares(uci).ok_or_else(|| format!("Malformed move: {}", uci))?;
+        let mover = self.side_to_move;
+        let piece = self.board[from.0][from.1]
+            .filter(|&p| Side::of_piece(p) == mover)
+            .ok_or_else(|| format!("No {} piece on the from-square of {}", mover, uci))?;
+        if self.board[to.0][to.1].is_some_and(|t| Side::of_piece(t) == mover) {
+            return Err(format!("Move {} captures its own piece", uci));
+        }
+        let kind = movement_kind(piece, from)
+            .ok_or_else(|| format!("The dark piece on the from-square of {} is not on a starting square", uci))?;
+        if !can_reach(&self.board, kind, mover, from, to) {
+            return Err(format!("{} cannot move like {}", piece_name(kind), uci));
+        }
+        let mut after = self.board;
+        after[from.0][from.1] = None;
+        after[to.0][to.1] = Some(piece);
+        if in_check(&after, mover) {
+            return Err(format!("Move {} leaves the {} king in check", uci, mover));
+        }
+        Ok(())
+    }
+
+    /// Whether the king of `side` is attacked.
+    pub fn in_check(&self, side: Side) -> bool {
+        in_check(&self.board, side)
+    }
+
+    /// Apply a legal move from the side to move. Dark pieces that move or get captured are
+    /// revealed: as dealt by `deal_hidden`, or else by drawing from the owner's hidden pool
+    /// with `rng`.
+    pub fn apply_move(&mut self, uci: &str, rng: &mut Rng) -> Result<AppliedMove, String> {
+        self.check_move(uci)?;
+        let (from, to) = parse_squares(uci).ok_or_else(|| format!("Malformed move: {}", uci))?;
+        let mover = self.side_to_move;
+        let piece = self.board[from.0][from.1].ok_or_else(|| format!("No piece on the from-square of {}", uci))?;
+        let target = self.board[to.0][to.1];
+
+        let mut notation = uci[..4].to_string();
+        let moved = if piece == mover.dark_piece() {
+            let revealed = self.reveal(from, mover, rng)?;
+            notation.push(revealed);
+            revealed
+        } else {
+            piece
+        };
+
+        let captured = match target {
+            Some(t) if t == mover.opponent().dark_piece() => {
+                let revealed = self.reveal(to, mover.opponent(), rng)?;
+                self.captured_dark.push((revealed, mover));
+                notation.push(revealed);
+                Some(revealed)
+            }
+            other => other,
+        };
+
+        self.board[from.0][from.1] = None;
+        self.board[to.0][to.1] = Some(moved);
+        self.halfmove = if captured.is_some() || piece != moved { 0 } else { self.halfmove + 1 };
+        if mover == Side::Black {
+            self.fullmove += 1;
+        }
+        self.side_to_move = mover.opponent();
+
+        Ok(AppliedMove { notation, captured })
+    }
+
+    fn reveal(&mut self, square: Square, side: Side, rng: &mut Rng) -> Result<char, String> {
+        match self.dealt.remove(&square) {
+            Some(piece) => {
+                let count = self.hidden.get_mut(&piece).filter(|n| **n > 0);
+                let count = count.ok_or_else(|| format!("No hidden {} left to reveal", piece))?;
+                *count -= 1;
+                Ok(piece)
+            }
+            None => self.draw_hidden(side, rng),
+        }
+    }
+
+    fn draw_hidden(&mut self, side: Side, rng: &mut Rng) -> Result<char, String> {
+        let candidates: Vec<(char, u32)> = self
+            .hidden
+            .iter()
+            .filter(|(&p, &n)| n > 0 && Side::of_piece(p) == side)
+            .map(|(&p, &n)| (p, n))
+            .collect();
+        let total: u32 = candidates.iter().map(|(_, n)| n).sum();
+        if total == 0 {
+            return Err(format!("No hidden {} pieces left to reveal", side));
+        }
+        let mut pick = rng.below(total);
+        for (piece, count) in candidates {
+            if pick < count {
+                *self.hidden.get_mut(&piece).unwrap() -= 1;
+                return Ok(piece);
+            }
+            pick -= count;
+        }
+        unreachable!("pick is always below the pool total")
+    }
+
+    fn format_fen(&self, hidden: &BTreeMap<char, u32>, captured: &[char]) -> String {
+        let mut rows = Vec::with_capacity(10);
+        for row in &self.board {
+            let mut s = String::new();
+            let mut empty = 0;
+            for sq in row {
+                match sq {
+                    Some(p) => {
+                        if empty > 0 {
+                            s.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        s.push(*p);
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                s.push_str(&empty.to_string());
+            }
+            rows.push(s);
+        }
+
+        let mut captured_pool = BTreeMap::new();
+        for &p in captured {
+            *captured_pool.entry(p).or_insert(0) += 1;
+        }
+
+        format!(
+            "{} {} {} {} {} {}",
+            rows.join("/"),
+            self.side_to_move.fen_char(),
+            format_pool(hidden),
+            format_pool(&captured_pool),
+            self.halfmove,
+            self.fullmove
+        )
+    }
+}
+
+// The piece letter (uppercase) whose moves `piece` on `square` follows. A dark piece moves as the
+// piece that starts on its square; `None` for a dark piece anywhere else.
+fn movement_kind(piece: char, square: Square) -> Option<char> {
+    if !piece.eq_ignore_ascii_case(&'x') {
+        return Some(piece.to_ascii_uppercase());
+    }
+    // Rows as seen from the dark piece's own side: 0 is its back rank
+    let (row, col) = square;
+    let own_row = if piece == 'X' { 9 - row } else { row };
+    match (own_row, col) {
+        (0, 0 | 8) => Some('R'),
+        (0, 1 | 7) => Some('N'),
+        (0, 2 | 6) => Some('B'),
+        (0, 3 | 5) => Some('A'),
+        (2, 1 | 7) => Some('C'),
+        (3, 0 | 2 | 4 | 6 | 8) => Some('P'),
+        _ => None,
+    }
+}
+
+fn piece_name(kind: char) -> &'static str {
+    match kind {
+        'R' => "A rook",
+        'N' => "A horse",
+        'B' => "An elephant",
+        'A' => "An advisor",
+        'K' => "A king",
+        'C' => "A cannon",
+        _ => "A pawn",
+    }
+}
+
+// Pieces strictly between two squares on the same rank or file
+fn pieces_between(board: &Board, from: Square, to: Square) -> usize {
+    let (dr, dc) = ((to.0 as i32 - from.0 as i32).signum(), (to.1 as i32 - from.1 as i32).signum());
+    let (mut r, mut c) = (from.0 as i32 + dr, from.1 as i32 + dc);
+    let mut count = 0;
+    while (r, c) != (to.0 as i32, to.1 as i32) {
+        if board[r as usize][c as usize].is_some() {
+            count += 1;
+        }
+        r += dr;
+        c += dc;
+    }
+    count
+}
+
+// Whether a piece moving as `kind` for `side` can go from `from` to `to`, capturing whatever is
+// there. Ownership of the target square is checked by the caller.
+fn can_reach(board: &Board, kind: char, side: Side, from: Square, to: Square) -> bool {
+    let dr = to.0 as i32 - from.0 as i32;
+    let dc = to.1 as i32 - from.1 as i32;
+    if (dr, dc) == (0, 0) {
+        return false;
+    }
+    match kind {
+        'R' => (dr == 0 || dc == 0) && pieces_between(board, from, to) == 0,
+        'C' => {
+            let screens = if board[to.0][to.1].is_some() { 1 } else { 0 };
+            (dr == 0 || dc == 0) && pieces_between(board, from, to) == screens
+        }
+        'N' => {
+            let leg = match (dr.abs(), dc.abs()) {
+                (2, 1) => ((from.0 as i32 + dr / 2) as usize, from.1),
+                (1, 2) => (from.0, (from.1 as i32 + dc / 2) as usize),
+                _ => return false,
+            };
+            board[leg.0][leg.1].is_none()
+        }
+        'B' => {
+            let eye = ((from.0 as i32 + dr / 2) as usize, (from.1 as i32 + dc / 2) as usize);
+            dr.abs() == 2 && dc.abs() == 2 && board[eye.0][eye.1].is_none()
+        }
+        'A' => dr.abs() == 1 && dc.abs() == 1,
+        'K' => {
+            let palace_rows = if side == Side::Red { 7..=9 } else { 0..=2 };
+            dr.abs() + dc.abs() == 1 && palace_rows.contains(&to.0) && (3..=5).contains(&to.1)
+        }
+        'P' => {
+            let forward = if side == Side::Red { -1 } else { 1 };
+            let crossed = if side == Side::Red { from.0 <= 4 } else { from.0 >= 5 };
+            (dr == forward && dc == 0) || (crossed && dr == 0 && dc.abs() == 1)
+        }
+        _ => false,
+    }
+}
+
+fn in_check(board: &Board, side: Side) -> bool {
+    let king = if side == Side::Red { 'K' } else { 'k' };
+    let enemy_king = if side == Side::Red { 'k' } else { 'K' };
+    let squares = || (0..10).flat_map(|r| (0..9).map(move |c| (r, c)));
+    // Without a king there is nothing to check; a captured king ends the game elsewhere
+    let Some(king_square) = squares().find(|&(r, c)| board[r][c] == Some(king)) else { return false };
+    squares().any(|(r, c)| match board[r][c] {
+        // Kings may not face each other on an open file
+        Some(p) if p == enemy_king => c == king_square.1 && pieces_between(board, (r, c), king_square) == 0,
+        Some(p) if Side::of_piece(p) != side => {
+            movement_kind(p, (r, c)).is_some_and(|kind| can_reach(board, kind, side.opponent(), (r, c), king_square))
+        }
+        _ => false,
+    })
+}
+
+fn parse_squares(uci: &str) -> Option<(Square, Square)> {
+    let b = uci.as_bytes();
+    if b.len() < 4 {
+        return None;
+    }
+    let square = |file: u8, rank: u8| -> Option<(usize, usize)> {
+        if !(b'a'..=b'i').contains(&file) || !rank.is_ascii_digit() {
+            return None;
+        }
+        Some((9 - (rank - b'0') as usize, (file - b'a') as usize))
+    };
+    Some((square(b[0], b[1])?, square(b[2], b[3])?))
+}
+
+fn parse_pool(pool: &str) -> BTreeMap<char, u32> {
+    let mut counts = BTreeMap::new();
+    let chars: Vec<char> = pool.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i].is_ascii_alphabetic() {
+            let mut j = i + 1;
+            while j < chars.len() && chars[j].is_ascii_digit() {
+                j += 1;
+            }
+            let count = chars[i + 1..j].iter().collect::<String>().parse().unwrap_or(1);
+            *counts.entry(chars[i]).or_insert(0) += count;
+            i = j;
+        } else {
+            i += 1;
+        }
+    }
+    counts
+}
+
+fn format_pool(pool: &BTreeMap<char, u32>) -> String {
+    let mut result = String::new();
+    for piece in POOL_ORDER.chars() {
+        match pool.get(&piece) {
+            Some(&count) if count > 0 => {
+                result.push(piece);
+                result.push_str(&count.to_string());
+            }
+            _ => {}
+        }
+    }
+    if result.is_empty() {
+        "-".to_string()
+    } else {
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(fen: &str, uci: &str) -> Result<(), String> {
+        Position::from_fen(fen).unwrap().check_move(uci)
+    }
+
+    #[test]
+    fn pieces_move_by_their_rules() {
+        // Dark pieces move as the piece on their starting square
+        assert!(check(START_FEN, "h2e2").is_ok());
+        assert!(check(START_FEN, "b0c2").is_ok());
+        assert!(check(START_FEN, "c0e2").is_ok());
+        assert!(check(START_FEN, "a3a4").is_ok());
+        assert!(check(START_FEN, "c0c1").is_err());
+        // Rooks do not jump, pawns do not go sideways before the river or backwards
+        assert!(check(START_FEN, "a0a5").is_err());
+        assert!(check(START_FEN, "a3b3").is_err());
+        assert!(check(START_FEN, "a3a2").is_err());
+        // A cannon captures over exactly one screen
+        assert!(check(START_FEN, "h2h9").is_ok());
+        assert!(check(START_FEN, "h2h7").is_err());
+    }
+
+    #[test]
+    fn kings_stay_in_the_palace() {
+        let fen = "3k5/9/9/9/9/9/9/4K4/9/9 w - - 0 1";
+        assert!(check(fen, "e2e1").is_ok());
+        assert!(check(fen, "e2e3").is_err());
+        assert!(check(fen, "e2e0").is_err());
+    }
+
+    #[test]
+    fn moves_may_not_leave_the_king_in_check() {
+        // The red rook is pinned against its king
+        let pinned = "4r4/3k5/9/9/9/9/9/9/4R4/4K4 w - - 0 1";
+        assert!(check(pinned, "e1e5").is_ok());
+        assert!(check(pinned, "e1d1").unwrap_err().contains("check"));
+        // Moving the only piece between the kings lets them face each other
+        let facing = "4k4/9/9/9/9/9/9/9/4R4/4K4 w - - 0 1";
+        assert!(check(facing, "e1d1").unwrap_err().contains("check"));
+        assert!(Position::from_fen("4k4/9/9/9/9/9/9/9/9/4K4 w - - 0 1").unwrap().in_check(Side::Red));
+    }
+
+    #[test]
+    fn dealt_pieces_do_not_depend_on_move_order() {
+        let play = |moves: &[&str]| {
+            let mut position = Position::from_fen(START_FEN).unwrap();
+            let mut rng = Rng::new(7);
+            position.deal_hidden(&mut rng);
+            let mut revealed = BTreeMap::new();
+            for uci in moves {
+                let applied = position.apply_move(uci, &mut rng).unwrap();
+                revealed.insert(uci[2..4].to_string(), applied.notation[4..].to_string());
+            }
+            revealed
+        };
+        assert_eq!(play(&["a3a4", "a6a5", "c3c4"]), play(&["c3c4", "a6a5", "a3a4"]));
+    }
+}
@@ -4,29 +4,204 @@
 
 use base64::Engine;
 use clipboard::{ClipboardContext, ClipboardProvider};
-use encoding_rs::GBK;
 use std::fs;
 #[cfg(target_os = "android")]
 use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
 use tauri::{AppHandle, Emitter, Manager, State};
 use tauri::async_runtime;
-use tauri_plugin_shell::process::{CommandChild, CommandEvent};
+use tauri_plugin_shell::process::{CommandChild, CommandEvent, TerminatedPayload};
 use tauri_plugin_shell::ShellExt;
 
 // --- THƯ VIỆN AUTO ZIGA ---
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use screenshots::Screen;
 use image::{GenericImageView, DynamicImage};
 use enigo::{Enigo, Mouse, Button, Direction, Coordinate, Settings};
 
-mod opening_book;
-use opening_book::{AddEntryRequest, JieqiOpeningBook, MoveData, OpeningBookStats};
+pub mod opening_book;
+use opening_book::{AddEntryRequest, ImportMode, ImportReport, JieqiOpeningBook, MergeOptions, MergeReport, MoveData, OpeningBookEntry, OpeningBookStats};
+pub mod uci;
+use uci::{LineBuffer, UciMessage};
+pub mod engine;
+use engine::{CommandHistory, EngineCodec, EngineEncoding, EngineInfo, EngineProtocol, RestartPolicy, SharedCodec};
+pub mod jieqi;
+pub mod notation;
+pub mod match_runner;
+use match_runner::{MatchConfig, MatchSummary};
+pub mod stats;
+use stats::{MatchStatistics, Pentanomial, SprtConfig, WdlCounts};
+pub mod clock;
+use clock::{ClockSnapshot, GameClock, TimeControl};
+pub mod analysis_cache;
+use analysis_cache::{AnalysisCache, CachedAnalysis, PruneCriteria, StoreAnalysisRequest};
+pub mod analysis;
+pub mod batch;
+use batch::{BatchConfig, BatchSummary};
+pub mod review;
+use review::{ReviewConfig, ReviewOutput};
+pub mod resources;
+use resources::{AppliedResources, EngineResources};
+pub mod transcript;
+use transcript::{Direction, SharedTranscript, Transcript};
+pub mod protocol;
+use protocol::ProtocolAdapter;
+pub mod remote;
+use remote::{EngineServer, EngineServerConfig, RemoteEndpoint, RemoteEngine, RemoteEvent};
+pub mod profiles;
+use profiles::{EngineOptionSetting, EngineProfile, EngineProfileInput, EngineProfileStatus, EngineProfileStore};
+pub mod networks;
+use networks::{Network, NetworkEntry, NetworkManager};
+pub mod coalesce;
+use coalesce::{Flush, OutputBatching, OutputCoalescer, OutputLine};
+pub mod discovery;
+use discovery::{DiscoveredEngine, EngineSource};
+pub mod book_builder;
+use book_builder::{BookBuildOptions, BookBuildReport};
 
 // -------------------------------------------------------------
-type EngineProcess = Arc<Mutex<Option<CommandChild>>>;
+// Running engine processes, keyed by the engine instance ID chosen by the caller
+type EngineRegistry = Arc<Mutex<HashMap<String, EngineSession>>>;
+
+// Number of trailing output lines reported when an engine exits
+const EXIT_TAIL_LINES: usize = 50;
+
+struct EngineSession {
+    child: EngineHandle,
+    path: String,
+    args: Vec<String>,
+    // Set for engines reached through an engine server; restarts reconnect to it
+    remote: Option<RemoteEndpoint>,
+    // The network of the engine's profile, sent right after every handshake
+    eval_file: Option<EngineOptionSetting>,
+    restart_policy: RestartPolicy,
+    restarts: u32,
+    history: CommandHistory,
+    resources: EngineResources,
+    io: EngineIo,
+}
+
+// A local engine process or a connection to one on an engine server, driven the same way
+enum EngineHandle {
+    Local(CommandChild),
+    Remote(RemoteEngine),
+}
+
+impl EngineHandle {
+    // Identifies this process or connection, so exits and timers can tell whether the session has moved on
+    fn id(&self) -> u64 {
+        match self {
+            EngineHandle::Local(child) => child.pid() as u64,
+            EngineHandle::Remote(engine) => (1 << 32) | engine.id(),
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), String> {
+        match self {
+            EngineHandle::Local(child) => child.write(bytes).map_err(|e| e.to_string()),
+            EngineHandle::Remote(engine) => engine.write(bytes),
+        }
+    }
+
+    fn kill(self) {
+        match self {
+            EngineHandle::Local(child) => { child.kill().ok(); }
+            EngineHandle::Remote(engine) => engine.kill(),
+        }
+    }
+}
+
+// Shared by a session's output reader and its command writer, and kept across restarts
+#[derive(Clone)]
+struct EngineIo {
+    transcript: SharedTranscript,
+    codec: SharedCodec,
+    adapter: Arc<Mutex<ProtocolAdapter>>,
+    batching: OutputBatching,
+}
+
+// Output of one engine process waiting to be emitted. The reader task pushes lines and the
+// flusher thread sends batches as they come due; `None` once the reader has finished.
+struct OutputQueue {
+    coalescer: Mutex<Option<OutputCoalescer>>,
+    wake: Condvar,
+}
+
+#[derive(Clone, serde::Serialize)]
+struct EngineOutputPayload {
+    engine_id: String,
+    data: String,
+    // "stdout" or "stderr"; only stdout lines are parsed as protocol messages
+    stream: &'static str,
+}
+
+// Lines sent together, each with its parsed message when it has one
+#[derive(Clone, serde::Serialize)]
+struct EngineOutputBatchPayload {
+    engine_id: String,
+    lines: Vec<BatchedOutputLine>,
+}
+
+#[derive(Clone, serde::Serialize)]
+struct BatchedOutputLine {
+    data: String,
+    stream: &'static str,
+    message: Option<UciMessage>,
+}
+
+#[derive(Clone, serde::Serialize)]
+struct EngineMessagePayload {
+    engine_id: String,
+    message: UciMessage,
+}
+
+#[derive(Clone, serde::Serialize)]
+struct EngineExitedPayload {
+    engine_id: String,
+    code: Option<i32>,
+    signal: Option<i32>,
+    // False when the exit was requested through `quit` or `kill_engine`
+    crashed: bool,
+    restarting: bool,
+    last_lines: Vec<String>,
+}
+
+#[derive(Default)]
+struct MatchState {
+    running: AtomicBool,
+    stop: Arc<AtomicBool>,
+}
+
+#[derive(Default)]
+struct BatchState {
+    running: AtomicBool,
+    stop: Arc<AtomicBool>,
+}
+
+#[derive(Default)]
+struct ReviewState {
+    running: AtomicBool,
+    stop: Arc<AtomicBool>,
+}
+
+// The engine server exposing a local engine over TCP, if one is running
+#[derive(Default)]
+struct EngineServerState {
+    server: Mutex<Option<EngineServer>>,
+}
+
+// The clock for the game in progress. `generation` is bumped on every start and stop so the ticker
+// thread of a previous clock notices it is obsolete and exits.
+#[derive(Default)]
+struct ClockState {
+    clock: Arc<Mutex<Option<GameClock>>>,
+    generation: Arc<AtomicU64>,
+}
+
+const CLOCK_TICK_MS: u64 = 100;
 
 struct TemplateState {
     templates: Mutex<HashMap<String, DynamicImage>>,
@@ -207,6 +382,26 @@ fn get_opening_book_db_path(app: &AppHandle) -> Result<String, String> {
     else { Ok("jieqi_openings.jb".to_string()) }
 }
 
+// The analysis cache lives next to the opening book
+fn get_analysis_cache_db_path(app: &AppHandle) -> Result<String, String> {
+    if cfg!(target_os = "android") { Ok(format!("/data/data/{}/files/jieqi_analysis.db", app.config().identifier)) }
+    else { Ok("jieqi_analysis.db".to_string()) }
+}
+fn get_engine_profiles_db_path(app: &AppHandle) -> Result<String, String> {
+    if cfg!(target_os = "android") { Ok(format!("/data/data/{}/files/jieqi_engines.db", app.config().identifier)) }
+    else { Ok("jieqi_engines.db".to_string()) }
+}
+// Imported network files, stored under their content hash (see NetworkManager)
+fn get_networks_dir(app: &AppHandle) -> Result<PathBuf, String> {
+    if cfg!(target_os = "android") { Ok(PathBuf::from(format!("/data/data/{}/files/networks", app.config().identifier))) }
+    else { Ok(app.path().app_data_dir().map_err(|e| e.to_string())?.join("networks")) }
+}
+// Where users drop engines to have them discovered; on Android this is where selected engines are copied
+fn get_user_engines_dir(app: &AppHandle) -> Result<PathBuf, String> {
+    if cfg!(target_os = "android") { Ok(PathBuf::from(format!("/data/data/{}/files/engines", app.config().identifier))) }
+    else { Ok(app.path().app_data_dir().map_err(|e| e.to_string())?.join("engines")) }
+}
+
 #[tauri::command]
 async fn load_config(app: AppHandle) -> Result<String, String> {
     let path_str = get_config_file_path(&app)?;
@@ -286,44 +481,547 @@ fn copy_file_to_internal_storage(source_path_str: &str, app_handle: &AppHandle)
     Ok(dest_path_str)
 }
 
-#[tauri::command]
-async fn kill_engine(process_state: State<'_, EngineProcess>) -> Result<(), String> {
-    if let Some(child) = process_state.lock().unwrap().take() { child.kill().ok(); }
-    Ok(())
+// Emit one complete output line: always as raw text, and additionally as a typed message when a stdout line parses
+fn emit_engine_line(app: &AppHandle, engine_id: &str, text: String, direction: Direction) {
+    let stream = if direction == Direction::Stdout { "stdout" } else { "stderr" };
+    if direction == Direction::Stdout {
+        if let Some(message) = uci::parse_line(&text) {
+            let _ = app.emit("engine-message", EngineMessagePayload { engine_id: engine_id.to_string(), message });
+        }
+    }
+    let _ = app.emit("engine-output", EngineOutputPayload { engine_id: engine_id.to_string(), data: text, stream });
 }
 
-#[tauri::command]
-async fn spawn_engine(path: String, args: Vec<String>, app: AppHandle, process_state: State<'_, EngineProcess>) -> Result<(), String> {
-    kill_engine(process_state.clone()).await.ok();
-    let parent = Path::new(&path).parent().ok_or("No parent dir")?.to_str().ok_or("Invalid path")?;
-    let (mut rx, child) = app.shell().command(&path).args(args).current_dir(parent).spawn().map_err(|e| e.to_string())?;
-    *process_state.lock().unwrap() = Some(child);
+// Emit coalesced lines as a single `engine-output-batch` event
+fn emit_engine_batch(app: &AppHandle, engine_id: &str, batch: Vec<OutputLine>) {
+    if batch.is_empty() { return; }
+    let lines = batch.into_iter().map(|line| {
+        let stdout = line.direction == Direction::Stdout;
+        let message = if stdout { uci::parse_line(&line.text) } else { None };
+        BatchedOutputLine { data: line.text, stream: if stdout { "stdout" } else { "stderr" }, message }
+    }).collect();
+    let _ = app.emit("engine-output-batch", EngineOutputBatchPayload { engine_id: engine_id.to_string(), lines });
+}
+
+fn emit_flush(app: &AppHandle, engine_id: &str, flush: Flush) {
+    emit_engine_batch(app, engine_id, flush.batch);
+    if let Some(line) = flush.immediate { emit_engine_line(app, engine_id, line.text, line.direction); }
+}
+
+// Send pending batches once their flush interval has passed, until the reader closes the queue
+fn spawn_output_flusher(app: &AppHandle, engine_id: &str, queue: Arc<OutputQueue>) {
+    let app = app.clone();
+    let engine_id = engine_id.to_string();
+    std::thread::spawn(move || {
+        let mut coalescer = queue.coalescer.lock().unwrap();
+        loop {
+            let deadline = match coalescer.as_ref() { Some(c) => c.deadline(), None => break };
+            let now = std::time::Instant::now();
+            match deadline {
+                None => coalescer = queue.wake.wait(coalescer).unwrap(),
+                Some(deadline) if deadline > now => coalescer = queue.wake.wait_timeout(coalescer, deadline - now).unwrap().0,
+                Some(_) => if let Some(c) = coalescer.as_mut() { emit_engine_batch(&app, &engine_id, c.take()); },
+            }
+        }
+    });
+}
+
+// Start the engine locally, or connect to it when `remote` is set. Remote output is fed through the
+// same channel a local process uses, so both end up in the same reader below.
+fn start_engine_process(app: &AppHandle, engine_id: &str, path: &str, args: &[String], resources: &EngineResources, remote: Option<&RemoteEndpoint>, io: EngineIo) -> Result<(EngineHandle, AppliedResources), String> {
+    let (mut rx, child, applied) = match remote {
+        None => {
+            resources.validate()?;
+            let parent = Path::new(path).parent().ok_or("No parent dir")?.to_str().ok_or("Invalid path")?;
+            let command = app.shell().command(path).args(args).envs(resources.env.iter().map(|(k, v)| (k, v))).current_dir(parent);
+            let (rx, child) = command.spawn().map_err(|e| e.to_string())?;
+            let applied = resources::apply(child.pid(), resources);
+            (rx, EngineHandle::Local(child), applied)
+        }
+        Some(endpoint) => {
+            let (tx, rx) = async_runtime::channel(64);
+            let engine = RemoteEngine::connect(endpoint, move |event| {
+                let event = match event {
+                    RemoteEvent::Stdout(line) => CommandEvent::Stdout(line),
+                    RemoteEvent::Stderr(line) => CommandEvent::Stderr(line),
+                    RemoteEvent::Exited { code, signal } => CommandEvent::Terminated(TerminatedPayload { code, signal }),
+                };
+                tx.blocking_send(event).is_ok()
+            })?;
+            // Process settings belong to the server that runs the engine
+            let mut applied = AppliedResources::default();
+            if *resources != EngineResources::default() {
+                applied.warnings.push(format!("resources are set by the engine server at {}", endpoint.address));
+            }
+            (rx, EngineHandle::Remote(engine), applied)
+        }
+    };
+    let instance = child.id();
+    let queue = Arc::new(OutputQueue { coalescer: Mutex::new(Some(OutputCoalescer::new(io.batching))), wake: Condvar::new() });
+    spawn_output_flusher(app, engine_id, queue.clone());
     let app_clone = app.clone();
+    let engine_id = engine_id.to_string();
     async_runtime::spawn(async move {
+        let mut stdout_lines = LineBuffer::default();
+        let mut stderr_lines = LineBuffer::default();
+        let mut tail: VecDeque<String> = VecDeque::with_capacity(EXIT_TAIL_LINES);
+        let mut exit_status = (None, None);
         while let Some(event) = rx.recv().await {
-            if let CommandEvent::Stdout(buf) | CommandEvent::Stderr(buf) = event {
-                let text = if cfg!(target_os = "windows") { let (cow, ..) = GBK.decode(&buf); cow.into_owned() } 
-                           else { String::from_utf8_lossy(&buf).into_owned() };
-                let _ = app_clone.emit("engine-output", text);
+            let lines: Vec<(Vec<u8>, Direction)> = match event {
+                CommandEvent::Stdout(buf) => stdout_lines.push(&buf).into_iter().map(|l| (l, Direction::Stdout)).collect(),
+                CommandEvent::Stderr(buf) => stderr_lines.push(&buf).into_iter().map(|l| (l, Direction::Stderr)).collect(),
+                CommandEvent::Terminated(payload) => {
+                    exit_status = (payload.code, payload.signal);
+                    let stdout_rest = stdout_lines.flush().map(|l| (l, Direction::Stdout));
+                    stdout_rest.into_iter().chain(stderr_lines.flush().map(|l| (l, Direction::Stderr))).collect()
+                }
+                CommandEvent::Error(message) => vec![(message.into_bytes(), Direction::Stderr)],
+                _ => Vec::new(),
+            };
+            for (line, direction) in lines {
+                let text = io.codec.lock().unwrap().decode(&line);
+                transcript::record(&io.transcript, direction, &text);
+                if tail.len() == EXIT_TAIL_LINES { tail.pop_front(); }
+                tail.push_back(text.clone());
+                let text = if direction == Direction::Stdout { io.adapter.lock().unwrap().incoming(&text) } else { text };
+                // Emitting under the queue lock keeps lines in order with the flusher's batches
+                let mut coalescer = queue.coalescer.lock().unwrap();
+                match coalescer.as_mut().and_then(|c| c.push(OutputLine { text, direction })) {
+                    Some(flush) => emit_flush(&app_clone, &engine_id, flush),
+                    None => queue.wake.notify_one(),
+                }
             }
         }
+        // Everything the engine printed goes out before its exit is reported
+        if let Some(mut coalescer) = queue.coalescer.lock().unwrap().take() {
+            emit_engine_batch(&app_clone, &engine_id, coalescer.take());
+        }
+        queue.wake.notify_one();
+        transcript::record(&io.transcript, Direction::Note, &format!("exited (code {:?}, signal {:?})", exit_status.0, exit_status.1));
+        handle_engine_exit(&app_clone, &engine_id, instance, exit_status, tail.into());
+    });
+    Ok((child, applied))
+}
+
+// Called once an engine's output channel closes. Decides whether the exit was a crash and restarts the
+// engine when its policy allows, replaying the handshake, options and position it had before.
+// The registry is not locked while the new process starts, so other engines are not held up by it.
+fn handle_engine_exit(app: &AppHandle, engine_id: &str, instance: u64, (code, signal): (Option<i32>, Option<i32>), last_lines: Vec<String>) {
+    let registry = app.state::<EngineRegistry>();
+    let (crashed, restart) = {
+        let mut engines = registry.lock().unwrap();
+        // If the session is gone or holds a different process or connection, this exit was caused by kill_engine or a respawn
+        let crashed = matches!(engines.get(engine_id), Some(s) if s.child.id() == instance && !s.history.quit_requested());
+        let restart = match engines.get_mut(engine_id) {
+            Some(s) if crashed && s.restart_policy.enabled && s.restarts < s.restart_policy.max_restarts => {
+                s.restarts += 1;
+                transcript::record(&s.io.transcript, Direction::Note, &format!("restarting (attempt {})", s.restarts));
+                s.io.adapter.lock().unwrap().restarted();
+                Some((s.path.clone(), s.args.clone(), s.resources.clone(), s.remote.clone(), s.io.clone()))
+            }
+            _ => None,
+        };
+        if restart.is_none() && matches!(engines.get(engine_id), Some(s) if s.child.id() == instance) {
+            engines.remove(engine_id);
+        }
+        (crashed, restart)
+    };
+
+    let _ = app.emit("engine-exited", EngineExitedPayload {
+        engine_id: engine_id.to_string(), code, signal, crashed, restarting: restart.is_some(), last_lines,
+    });
+    let Some((path, args, resources, remote, io)) = restart else { return };
+
+    let started = start_engine_process(app, engine_id, &path, &args, &resources, remote.as_ref(), io);
+    let mut engines = registry.lock().unwrap();
+    // Killed or respawned while the new process was starting: the new process is not wanted
+    let Some(session) = engines.get_mut(engine_id).filter(|s| s.child.id() == instance) else {
+        if let Ok((child, _)) = started { child.kill(); }
+        return;
+    };
+    match started {
+        Ok((child, _)) => {
+            session.child = child;
+            for command in session.history.replay() {
+                if write_engine_command(app, engine_id, session, &command).is_err() { break; }
+            }
+        }
+        Err(e) => {
+            engines.remove(engine_id);
+            let _ = app.emit("engine-output", EngineOutputPayload { engine_id: engine_id.to_string(), data: format!("Restart failed: {}", e), stream: "stderr" });
+        }
+    }
+}
+
+// Translate a GUI command for the engine's protocol, log it and write it in the engine's encoding.
+// A `uci` handshake to an engine of unknown protocol arms the fallback to UCCI.
+fn write_engine_command(app: &AppHandle, engine_id: &str, session: &mut EngineSession, command: &str) -> Result<(), String> {
+    let lines = session.io.adapter.lock().unwrap().outgoing(command);
+    for line in lines {
+        write_engine_line(session, &line)?;
+    }
+    if matches!(command.trim(), "uci" | "ucci" | "jai") {
+        send_eval_file(session)?;
+    }
+    if command.trim() == "uci" && session.io.adapter.lock().unwrap().detecting() {
+        schedule_protocol_detection(app, engine_id, session.child.id());
+    }
+    Ok(())
+}
+
+// Engines read their input in order, so the network option written straight after the handshake
+// command is only applied once the handshake is done
+fn send_eval_file(session: &mut EngineSession) -> Result<(), String> {
+    let Some(command) = session.eval_file.as_ref().map(EngineOptionSetting::command) else { return Ok(()) };
+    let lines = session.io.adapter.lock().unwrap().outgoing(&command);
+    for line in lines {
+        write_engine_line(session, &line)?;
+    }
+    Ok(())
+}
+
+fn write_engine_line(session: &mut EngineSession, line: &str) -> Result<(), String> {
+    transcript::record(&session.io.transcript, Direction::Sent, line);
+    let bytes = session.io.codec.lock().unwrap().encode(&format!("{}\n", line));
+    session.child.write(&bytes)
+}
+
+// If the engine has not answered `uci` with `uciok` in time, assume UCCI and send `ucci` instead
+fn schedule_protocol_detection(app: &AppHandle, engine_id: &str, instance: u64) {
+    let app = app.clone();
+    let engine_id = engine_id.to_string();
+    std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(protocol::PROTOCOL_DETECT_MS));
+        let registry = app.state::<EngineRegistry>();
+        let mut engines = registry.lock().unwrap();
+        let Some(session) = engines.get_mut(&engine_id).filter(|s| s.child.id() == instance) else { return };
+        let handshake = session.io.adapter.lock().unwrap().handshake_timeout();
+        if let Some(handshake) = handshake {
+            let _ = write_engine_line(session, &handshake).and_then(|_| send_eval_file(session));
+        }
     });
+}
+
+#[tauri::command]
+async fn kill_engine(engine_id: String, registry: State<'_, EngineRegistry>) -> Result<(), String> {
+    if let Some(session) = registry.lock().unwrap().remove(&engine_id) { session.child.kill(); }
     Ok(())
 }
 
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SpawnEngineOptions {
+    engine_id: String,
+    path: String,
+    #[serde(default)]
+    args: Vec<String>,
+    restart_policy: Option<RestartPolicy>,
+    resources: Option<EngineResources>,
+    transcript: Option<String>,
+    encoding: Option<EngineEncoding>,
+    protocol: Option<EngineProtocol>,
+    remote: Option<RemoteEndpoint>,
+    profile_id: Option<i64>,
+    allow_unregistered: Option<bool>,
+    output_batching: Option<OutputBatching>,
+}
+
+// Returns the niceness, affinity and memory limit the engine actually got, for reproducible results.
+// With `transcript` set, every line sent and received is also logged to that file.
+// `encoding` applies to both directions and defaults to auto-detection. Without `protocol`, an engine
+// that does not answer `uci` is driven as a UCCI engine behind the same UCI-style commands and output.
+// With `remote`, the engine runs on an engine server instead and `path` only labels it.
+// Local engines must have a profile, given by `profile_id` or else found by path, unless
+// `allow_unregistered` is set for an ad-hoc launch; a profile whose binary
+// has changed or gone missing is reported in the returned warnings. If the profile has a network,
+// it must be intact and is loaded through its option after the handshake.
+// Output is coalesced per `output_batching` and arrives as `engine-output-batch` events; handshake
+// replies and `bestmove` are still sent straight away as single `engine-output` events.
 #[tauri::command]
-async fn send_to_engine(command: String, process_state: State<'_, EngineProcess>) -> Result<(), String> {
-    if let Some(child) = process_state.lock().unwrap().as_mut() {
-        child.write(format!("{}\n", command).as_bytes()).map_err(|e| e.to_string())?;
-        Ok(())
-    } else { Err("Engine not running.".into()) }
+async fn spawn_engine(
+    options: SpawnEngineOptions,
+    app: AppHandle,
+    registry: State<'_, EngineRegistry>,
+) -> Result<AppliedResources, String> {
+    let SpawnEngineOptions {
+        engine_id, path, args, restart_policy, resources, transcript, encoding, protocol, remote, profile_id, allow_unregistered, output_batching,
+    } = options;
+    let (profile, profile_warning) = if remote.is_none() { check_engine_profile(&app, &path, profile_id, allow_unregistered.unwrap_or(false))? } else { (None, None) };
+    let eval_file = match &profile {
+        Some(profile) => NetworkManager::new(get_engine_profiles_db_path(&app)?, get_networks_dir(&app)?)?.eval_file_option(profile.id)?,
+        None => None,
+    };
+    // Only an engine already registered under the same ID is replaced; other instances keep running
+    kill_engine(engine_id.clone(), registry.clone()).await.ok();
+    let resources = resources.unwrap_or_default();
+    let transcript = transcript.map(|file| Transcript::create(file, &path, &args)).transpose()?;
+    let io = EngineIo {
+        transcript: Arc::new(Mutex::new(transcript)),
+        codec: EngineCodec::shared(encoding.unwrap_or_default()),
+        adapter: Arc::new(Mutex::new(ProtocolAdapter::new(protocol))),
+        batching: output_batching.unwrap_or_default(),
+    };
+    let (child, mut applied) = start_engine_process(&app, &engine_id, &path, &args, &resources, remote.as_ref(), io.clone())?;
+    applied.warnings.extend(profile_warning);
+    registry.lock().unwrap().insert(engine_id, EngineSession {
+        child, path, args, remote, eval_file, restart_policy: restart_policy.unwrap_or_default(), restarts: 0, history: CommandHistory::default(), resources, io,
+    });
+    Ok(applied)
+}
+
+// The profile with `profile_id`, or else the one registered for `path`, with a warning if its binary
+// has changed or gone missing
+fn check_engine_profile(app: &AppHandle, path: &str, profile_id: Option<i64>, allow_unregistered: bool) -> Result<(Option<EngineProfile>, Option<String>), String> {
+    let store = EngineProfileStore::new(get_engine_profiles_db_path(app)?)?;
+    let profile = match profile_id {
+        Some(id) => {
+            let profile = store.get(id)?.ok_or_else(|| format!("No engine profile with id {}", id))?;
+            if !profile.is_for(path) { return Err(format!("Engine profile '{}' is for {}, not {}", profile.name, profile.path, path)); }
+            Some(profile)
+        }
+        None => store.find_by_path(path)?,
+    };
+    match profile {
+        Some(profile) => {
+            let warning = store.check_binary(&profile)?.warning(&profile);
+            Ok((Some(profile), warning))
+        }
+        None if allow_unregistered => Ok((None, None)),
+        None => Err(format!("Engine is not registered: {}", path)),
+    }
+}
+
+// Start a fresh transcript for a running engine, or stop logging with `path: None`
+#[tauri::command]
+async fn set_engine_transcript(engine_id: String, path: Option<String>, registry: State<'_, EngineRegistry>) -> Result<(), String> {
+    let engines = registry.lock().unwrap();
+    let session = engines.get(&engine_id).ok_or_else(|| format!("Engine '{}' not running.", engine_id))?;
+    let transcript = path.map(|file| Transcript::create(file, &session.path, &session.args)).transpose()?;
+    *session.io.transcript.lock().unwrap() = transcript;
+    Ok(())
+}
+
+#[tauri::command]
+async fn send_to_engine(engine_id: String, command: String, app: AppHandle, registry: State<'_, EngineRegistry>) -> Result<(), String> {
+    if let Some(session) = registry.lock().unwrap().get_mut(&engine_id) {
+        session.history.record(&command);
+        write_engine_command(&app, &engine_id, session, &command)
+    } else { Err(format!("Engine '{}' not running.", engine_id)) }
+}
+
+// Serve an engine over TCP to clients presenting the token, replacing any server already running.
+// Returns the address actually bound, which matters when the configured port is 0.
+#[tauri::command]
+async fn start_engine_server(config: EngineServerConfig, state: State<'_, EngineServerState>) -> Result<String, String> {
+    if let Some(old) = state.server.lock().unwrap().take() { old.stop(); }
+    let server = EngineServer::start(config)?;
+    let address = server.local_addr().to_string();
+    *state.server.lock().unwrap() = Some(server);
+    Ok(address)
+}
+
+#[tauri::command]
+async fn stop_engine_server(state: State<'_, EngineServerState>) -> Result<(), String> {
+    if let Some(server) = state.server.lock().unwrap().take() { server.stop(); }
+    Ok(())
+}
+
+#[tauri::command]
+async fn list_engines(registry: State<'_, EngineRegistry>) -> Result<Vec<String>, String> {
+    let mut ids: Vec<String> = registry.lock().unwrap().keys().cloned().collect();
+    ids.sort();
+    Ok(ids)
+}
+
+// Spawn a candidate engine, detect its protocol and read its id/options, then shut it down again
+#[tauri::command]
+async fn validate_engine(path: String, args: Vec<String>, timeout_ms: Option<u64>, encoding: Option<EngineEncoding>) -> Result<EngineInfo, String> {
+    let timeout = std::time::Duration::from_millis(timeout_ms.unwrap_or(engine::DEFAULT_HANDSHAKE_TIMEOUT_MS));
+    let encoding = encoding.unwrap_or_default();
+    async_runtime::spawn_blocking(move || engine::probe_engine(&path, &args, encoding, timeout))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+// Look for engines bundled with the app, in the user's engines directory and, unless `include_path`
+// is false, on $PATH. Each candidate gets a short handshake; files that cannot run here are reported
+// as such instead of being probed.
+#[tauri::command]
+async fn discover_engines(timeout_ms: Option<u64>, include_path: Option<bool>, app: AppHandle) -> Result<Vec<DiscoveredEngine>, String> {
+    let timeout = std::time::Duration::from_millis(timeout_ms.unwrap_or(discovery::DEFAULT_PROBE_TIMEOUT_MS));
+    let mut dirs = vec![(EngineSource::UserDir, get_user_engines_dir(&app)?)];
+    if let Ok(bundled) = app.path().resolve("resources/engine", tauri::path::BaseDirectory::Resource) {
+        dirs.insert(0, (EngineSource::Bundled, bundled));
+    }
+    let include_path = include_path.unwrap_or(true);
+    let mut found = async_runtime::spawn_blocking(move || discovery::discover(&dirs, include_path, timeout))
+        .await
+        .map_err(|e| e.to_string())?;
+    let store = EngineProfileStore::new(get_engine_profiles_db_path(&app)?)?;
+    for engine in &mut found {
+        engine.registered = store.find_by_path(&engine.path)?.is_some();
+    }
+    Ok(found)
+}
+
+// Run an engine-vs-engine match in the backend. Progress is reported through `match-event`,
+// the final summary is returned once all games are played or the match is stopped.
+#[tauri::command]
+async fn start_match(config: MatchConfig, app: AppHandle) -> Result<MatchSummary, String> {
+    let state = app.state::<MatchState>();
+    if state.running.swap(true, Ordering::SeqCst) { return Err("A match is already running.".into()); }
+    state.stop.store(false, Ordering::SeqCst);
+    let stop = state.stop.clone();
+    let app_clone = app.clone();
+    let result = async_runtime::spawn_blocking(move || {
+        match_runner::run_match(&config, &stop, |event| { let _ = app_clone.emit("match-event", event); })
+    }).await.map_err(|e| e.to_string());
+    state.running.store(false, Ordering::SeqCst);
+    result?
+}
+
+#[tauri::command]
+async fn stop_match(state: State<'_, MatchState>) -> Result<(), String> {
+    state.stop.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+#[tauri::command]
+async fn start_batch_analysis(config: BatchConfig, app: AppHandle) -> Result<BatchSummary, String> {
+    let state = app.state::<BatchState>();
+    if state.running.swap(true, Ordering::SeqCst) { return Err("A batch analysis is already running.".into()); }
+    state.stop.store(false, Ordering::SeqCst);
+    let stop = state.stop.clone();
+    let app_clone = app.clone();
+    let result = async_runtime::spawn_blocking(move || {
+        batch::run_batch(&config, &stop, |event| { let _ = app_clone.emit("batch-event", event); })
+    }).await.map_err(|e| e.to_string());
+    state.running.store(false, Ordering::SeqCst);
+    result?
+}
+
+#[tauri::command]
+async fn stop_batch_analysis(state: State<'_, BatchState>) -> Result<(), String> {
+    state.stop.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+// Annotates every move of a notation JSON game; progress is emitted as "review-event"
+#[tauri::command]
+async fn start_review(notation_json: String, config: ReviewConfig, app: AppHandle) -> Result<ReviewOutput, String> {
+    let game = notation::GameNotation::from_json(&notation_json)?;
+    let state = app.state::<ReviewState>();
+    if state.running.swap(true, Ordering::SeqCst) { return Err("A review is already running.".into()); }
+    state.stop.store(false, Ordering::SeqCst);
+    let stop = state.stop.clone();
+    let app_clone = app.clone();
+    let result = async_runtime::spawn_blocking(move || {
+        review::review_game(game, &config, &stop, |event| { let _ = app_clone.emit("review-event", event); })
+    }).await.map_err(|e| e.to_string());
+    state.running.store(false, Ordering::SeqCst);
+    result?
+}
+
+#[tauri::command]
+async fn stop_review(state: State<'_, ReviewState>) -> Result<(), String> {
+    state.stop.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+// Elo, confidence interval, LOS and optional SPRT from pentanomial pair counts when given, else from WDL
+#[tauri::command]
+async fn compute_match_statistics(wdl: Option<WdlCounts>, pentanomial: Option<Pentanomial>, sprt: Option<SprtConfig>) -> Result<MatchStatistics, String> {
+    let statistics = match (pentanomial, wdl) {
+        (Some(pairs), _) => stats::from_pentanomial(pairs, sprt.as_ref()),
+        (None, Some(counts)) => stats::from_wdl(counts, sprt.as_ref()),
+        (None, None) => return Err("Either WDL or pentanomial counts are required.".into()),
+    };
+    statistics.ok_or_else(|| "No games played.".to_string())
+}
+
+// Emits "clock-tick" every CLOCK_TICK_MS while the clock runs, and "clock-flag" once when a side runs out of time
+fn spawn_clock_ticker(app: AppHandle, clock: Arc<Mutex<Option<GameClock>>>, generation: Arc<AtomicU64>, mine: u64) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(std::time::Duration::from_millis(CLOCK_TICK_MS));
+        if generation.load(Ordering::SeqCst) != mine { break; }
+        let mut guard = clock.lock().unwrap();
+        let Some(clock) = guard.as_mut() else { break };
+        if clock.flagged().is_some() { break; }
+        if !clock.is_running() { continue; }
+        let flagged = clock.check_flag();
+        let snapshot = clock.snapshot();
+        drop(guard);
+        let _ = app.emit("clock-tick", &snapshot);
+        if flagged.is_some() {
+            let _ = app.emit("clock-flag", &snapshot);
+            break;
+        }
+    });
+}
+
+fn with_clock<T>(state: &ClockState, f: impl FnOnce(&mut GameClock) -> T) -> Result<T, String> {
+    let mut guard = state.clock.lock().unwrap();
+    guard.as_mut().map(f).ok_or_else(|| "No clock is running.".to_string())
+}
+
+// `black_control` gives Black a different time control (handicap games); Red moves first unless `side` says otherwise
+#[tauri::command]
+async fn clock_start(control: TimeControl, black_control: Option<TimeControl>, side: Option<jieqi::Side>, app: AppHandle, state: State<'_, ClockState>) -> Result<ClockSnapshot, String> {
+    let black = black_control.unwrap_or_else(|| control.clone());
+    let mut clock = GameClock::with_controls(control, black);
+    clock.start(side.unwrap_or(jieqi::Side::Red));
+    let snapshot = clock.snapshot();
+    *state.clock.lock().unwrap() = Some(clock);
+    let mine = state.generation.fetch_add(1, Ordering::SeqCst) + 1;
+    spawn_clock_ticker(app, state.clock.clone(), state.generation.clone(), mine);
+    Ok(snapshot)
+}
+
+// Ends the current move and starts the opponent's clock; a time loss is reported through `flagged`
+#[tauri::command]
+async fn clock_press(app: AppHandle, state: State<'_, ClockState>) -> Result<ClockSnapshot, String> {
+    let (result, snapshot) = with_clock(&state, |clock| (clock.press(), clock.snapshot()))?;
+    if result.is_err() { let _ = app.emit("clock-flag", &snapshot); }
+    Ok(snapshot)
+}
+
+#[tauri::command]
+async fn clock_pause(state: State<'_, ClockState>) -> Result<ClockSnapshot, String> {
+    with_clock(&state, |clock| { clock.pause(); clock.snapshot() })
+}
+
+#[tauri::command]
+async fn clock_resume(state: State<'_, ClockState>) -> Result<ClockSnapshot, String> {
+    with_clock(&state, |clock| { clock.resume(); clock.snapshot() })
+}
+
+#[tauri::command]
+async fn clock_state(state: State<'_, ClockState>) -> Result<ClockSnapshot, String> {
+    with_clock(&state, |clock| clock.snapshot())
+}
+
+// `go wtime ... btime ...` for the side to move, from the backend clock
+#[tauri::command]
+async fn clock_go_command(state: State<'_, ClockState>) -> Result<String, String> {
+    with_clock(&state, |clock| clock.go_command())
+}
+
+#[tauri::command]
+async fn clock_stop(state: State<'_, ClockState>) -> Result<(), String> {
+    state.generation.fetch_add(1, Ordering::SeqCst);
+    *state.clock.lock().unwrap() = None;
+    Ok(())
 }
 
 #[tauri::command]
 async fn open_external_url(_url: String, _app: AppHandle) -> Result<(), String> { Ok(()) }
 
 #[tauri::command]
-async fn opening_book_import_entries(_json_data: String, _app: AppHandle) -> Result<(i32, Vec<String>), String> { Ok((0, vec![])) }
+async fn opening_book_import_entries(json_data: String, mode: Option<ImportMode>, app: AppHandle) -> Result<ImportReport, String> {
+    let db_path = get_opening_book_db_path(&app)?;
+    let book = JieqiOpeningBook::new(db_path).map_err(|e| e.to_string())?;
+    book.import_data(&json_data, mode.unwrap_or_default()).map_err(|e| e.to_string())
+}
 
 #[tauri::command]
 async fn opening_book_add_entry(request: AddEntryRequest, app: AppHandle) -> Result<bool, String> {
@@ -349,6 +1047,34 @@ async fn opening_book_get_stats(app: AppHandle) -> Result<OpeningBookStats, Stri
     let book = JieqiOpeningBook::new(db_path).map_err(|e| e.to_string())?;
     book.get_stats().map_err(|e| e.to_string())
 }
+// Progress is emitted as "book-build-progress" after each game file
+#[tauri::command]
+async fn opening_book_build(paths: Vec<String>, options: Option<BookBuildOptions>, app: AppHandle) -> Result<BookBuildReport, String> {
+    let db_path = get_opening_book_db_path(&app)?;
+    let paths: Vec<PathBuf> = paths.into_iter().map(PathBuf::from).collect();
+    async_runtime::spawn_blocking(move || {
+        let book = JieqiOpeningBook::new(db_path).map_err(|e| e.to_string())?;
+        book_builder::build_book(&book, &paths, &options.unwrap_or_default(), |progress| { let _ = app.emit("book-build-progress", progress); })
+    }).await.map_err(|e| e.to_string())?
+}
+#[tauri::command]
+async fn opening_book_get_position(key: String, app: AppHandle) -> Result<Option<OpeningBookEntry>, String> {
+    let db_path = get_opening_book_db_path(&app)?;
+    let book = JieqiOpeningBook::new(db_path).map_err(|e| e.to_string())?;
+    book.get_position(&key).map_err(|e| e.to_string())
+}
+#[tauri::command]
+async fn opening_book_keys_without_fen(app: AppHandle) -> Result<Vec<String>, String> {
+    let db_path = get_opening_book_db_path(&app)?;
+    let book = JieqiOpeningBook::new(db_path).map_err(|e| e.to_string())?;
+    book.keys_without_fen().map_err(|e| e.to_string())
+}
+#[tauri::command]
+async fn opening_book_backfill_fens(fens: Vec<String>, app: AppHandle) -> Result<usize, String> {
+    let db_path = get_opening_book_db_path(&app)?;
+    let book = JieqiOpeningBook::new(db_path).map_err(|e| e.to_string())?;
+    book.backfill_fens(&fens).map_err(|e| e.to_string())
+}
 #[tauri::command]
 async fn opening_book_clear_all(app: AppHandle) -> Result<(), String> {
     let db_path = get_opening_book_db_path(&app)?;
@@ -369,10 +1095,88 @@ async fn opening_book_export_db(destination_path: String, app: AppHandle) -> Res
     Ok(())
 }
 #[tauri::command]
-async fn opening_book_import_db(source_path: String, app: AppHandle) -> Result<(), String> {
-    let dest_path = get_opening_book_db_path(&app)?;
-    fs::copy(source_path, dest_path).map_err(|e| e.to_string())?;
-    Ok(())
+async fn opening_book_merge_db(source_path: String, options: Option<MergeOptions>, dry_run: bool, app: AppHandle) -> Result<MergeReport, String> {
+    let db_path = get_opening_book_db_path(&app)?;
+    let book = JieqiOpeningBook::new(db_path).map_err(|e| e.to_string())?;
+    book.merge_database(&source_path, options.unwrap_or_default(), dry_run).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn analysis_cache_query(fen: String, min_depth: Option<u32>, app: AppHandle) -> Result<Option<CachedAnalysis>, String> {
+    let db_path = get_analysis_cache_db_path(&app)?;
+    let cache = AnalysisCache::new(db_path).map_err(|e| e.to_string())?;
+    cache.query(&fen, min_depth).map_err(|e| e.to_string())
+}
+#[tauri::command]
+async fn analysis_cache_insert(request: StoreAnalysisRequest, app: AppHandle) -> Result<bool, String> {
+    let db_path = get_analysis_cache_db_path(&app)?;
+    let cache = AnalysisCache::new(db_path).map_err(|e| e.to_string())?;
+    cache.insert(&request).map_err(|e| e.to_string())
+}
+#[tauri::command]
+async fn analysis_cache_prune(criteria: PruneCriteria, app: AppHandle) -> Result<usize, String> {
+    let db_path = get_analysis_cache_db_path(&app)?;
+    let cache = AnalysisCache::new(db_path).map_err(|e| e.to_string())?;
+    cache.prune(&criteria).map_err(|e| e.to_string())
+}
+#[tauri::command]
+async fn engine_profiles_list(app: AppHandle) -> Result<Vec<EngineProfileStatus>, String> {
+    let store = EngineProfileStore::new(get_engine_profiles_db_path(&app)?)?;
+    store.list()
+}
+#[tauri::command]
+async fn engine_profile_add(profile: EngineProfileInput, app: AppHandle) -> Result<EngineProfile, String> {
+    let mut store = EngineProfileStore::new(get_engine_profiles_db_path(&app)?)?;
+    store.add(&profile)
+}
+// The profile for an engine defined in config.ini, registering it the first time it is loaded
+#[tauri::command]
+async fn engine_profile_register(profile: EngineProfileInput, app: AppHandle) -> Result<EngineProfile, String> {
+    let mut store = EngineProfileStore::new(get_engine_profiles_db_path(&app)?)?;
+    store.find_or_add(&profile)
+}
+// Saving a profile fingerprints its binary again, which is how a changed binary is accepted
+#[tauri::command]
+async fn engine_profile_update(id: i64, profile: EngineProfileInput, app: AppHandle) -> Result<EngineProfile, String> {
+    let mut store = EngineProfileStore::new(get_engine_profiles_db_path(&app)?)?;
+    store.update(id, &profile)
+}
+#[tauri::command]
+async fn engine_profile_remove(id: i64, app: AppHandle) -> Result<bool, String> {
+    let mut store = EngineProfileStore::new(get_engine_profiles_db_path(&app)?)?;
+    store.remove(id)
+}
+#[tauri::command]
+async fn engine_profile_duplicate(id: i64, name: Option<String>, app: AppHandle) -> Result<EngineProfile, String> {
+    let mut store = EngineProfileStore::new(get_engine_profiles_db_path(&app)?)?;
+    let copy = store.duplicate(id, name.as_deref())?;
+    let networks = NetworkManager::new(get_engine_profiles_db_path(&app)?, get_networks_dir(&app)?)?;
+    if let Some((network, option_name)) = networks.assignment(id)? {
+        networks.assign(copy.id, Some(network.id), Some(&option_name))?;
+    }
+    Ok(copy)
+}
+#[tauri::command]
+async fn networks_list(app: AppHandle) -> Result<Vec<NetworkEntry>, String> {
+    let networks = NetworkManager::new(get_engine_profiles_db_path(&app)?, get_networks_dir(&app)?)?;
+    networks.list()
+}
+// Copy a network file into the managed directory; importing the same content twice is harmless
+#[tauri::command]
+async fn network_import(path: String, name: Option<String>, app: AppHandle) -> Result<Network, String> {
+    let networks = NetworkManager::new(get_engine_profiles_db_path(&app)?, get_networks_dir(&app)?)?;
+    async_runtime::spawn_blocking(move || networks.import(&path, name.as_deref())).await.map_err(|e| e.to_string())?
+}
+#[tauri::command]
+async fn network_remove(id: i64, app: AppHandle) -> Result<bool, String> {
+    let networks = NetworkManager::new(get_engine_profiles_db_path(&app)?, get_networks_dir(&app)?)?;
+    networks.remove(id)
+}
+// Use a network for an engine profile (`network_id: None` clears it). `option_name` defaults to EvalFile.
+#[tauri::command]
+async fn network_assign(profile_id: i64, network_id: Option<i64>, option_name: Option<String>, app: AppHandle) -> Result<(), String> {
+    let networks = NetworkManager::new(get_engine_profiles_db_path(&app)?, get_networks_dir(&app)?)?;
+    networks.assign(profile_id, network_id, option_name.as_deref())
 }
 #[tauri::command]
 async fn save_game_notation(content: String, filename: String, app: AppHandle) -> Result<String, String> {
@@ -445,14 +1249,33 @@ async fn request_saf_file_selection(name: String, args: String, has_nnue: bool,
 async fn handle_saf_file_result(_temp_file_path: String, _filename: String, _name: String, _args: String, _has_nnue: bool, _app: AppHandle) -> Result<(), String> { Ok(()) }
 #[cfg(target_os = "android")]
 #[tauri::command]
-async fn handle_nnue_file_result(_temp_file_path: String, _filename: String, _engine_name: String, _engine_path: String, _args: String, _engine_instance_id: String, _app: AppHandle) -> Result<(), String> { Ok(()) }
+async fn handle_nnue_file_result(temp_file_path: String, filename: String, engine_name: String, engine_path: String, args: String, _engine_instance_id: String, app: AppHandle) -> Result<(), String> {
+    // The picked file arrives under a temporary name; import it under the name the user chose
+    let named_path = Path::new(&temp_file_path).with_file_name(&filename);
+    fs::rename(&temp_file_path, &named_path).map_err(|e| e.to_string())?;
+    let networks = NetworkManager::new(get_engine_profiles_db_path(&app)?, get_networks_dir(&app)?)?;
+    let network = networks.import(&named_path.to_string_lossy(), None);
+    let _ = fs::remove_file(&named_path);
+    let network = network?;
+    let mut store = EngineProfileStore::new(get_engine_profiles_db_path(&app)?)?;
+    let profile = store.find_or_add(&EngineProfileInput {
+        name: engine_name, path: engine_path, args: args.split_whitespace().map(str::to_string).collect(),
+        protocol: EngineProtocol::default(), encoding: EngineEncoding::default(), options: Vec::new(),
+    })?;
+    networks.assign(profile.id, Some(network.id), None)
+}
 
 // === RUN ===
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_os::init())
-        .manage(Arc::new(Mutex::new(None)) as EngineProcess)
+        .manage(Arc::new(Mutex::new(HashMap::new())) as EngineRegistry)
+        .manage(MatchState::default())
+        .manage(ClockState::default())
+        .manage(BatchState::default())
+        .manage(ReviewState::default())
+        .manage(EngineServerState::default())
         .setup(|app| {
             let templates = load_templates(app.handle());
             app.manage(TemplateState { templates: Mutex::new(templates) });
@@ -461,13 +1284,21 @@ pub fn run() {
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_shell::init())
         .invoke_handler(tauri::generate_handler![
-            spawn_engine, kill_engine, send_to_engine, open_external_url,
+            spawn_engine, kill_engine, send_to_engine, set_engine_transcript, list_engines, validate_engine, discover_engines, start_engine_server, stop_engine_server, start_match, stop_match, compute_match_statistics,
+            start_batch_analysis, stop_batch_analysis, start_review, stop_review,
+            clock_start, clock_press, clock_pause, clock_resume, clock_state, clock_go_command, clock_stop,
+            open_external_url,
             save_game_notation, save_chart_image, load_config, save_config, clear_config,
             save_autosave, load_autosave, save_game_notation_with_dialog,
             copy_to_clipboard, paste_from_clipboard,
             opening_book_add_entry, opening_book_delete_entry, opening_book_query_moves,
             opening_book_get_stats, opening_book_clear_all, opening_book_export_all,
-            opening_book_import_entries, opening_book_export_db, opening_book_import_db,
+            opening_book_import_entries, opening_book_export_db,
+            opening_book_get_position, opening_book_keys_without_fen, opening_book_backfill_fens, opening_book_merge_db,
+            opening_book_build,
+            analysis_cache_query, analysis_cache_insert, analysis_cache_prune,
+            engine_profiles_list, engine_profile_add, engine_profile_register, engine_profile_update, engine_profile_remove, engine_profile_duplicate,
+            networks_list, network_import, network_remove, network_assign,
             #[cfg(target_os = "android")] get_bundle_identifier,
             #[cfg(target_os = "android")] get_default_android_engine_path,
             #[cfg(target_os = "android")] check_android_file_permissions,
@@ -0,0 +1,652 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::clock::{GameClock, TimeControl};
+use crate::engine::{EngineEncoding, EngineEvent, EngineProcess, EngineProtocol, DEFAULT_HANDSHAKE_TIMEOUT_MS};
+use crate::jieqi::{Position, Rng, Side, START_FEN};
+use crate::notation::{self, GameNotation, NotationMetadata, NotationMove};
+use crate::resources::{AppliedResources, EngineResources};
+use crate::stats::{self, MatchStatistics, Pentanomial, SprtConfig, SprtDecision, WdlCounts};
+use crate::uci::{self, Score, UciMessage};
+
+// Extra time an engine gets to answer `stop` after its clock ran out
+const STOP_GRACE: Duration = Duration::from_millis(1000);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EngineSpec {
+    #[serde(default)]
+    pub name: String,
+    pub path: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub options: Vec<(String, String)>,
+    #[serde(default)]
+    pub resources: EngineResources,
+    #[serde(default)]
+    pub encoding: EngineEncoding,
+    /// UCCI engines are driven through the protocol adapter; JAI is not supported here.
+    #[serde(default)]
+    pub protocol: EngineProtocol,
+}
+
+fn default_max_plies() -> u32 {
+    300
+}
+
+fn default_no_capture_plies() -> u32 {
+    120
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MatchConfig {
+    pub engine_a: EngineSpec,
+    pub engine_b: EngineSpec,
+    pub games: u32,
+    #[serde(default)]
+    pub time_control: TimeControl,
+    #[serde(default)]
+    pub start_fen: Option<String>,
+    /// Games longer than this are drawn.
+    #[serde(default = "default_max_plies")]
+    pub max_plies: u32,
+    /// Plies without a capture or reveal after which the game is drawn.
+    #[serde(default = "default_no_capture_plies")]
+    pub no_capture_plies: u32,
+    /// Seed for dark-piece assignment; both games of a colour-swapped pair share the same draws.
+    #[serde(default)]
+    pub seed: Option<u64>,
+    #[serde(default)]
+    pub output_dir: Option<String>,
+    /// Stop the match as soon as the SPRT accepts either hypothesis (checked after each game pair).
+    #[serde(default)]
+    pub sprt: Option<SprtConfig>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GameResult {
+    RedWins,
+    BlackWins,
+    Draw,
+}
+
+impl GameResult {
+    pub fn as_notation(self) -> &'static str {
+        match self {
+            GameResult::RedWins => "1-0",
+            GameResult::BlackWins => "0-1",
+            GameResult::Draw => "1/2-1/2",
+        }
+    }
+
+    fn score_for_red(self) -> f64 {
+        match self {
+            GameResult::RedWins => 1.0,
+            GameResult::BlackWins => 0.0,
+            GameResult::Draw => 0.5,
+        }
+    }
+
+    fn win_for(side: Side) -> Self {
+        match side {
+            Side::Red => GameResult::RedWins,
+            Side::Black => GameResult::BlackWins,
+        }
+    }
+}
+
+/// Running tally from engine A's point of view.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct MatchScore {
+    pub wins: u32,
+    pub losses: u32,
+    pub draws: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GameRecord {
+    pub game: u32,
+    pub red: String,
+    pub black: String,
+    pub engine_a_red: bool,
+    pub result: GameResult,
+    pub reason: String,
+    pub plies: u32,
+    pub seed: u64,
+    pub file: Option<String>,
+}
+
+impl GameRecord {
+    fn score_for_engine_a(&self) -> f64 {
+        let red = self.result.score_for_red();
+        if self.engine_a_red {
+            red
+        } else {
+            1.0 - red
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MatchSummary {
+    pub engine_a: String,
+    pub engine_b: String,
+    pub score: MatchScore,
+    pub pentanomial: Pentanomial,
+    pub statistics: Option<MatchStatistics>,
+    pub games: Vec<GameRecord>,
+    pub aborted: bool,
+    /// Resource settings each engine ran with, as applied by the OS.
+    pub engine_a_resources: Option<AppliedResources>,
+    pub engine_b_resources: Option<AppliedResources>,
+}
+
+impl MatchSummary {
+    /// Statistics for engine A. Pentanomial counts are used once at least one pair is complete
+    /// and the game count is even, so a half-played pair never skews the result.
+    fn compute_statistics(&self, sprt: Option<&SprtConfig>) -> Option<MatchStatistics> {
+        let pairs: u32 = self.pentanomial.iter().sum();
+        if pairs > 0 && self.games.len().is_multiple_of(2) {
+            stats::from_pentanomial(self.pentanomial, sprt)
+        } else {
+            let counts = WdlCounts {
+                wins: self.score.wins,
+                draws: self.score.draws,
+                losses: self.score.losses,
+            };
+            stats::from_wdl(counts, sprt)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MatchEvent {
+    GameStarted {
+        game: u32,
+        total: u32,
+        red: String,
+        black: String,
+        seed: u64,
+    },
+    Move {
+        game: u32,
+        ply: u32,
+        notation: String,
+        fen: String,
+        score: Option<i32>,
+        time_ms: u64,
+        red_clock_ms: Option<u64>,
+        black_clock_ms: Option<u64>,
+    },
+    GameFinished {
+        game: u32,
+        result: String,
+        reason: String,
+        score: MatchScore,
+        statistics: Option<MatchStatistics>,
+        file: Option<String>,
+    },
+    Finished {
+        score: MatchScore,
+        statistics: Option<MatchStatistics>,
+        aborted: bool,
+    },
+}
+
+struct SearchOutcome {
+    best_move: String,
+    score: Option<Score>,
+    elapsed: Duration,
+}
+
+/// One engine taking part in the match, kept alive across games.
+struct MatchEngine {
+    spec: EngineSpec,
+    name: String,
+    process: Option<EngineProcess>,
+    applied: Option<AppliedResources>,
+}
+
+impl MatchEngine {
+    fn new(spec: EngineSpec) -> Self {
+        let name = if spec.name.is_empty() {
+            Path::new(&spec.path)
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_else(|| spec.path.clone())
+        } else {
+            spec.name.clone()
+        };
+        MatchEngine {
+            spec,
+            name,
+            process: None,
+            applied: None,
+        }
+    }
+
+    /// Start the engine if it is not running (first game, or after a crash) and prepare a new game.
+    fn prepare(&mut self) -> Result<(), String> {
+        let timeout = Duration::from_millis(DEFAULT_HANDSHAKE_TIMEOUT_MS);
+        if !self.process.as_mut().is_some_and(|p| p.is_running()) {
+            let spec = &self.spec;
+            let mut process = EngineProcess::spawn_with(&spec.path, &spec.args, &spec.resources, spec.encoding)?;
+            process.set_protocol(spec.protocol);
+            self.applied = Some(process.applied().clone());
+            process.send("uci")?;
+            process.read_until(timeout, |l| l.trim() == "uciok")?;
+            for (name, value) in &self.spec.options {
+                process.send(&format!("setoption name {} value {}", name, value))?;
+            }
+            self.process = Some(process);
+        }
+        let process = self.process.as_mut().ok_or("Engine not running")?;
+        process.send("ucinewgame")?;
+        process.send("isready")?;
+        process.read_until(timeout, |l| l.trim() == "readyok")?;
+        Ok(())
+    }
+
+    /// Run one search. `budget` is how long the engine may think before it is told to stop.
+    fn search(&mut self, fen: &str, go: &str, budget: Duration) -> Result<SearchOutcome, String> {
+        let process = self.process.as_mut().ok_or("Engine not running")?;
+        process.send(&format!("position fen {}", fen))?;
+        let started = Instant::now();
+        process.send(go)?;
+
+        let mut score = None;
+        let mut stop_sent = false;
+        loop {
+            let elapsed = started.elapsed();
+            let wait = if stop_sent {
+                (budget + STOP_GRACE).saturating_sub(elapsed)
+            } else {
+                budget.saturating_sub(elapsed)
+            };
+            if wait.is_zero() {
+                if stop_sent {
+                    return Err("Engine did not answer stop".into());
+                }
+                process.send("stop")?;
+                stop_sent = true;
+                continue;
+            }
+            match process.recv(wait) {
+                Some(EngineEvent::Stdout(line)) => match uci::parse_line(&line) {
+                    Some(UciMessage::Info(info)) if info.multipv.unwrap_or(1) == 1 && info.score.is_some() => {
+                        score = info.score;
+                    }
+                    Some(UciMessage::BestMove { best_move, .. }) => {
+                        return Ok(SearchOutcome {
+                            best_move,
+                            score,
+                            elapsed: started.elapsed(),
+                        });
+                    }
+                    _ => {}
+                },
+                Some(EngineEvent::Stderr(_)) => {}
+                Some(EngineEvent::Exited { code, signal }) => {
+                    self.process = None;
+                    return Err(format!("Engine exited (code {:?}, signal {:?})", code, signal));
+                }
+                None => {}
+            }
+        }
+    }
+
+    fn shutdown(&mut self) {
+        if let Some(process) = self.process.take() {
+            process.shutdown(Duration::from_millis(500));
+        }
+    }
+}
+
+/// Play `config.games` games between the two engines, alternating colours every game.
+/// `stop` aborts the match between moves; `on_event` receives progress as it happens.
+pub fn run_match<F>(config: &MatchConfig, stop: &AtomicBool, mut on_event: F) -> Result<MatchSummary, String>
+where
+    F: FnMut(&MatchEvent),
+{
+    let start_fen = config.start_fen.clone().unwrap_or_else(|| START_FEN.to_string());
+    Position::from_fen(&start_fen)?;
+    let base_seed = config.seed.unwrap_or_else(|| chrono::Utc::now().timestamp_nanos_opt().unwrap_or(0) as u64);
+    let output_dir = match &config.output_dir {
+        Some(dir) => {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+            Some(PathBuf::from(dir))
+        }
+        None => None,
+    };
+    let match_tag = chrono::Local::now().format("%Y%m%d_%H%M%S").to_string();
+
+    let mut engine_a = MatchEngine::new(config.engine_a.clone());
+    let mut engine_b = MatchEngine::new(config.engine_b.clone());
+    let mut summary = MatchSummary {
+        engine_a: engine_a.name.clone(),
+        engine_b: engine_b.name.clone(),
+        score: MatchScore::default(),
+        pentanomial: [0; 5],
+        statistics: None,
+        games: Vec::new(),
+        aborted: false,
+        engine_a_resources: None,
+        engine_b_resources: None,
+    };
+
+    for game in 1..=config.games {
+        if stop.load(Ordering::Relaxed) {
+            summary.aborted = true;
+            break;
+        }
+        let engine_a_red = game % 2 == 1;
+        // Both games of a pair share a seed, which deals every dark piece its identity before the
+        // first move (see play_game): the same square reveals the same piece in both games
+        let seed = base_seed.wrapping_add(((game - 1) / 2) as u64);
+        let (red, black) = if engine_a_red {
+            (&mut engine_a, &mut engine_b)
+        } else {
+            (&mut engine_b, &mut engine_a)
+        };
+        on_event(&MatchEvent::GameStarted {
+            game,
+            total: config.games,
+            red: red.name.clone(),
+            black: black.name.clone(),
+            seed,
+        });
+
+        let start = GameStart {
+            game,
+            fen: &start_fen,
+            seed,
+        };
+        let outcome = play_game(config, start, red, black, stop, &mut on_event)?;
+        let Some((result, reason, mut notation)) = outcome else {
+            summary.aborted = true;
+            break;
+        };
+
+        match (result, engine_a_red) {
+            (GameResult::Draw, _) => summary.score.draws += 1,
+            (GameResult::RedWins, true) | (GameResult::BlackWins, false) => summary.score.wins += 1,
+            _ => summary.score.losses += 1,
+        }
+
+        notation.metadata.round = Some(game.to_string());
+        let file = match &output_dir {
+            Some(dir) => {
+                let path = dir.join(format!("{}_game{:03}.json", match_tag, game));
+                fs::write(&path, notation.to_json()?).map_err(|e| e.to_string())?;
+                Some(path.to_string_lossy().into_owned())
+            }
+            None => None,
+        };
+
+        summary.games.push(GameRecord {
+            game,
+            red: notation.metadata.white.clone(),
+            black: notation.metadata.black.clone(),
+            engine_a_red,
+            result,
+            reason: reason.clone(),
+            plies: notation.moves.len() as u32,
+            seed,
+            file: file.clone(),
+        });
+        let pair_complete = game.is_multiple_of(2);
+        if pair_complete {
+            let n = summary.games.len();
+            let pair_score = summary.games[n - 2].score_for_engine_a() + summary.games[n - 1].score_for_engine_a();
+            summary.pentanomial[(pair_score * 2.0).round() as usize] += 1;
+        }
+        summary.statistics = summary.compute_statistics(config.sprt.as_ref());
+
+        on_event(&MatchEvent::GameFinished {
+            game,
+            result: result.as_notation().to_string(),
+            reason,
+            score: summary.score,
+            statistics: summary.statistics.clone(),
+            file,
+        });
+
+        let sprt_decision = summary.statistics.as_ref().and_then(|s| s.sprt.as_ref()).map(|s| s.decision);
+        if pair_complete && matches!(sprt_decision, Some(SprtDecision::AcceptH0) | Some(SprtDecision::AcceptH1)) {
+            break;
+        }
+    }
+
+    engine_a.shutdown();
+    engine_b.shutdown();
+    summary.engine_a_resources = engine_a.applied.take();
+    summary.engine_b_resources = engine_b.applied.take();
+    on_event(&MatchEvent::Finished {
+        score: summary.score,
+        statistics: summary.statistics.clone(),
+        aborted: summary.aborted,
+    });
+    Ok(summary)
+}
+
+// Remaining time for the Move event; fixed movetime has no running clocks to report
+fn clock_readings(clock: &GameClock) -> (Option<u64>, Option<u64>) {
+    if matches!(clock.control(Side::Red), TimeControl::Movetime { .. }) {
+        return (None, None);
+    }
+    let snapshot = clock.snapshot();
+    (Some(snapshot.red.remaining_ms), Some(snapshot.black.remaining_ms))
+}
+
+// Which game of the match is played, from which position and with which dealing seed
+struct GameStart<'a> {
+    game: u32,
+    fen: &'a str,
+    seed: u64,
+}
+
+/// Play a single game. Returns `None` if the match was stopped before the game ended.
+fn play_game<F>(
+    config: &MatchConfig,
+    start: GameStart,
+    red: &mut MatchEngine,
+    black: &mut MatchEngine,
+    stop: &AtomicBool,
+    on_event: &mut F,
+) -> Result<Option<(GameResult, String, GameNotation)>, String>
+where
+    F: FnMut(&MatchEvent),
+{
+    let mut position = Position::from_fen(start.fen)?;
+    let mut rng = Rng::new(start.seed);
+    position.deal_hidden(&mut rng);
+    let mut clock = GameClock::new(config.time_control.clone());
+    let mut repetitions: HashMap<String, u32> = HashMap::new();
+    let mut moves: Vec<NotationMove> = Vec::new();
+    repetitions.insert(position.repetition_key(), 1);
+
+    let (result, reason) = 'game: {
+        for (side, engine) in [(Side::Red, &mut *red), (Side::Black, &mut *black)] {
+            if let Err(e) = engine.prepare() {
+                break 'game (GameResult::win_for(side.opponent()), format!("{} failed to start: {}", engine.name, e));
+            }
+        }
+
+        loop {
+            if stop.load(Ordering::Relaxed) {
+                return Ok(None);
+            }
+            if moves.len() as u32 >= config.max_plies {
+                break 'game (GameResult::Draw, "move limit".to_string());
+            }
+
+            let side = position.side_to_move();
+            let engine = if side == Side::Red { &mut *red } else { &mut *black };
+            clock.start(side);
+            let search = engine.search(&position.fen_for(side), &clock.go_command(), clock.budget());
+            let outcome = match search {
+                Ok(outcome) => outcome,
+                Err(e) => break 'game (GameResult::win_for(side.opponent()), format!("{}: {}", engine.name, e)),
+            };
+            if clock.press().is_err() {
+                break 'game (GameResult::win_for(side.opponent()), format!("{} lost on time", side));
+            }
+            if matches!(outcome.best_move.as_str(), "(none)" | "0000" | "none") {
+                break 'game (GameResult::win_for(side.opponent()), format!("{} has no legal moves", side));
+            }
+            let applied = match position.apply_move(&outcome.best_move, &mut rng) {
+                Ok(applied) => applied,
+                Err(e) => break 'game (GameResult::win_for(side.opponent()), format!("illegal move by {}: {}", engine.name, e)),
+            };
+
+            let fen = position.to_fen();
+            let score = outcome.score.as_ref().map(notation::encode_score);
+            let time_ms = outcome.elapsed.as_millis() as u64;
+            let (red_clock_ms, black_clock_ms) = clock_readings(&clock);
+            on_event(&MatchEvent::Move {
+                game: start.game,
+                ply: moves.len() as u32 + 1,
+                notation: applied.notation.clone(),
+                fen: fen.clone(),
+                score,
+                time_ms,
+                red_clock_ms,
+                black_clock_ms,
+            });
+            moves.push(NotationMove {
+                kind: "move".to_string(),
+                data: applied.notation,
+                fen,
+                engine_score: score.map(f64::from),
+                engine_time: Some(time_ms),
+                ..Default::default()
+            });
+
+            if matches!(applied.captured, Some('k') | Some('K')) || !position.has_king(side.opponent()) {
+                break 'game (GameResult::win_for(side), "king captured".to_string());
+            }
+            if position.halfmove_clock() >= config.no_capture_plies {
+                break 'game (GameResult::Draw, format!("{} plies without capture", config.no_capture_plies));
+            }
+            let seen = repetitions.entry(position.repetition_key()).or_insert(0);
+            *seen += 1;
+            if *seen >= 3 {
+                break 'game (GameResult::Draw, "threefold repetition".to_string());
+            }
+        }
+    };
+
+    let notation = GameNotation {
+        metadata: NotationMetadata {
+            event: "Engine match".to_string(),
+            site: "jieqibox".to_string(),
+            date: chrono::Local::now().format("%Y-%m-%d").to_string(),
+            white: red.name.clone(),
+            black: black.name.clone(),
+            result: result.as_notation().to_string(),
+            initial_fen: start.fen.to_string(),
+            flip_mode: "random".to_string(),
+            current_fen: position.to_fen(),
+            ..Default::default()
+        },
+        moves,
+    };
+    Ok(Some((result, reason, notation)))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    // An engine that answers the handshake and plays the moves given as its arguments in order
+    fn scripted_engine(dir: &Path, name: &str, moves: &[&str]) -> MatchEngine {
+        let path = dir.join(name);
+        let script = r#"#!/bin/sh
+n=0
+while read -r line; do
+  case "$line" in
+    uci) echo uciok ;;
+    isready) echo readyok ;;
+    go*) n=$((n + 1)); eval "m=\${$n}"; echo "bestmove $m" ;;
+    quit) exit 0 ;;
+  esac
+done
+"#;
+        fs::write(&path, script).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        MatchEngine::new(EngineSpec {
+            name: name.to_string(),
+            path: path.to_string_lossy().into_owned(),
+            args: moves.iter().map(|m| m.to_string()).collect(),
+            ..Default::default()
+        })
+    }
+
+    fn play(start_fen: &str, red_moves: &[&str], black_moves: &[&str]) -> (GameResult, String, usize) {
+        let dir = std::env::temp_dir().join(format!("jieqibox-match-{}-{}", std::process::id(), red_moves.join("")));
+        fs::create_dir_all(&dir).unwrap();
+        let mut red = scripted_engine(&dir, "red", red_moves);
+        let mut black = scripted_engine(&dir, "black", black_moves);
+        let config = MatchConfig {
+            engine_a: red.spec.clone(),
+            engine_b: black.spec.clone(),
+            games: 1,
+            time_control: TimeControl::Movetime { movetime_ms: 2000 },
+            start_fen: None,
+            max_plies: default_max_plies(),
+            no_capture_plies: default_no_capture_plies(),
+            seed: Some(1),
+            output_dir: None,
+            sprt: None,
+        };
+        let stop = AtomicBool::new(false);
+        let outcome = play_game(
+            &config,
+            GameStart {
+                game: 1,
+                fen: start_fen,
+                seed: 1,
+            },
+            &mut red,
+            &mut black,
+            &stop,
+            &mut |_| {},
+        );
+        red.shutdown();
+        black.shutdown();
+        let _ = fs::remove_dir_all(&dir);
+        let (result, reason, notation) = outcome.unwrap().unwrap();
+        (result, reason, notation.moves.len())
+    }
+
+    #[test]
+    fn rook_jumping_over_pieces_loses() {
+        let (result, reason, plies) = play(START_FEN, &["a0a5"], &[]);
+        assert_eq!(result, GameResult::BlackWins);
+        assert!(reason.contains("illegal move by red"), "{}", reason);
+        assert_eq!(plies, 0);
+    }
+
+    #[test]
+    fn king_leaving_the_palace_loses() {
+        let fen = "3k5/9/9/9/9/9/9/4K4/9/9 w - - 0 1";
+        let (result, reason, plies) = play(fen, &["e2e1"], &["d9c9"]);
+        assert_eq!(result, GameResult::RedWins);
+        assert!(reason.contains("illegal move by black"), "{}", reason);
+        assert_eq!(plies, 1);
+    }
+
+    #[test]
+    fn leaving_the_king_in_check_loses() {
+        let fen = "4r4/3k5/9/9/9/9/9/9/4R4/4K4 w - - 0 1";
+        let (result, reason, _) = play(fen, &["e1d1"], &[]);
+        assert_eq!(result, GameResult::BlackWins);
+        assert!(reason.contains("in check"), "{}", reason);
+    }
+}
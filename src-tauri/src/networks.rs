@@ -0,0 +1,358 @@
+// Evaluation networks (NNUE files) kept in an app-managed directory. Each network is stored under
+// its content hash and can be assigned to engine profiles, which then get the network's path as
+// their `EvalFile` option when loaded. Networks are checked before use, so a deleted or replaced
+// file is reported instead of the engine quietly falling back to its built-in default.
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::profiles::{self, modified_secs, EngineOptionSetting, EngineProfileStore, OptionValue};
+
+pub const DEFAULT_NETWORK_OPTION: &str = "EvalFile";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Network {
+    pub id: i64,
+    pub name: String,
+    /// Where the managed copy lives.
+    pub path: String,
+    pub sha256: String,
+    pub size: u64,
+    /// Unix time in seconds.
+    pub imported_at: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum NetworkStatus {
+    Ok,
+    Missing,
+    /// The file no longer matches the hash it was imported with.
+    Changed { actual: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetworkEntry {
+    #[serde(flatten)]
+    pub network: Network,
+    pub status: NetworkStatus,
+    /// Ids of the engine profiles using this network.
+    pub profiles: Vec<i64>,
+}
+
+pub struct NetworkManager {
+    conn: Connection,
+    dir: PathBuf,
+}
+
+impl NetworkManager {
+    /// Networks are recorded in the engine profile database so assignments can refer to profiles.
+    pub fn new<P: AsRef<Path>, D: AsRef<Path>>(db_path: P, dir: D) -> Result<Self, String> {
+        // Assignments are joined against the profiles, so their tables must exist too
+        EngineProfileStore::new(&db_path)?;
+        let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
+        let manager = NetworkManager {
+            conn,
+            dir: dir.as_ref().to_path_buf(),
+        };
+        manager.initialize_database().map_err(|e| e.to_string())?;
+        Ok(manager)
+    }
+
+    fn initialize_database(&self) -> rusqlite::Result<()> {
+        self.conn.execute_batch(
+            r#"
+            CREATE TABLE IF NOT EXISTS networks (
+                id          INTEGER PRIMARY KEY AUTOINCREMENT,
+                name        TEXT NOT NULL,
+                path        TEXT NOT NULL,
+                sha256      TEXT NOT NULL UNIQUE,
+                size        INTEGER NOT NULL,
+                file_mtime  INTEGER NOT NULL,
+                imported_at INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS profile_networks (
+                profile_id  INTEGER PRIMARY KEY,
+                network_id  INTEGER NOT NULL,
+                option_name TEXT NOT NULL
+            );
+            "#,
+        )
+    }
+
+    /// Copy a network into the managed directory as `<dir>/<hash prefix>/<file name>`, keeping
+    /// the original name for engines that care about it. Importing a file with the same content
+    /// again returns the existing network.
+    pub fn import(&self, source: &str, name: Option<&str>) -> Result<Network, String> {
+        let sha256 = profiles::fingerprint(source)?;
+        if let Some(existing) = self.find_by_hash(&sha256)? {
+            if self.status(&existing)? == NetworkStatus::Ok {
+                return Ok(existing);
+            }
+        }
+        let file_name = Path::new(source)
+            .file_name()
+            .ok_or_else(|| format!("Invalid network path: {}", source))?;
+        let target_dir = self.dir.join(&sha256[..16]);
+        fs::create_dir_all(&target_dir).map_err(|e| format!("Failed to create {}: {}", target_dir.display(), e))?;
+        let target = target_dir.join(file_name);
+        // Copy under a temporary name so a half-written file never looks like the network
+        let partial = target_dir.join(".partial");
+        fs::copy(source, &partial).map_err(|e| format!("Failed to copy {}: {}", source, e))?;
+        if profiles::fingerprint(&partial)? != sha256 {
+            let _ = fs::remove_file(&partial);
+            return Err(format!("{} changed while it was being imported", source));
+        }
+        fs::rename(&partial, &target).map_err(|e| e.to_string())?;
+
+        let metadata = fs::metadata(&target).map_err(|e| e.to_string())?;
+        let name = name
+            .map(str::to_string)
+            .unwrap_or_else(|| Path::new(file_name).file_stem().unwrap_or(file_name).to_string_lossy().into_owned());
+        self.conn
+            .execute(
+                "INSERT INTO networks (name, path, sha256, size, file_mtime, imported_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                 ON CONFLICT(sha256) DO UPDATE SET path = excluded.path, size = excluded.size, file_mtime = excluded.file_mtime",
+                rusqlite::params![
+                    name,
+                    target.to_string_lossy(),
+                    sha256,
+                    metadata.len() as i64,
+                    modified_secs(&metadata),
+                    chrono::Utc::now().timestamp(),
+                ],
+            )
+            .map_err(|e| e.to_string())?;
+        self.find_by_hash(&sha256)?.ok_or_else(|| "Network was not saved".to_string())
+    }
+
+    pub fn list(&self) -> Result<Vec<NetworkEntry>, String> {
+        let networks = self.query_networks("SELECT id, name, path, sha256, size, imported_at FROM networks ORDER BY id", [])?;
+        let mut entries = Vec::with_capacity(networks.len());
+        for network in networks {
+            let mut stmt = self
+                .conn
+                .prepare(
+                    "SELECT pn.profile_id FROM profile_networks pn JOIN engine_profiles p ON p.id = pn.profile_id
+                     WHERE pn.network_id = ?1 ORDER BY pn.profile_id",
+                )
+                .map_err(|e| e.to_string())?;
+            let profiles = stmt
+                .query_map([network.id], |row| row.get(0))
+                .and_then(|rows| rows.collect::<rusqlite::Result<Vec<i64>>>())
+                .map_err(|e| e.to_string())?;
+            entries.push(NetworkEntry {
+                status: self.status(&network)?,
+                network,
+                profiles,
+            });
+        }
+        Ok(entries)
+    }
+
+    /// Delete a network and its managed file. Networks still assigned to a profile are kept.
+    pub fn remove(&self, id: i64) -> Result<bool, String> {
+        let Some(network) = self.get(id)? else { return Ok(false) };
+        let users: i64 = self
+            .conn
+            .query_row(
+                "SELECT COUNT(*) FROM profile_networks pn JOIN engine_profiles p ON p.id = pn.profile_id WHERE pn.network_id = ?1",
+                [id],
+                |row| row.get(0),
+            )
+            .map_err(|e| e.to_string())?;
+        if users > 0 {
+            return Err(format!("Network '{}' is used by {} engine profile(s)", network.name, users));
+        }
+        self.conn.execute("DELETE FROM profile_networks WHERE network_id = ?1", [id]).map_err(|e| e.to_string())?;
+        self.conn.execute("DELETE FROM networks WHERE id = ?1", [id]).map_err(|e| e.to_string())?;
+        let path = Path::new(&network.path);
+        let _ = fs::remove_file(path);
+        if let Some(dir) = path.parent().filter(|d| d.starts_with(&self.dir)) {
+            let _ = fs::remove_dir(dir);
+        }
+        Ok(true)
+    }
+
+    /// Use a network for an engine profile, or stop managing its network with `None`.
+    /// `option_name` is the engine option that takes the path, `EvalFile` by default.
+    pub fn assign(&self, profile_id: i64, network_id: Option<i64>, option_name: Option<&str>) -> Result<(), String> {
+        match network_id {
+            Some(network_id) => {
+                self.get(network_id)?.ok_or_else(|| format!("No network with id {}", network_id))?;
+                self.conn
+                    .execute(
+                        "INSERT OR REPLACE INTO profile_networks (profile_id, network_id, option_name) VALUES (?1, ?2, ?3)",
+                        rusqlite::params![profile_id, network_id, option_name.unwrap_or(DEFAULT_NETWORK_OPTION)],
+                    )
+                    .map_err(|e| e.to_string())?;
+            }
+            None => {
+                self.conn
+                    .execute("DELETE FROM profile_networks WHERE profile_id = ?1", [profile_id])
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The network assigned to a profile and the option it is set through.
+    pub fn assignment(&self, profile_id: i64) -> Result<Option<(Network, String)>, String> {
+        let row: Option<(i64, String)> = self
+            .conn
+            .query_row(
+                "SELECT network_id, option_name FROM profile_networks WHERE profile_id = ?1",
+                [profile_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .map_err(|e| e.to_string())?;
+        let Some((network_id, option_name)) = row else { return Ok(None) };
+        Ok(self.get(network_id)?.map(|network| (network, option_name)))
+    }
+
+    /// The option setting that loads the profile's network, after checking the file is intact.
+    /// Errors name the network and what is wrong with it.
+    pub fn eval_file_option(&self, profile_id: i64) -> Result<Option<EngineOptionSetting>, String> {
+        let Some((network, option_name)) = self.assignment(profile_id)? else { return Ok(None) };
+        match self.status(&network)? {
+            NetworkStatus::Ok => Ok(Some(EngineOptionSetting {
+                name: option_name,
+                value: OptionValue::String(network.path),
+            })),
+            NetworkStatus::Missing => Err(format!("Network '{}' is missing: {}", network.name, network.path)),
+            NetworkStatus::Changed { .. } => Err(format!(
+                "Network '{}' has changed since it was imported: {}",
+                network.name, network.path
+            )),
+        }
+    }
+
+    /// Compare the managed file with the hash it was imported with. Like engine binaries, the
+    /// file is only hashed again when its size or modification time changed.
+    pub fn status(&self, network: &Network) -> Result<NetworkStatus, String> {
+        let Ok(metadata) = fs::metadata(&network.path) else {
+            return Ok(NetworkStatus::Missing);
+        };
+        let mtime: i64 = self
+            .conn
+            .query_row("SELECT file_mtime FROM networks WHERE id = ?1", [network.id], |row| row.get(0))
+            .map_err(|e| e.to_string())?;
+        if metadata.len() == network.size && modified_secs(&metadata) == mtime {
+            return Ok(NetworkStatus::Ok);
+        }
+        let actual = profiles::fingerprint(&network.path)?;
+        if actual != network.sha256 {
+            return Ok(NetworkStatus::Changed { actual });
+        }
+        self.conn
+            .execute(
+                "UPDATE networks SET size = ?2, file_mtime = ?3 WHERE id = ?1",
+                rusqlite::params![network.id, metadata.len() as i64, modified_secs(&metadata)],
+            )
+            .map_err(|e| e.to_string())?;
+        Ok(NetworkStatus::Ok)
+    }
+
+    pub fn get(&self, id: i64) -> Result<Option<Network>, String> {
+        let networks = self.query_networks("SELECT id, name, path, sha256, size, imported_at FROM networks WHERE id = ?1", [id])?;
+        Ok(networks.into_iter().next())
+    }
+
+    fn find_by_hash(&self, sha256: &str) -> Result<Option<Network>, String> {
+        let networks =
+            self.query_networks("SELECT id, name, path, sha256, size, imported_at FROM networks WHERE sha256 = ?1", [sha256])?;
+        Ok(networks.into_iter().next())
+    }
+
+    fn query_networks<P: rusqlite::Params>(&self, sql: &str, params: P) -> Result<Vec<Network>, String> {
+        let mut stmt = self.conn.prepare(sql).map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map(params, |row| {
+                Ok(Network {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    path: row.get(2)?,
+                    sha256: row.get(3)?,
+                    size: row.get::<_, i64>(4)? as u64,
+                    imported_at: row.get(5)?,
+                })
+            })
+            .map_err(|e| e.to_string())?;
+        rows.collect::<rusqlite::Result<_>>().map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profiles::EngineProfileInput;
+
+    // A fresh directory with a network file and a registered engine profile
+    fn setup(name: &str) -> (PathBuf, NetworkManager, i64) {
+        let dir = std::env::temp_dir().join(format!("jieqibox-networks-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("pikafish.nnue"), "weights").unwrap();
+        fs::write(dir.join("engine"), "binary").unwrap();
+        let db = dir.join("profiles.db");
+        let profile = EngineProfileStore::new(&db)
+            .unwrap()
+            .add(&EngineProfileInput {
+                name: "Pikafish".into(),
+                path: dir.join("engine").to_string_lossy().into_owned(),
+                args: Vec::new(),
+                protocol: Default::default(),
+                encoding: Default::default(),
+                options: Vec::new(),
+            })
+            .unwrap();
+        let manager = NetworkManager::new(&db, dir.join("networks")).unwrap();
+        (dir, manager, profile.id)
+    }
+
+    #[test]
+    fn the_same_content_is_imported_once() {
+        let (dir, manager, _) = setup("import");
+        let source = dir.join("pikafish.nnue");
+        let first = manager.import(source.to_str().unwrap(), None).unwrap();
+        assert_eq!(first.name, "pikafish");
+        assert!(first.path.ends_with("pikafish.nnue") && first.path.starts_with(dir.join("networks").to_str().unwrap()));
+
+        let copy = dir.join("copy.nnue");
+        fs::copy(&source, &copy).unwrap();
+        let second = manager.import(copy.to_str().unwrap(), Some("Other name")).unwrap();
+        assert_eq!((second.id, second.path.as_str()), (first.id, first.path.as_str()));
+        assert_eq!(manager.list().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn assigned_networks_cannot_be_removed() {
+        let (dir, manager, profile_id) = setup("remove");
+        let network = manager.import(dir.join("pikafish.nnue").to_str().unwrap(), None).unwrap();
+        manager.assign(profile_id, Some(network.id), None).unwrap();
+        assert_eq!(manager.list().unwrap()[0].profiles, [profile_id]);
+        assert!(manager.remove(network.id).unwrap_err().contains("used by 1"));
+
+        manager.assign(profile_id, None, None).unwrap();
+        assert!(manager.remove(network.id).unwrap());
+        assert!(!Path::new(&network.path).exists());
+        assert!(!manager.remove(network.id).unwrap());
+    }
+
+    #[test]
+    fn eval_file_option_checks_the_file() {
+        let (dir, manager, profile_id) = setup("option");
+        assert_eq!(manager.eval_file_option(profile_id).unwrap(), None);
+        let network = manager.import(dir.join("pikafish.nnue").to_str().unwrap(), None).unwrap();
+        manager.assign(profile_id, Some(network.id), Some("NNUEFile")).unwrap();
+        let option = manager.eval_file_option(profile_id).unwrap().unwrap();
+        assert_eq!(option.command(), format!("setoption name NNUEFile value {}", network.path));
+
+        fs::write(&network.path, "other weights").unwrap();
+        assert!(manager.eval_file_option(profile_id).unwrap_err().contains("has changed"));
+        fs::remove_file(&network.path).unwrap();
+        assert!(manager.eval_file_option(profile_id).unwrap_err().contains("is missing"));
+    }
+}
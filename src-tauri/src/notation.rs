@@ -0,0 +1,79 @@
+// Game notation JSON, see NOTATION_FORMAT.md. Unknown fields are kept so files round-trip.
+use serde::{Deserialize, Serialize};
+
+// Mate scores are stored as +/-(MATE_SCORE_BASE - ply), matching the frontend
+pub const MATE_SCORE_BASE: i32 = 30000;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotationMetadata {
+    #[serde(default)]
+    pub event: String,
+    #[serde(default)]
+    pub site: String,
+    #[serde(default)]
+    pub date: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub round: Option<String>,
+    #[serde(default)]
+    pub white: String,
+    #[serde(default)]
+    pub black: String,
+    #[serde(default)]
+    pub result: String,
+    #[serde(default)]
+    pub initial_fen: String,
+    #[serde(default)]
+    pub flip_mode: String,
+    #[serde(default)]
+    pub current_fen: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub opening_comment: Option<String>,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotationMove {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub data: String,
+    #[serde(default)]
+    pub fen: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotation: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub engine_score: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub engine_time: Option<u64>,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GameNotation {
+    pub metadata: NotationMetadata,
+    pub moves: Vec<NotationMove>,
+}
+
+impl GameNotation {
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|e| format!("Invalid game notation: {}", e))
+    }
+
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(self).map_err(|e| e.to_string())
+    }
+}
+
+/// Convert an engine score to the notation encoding (centipawns, or MATE_SCORE_BASE-based for mates).
+pub fn encode_score(score: &crate::uci::Score) -> i32 {
+    match *score {
+        crate::uci::Score::Cp(cp) => cp,
+        crate::uci::Score::Mate(ply) if ply >= 0 => MATE_SCORE_BASE - ply,
+        crate::uci::Score::Mate(ply) => -(MATE_SCORE_BASE + ply),
+    }
+}
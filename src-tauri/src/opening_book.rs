@@ -1,8 +1,38 @@
-use rusqlite::{Connection, Result};
+use rusqlite::{Connection, OptionalExtension, Result};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 use std::path::Path;
+use std::str::FromStr;
+
+// Schema changes, applied in order; the database's `user_version` is the number already applied.
+// Books from before versioning are version 0 and may already have some of these tables, so every
+// step must also work on them. Append new migrations; never edit one that has shipped.
+const MIGRATIONS: &[&str] = &[
+    // 1: moves by position key
+    r#"
+    CREATE TABLE IF NOT EXISTS openings (
+        key      BLOB NOT NULL,
+        move     INTEGER NOT NULL,
+        priority INTEGER NOT NULL,
+        wins     INTEGER NOT NULL,
+        draws    INTEGER NOT NULL,
+        losses   INTEGER NOT NULL,
+        allowed  INTEGER NOT NULL,
+        comment  TEXT,
+        PRIMARY KEY (key, move)
+    );
+    "#,
+    // 2: the canonical FEN each key was computed from
+    r#"
+    CREATE TABLE IF NOT EXISTS positions (
+        key      BLOB PRIMARY KEY,
+        fen      TEXT NOT NULL
+    );
+    "#,
+];
+
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MoveData {
@@ -30,6 +60,7 @@ pub struct AddEntryRequest {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OpeningBookEntry {
     pub key: String,
+    #[serde(default)]
     pub fen: String,
     pub moves: Vec<MoveData>,
 }
@@ -40,6 +71,122 @@ pub struct OpeningBookStats {
     pub total_moves: i64,
     pub allowed_moves: i64,
     pub disallowed_moves: i64,
+    /// Positions whose FEN is unknown, from books written before FENs were stored.
+    #[serde(default)]
+    pub positions_without_fen: i64,
+}
+
+/// What an import does with (key, move) rows the book already has.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportMode {
+    /// The imported row overwrites the existing one.
+    #[default]
+    Replace,
+    /// The existing row is kept.
+    SkipExisting,
+    /// Wins, draws and losses are added to the existing row and the higher priority is kept.
+    MergeStatistics,
+}
+
+impl FromStr for ImportMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().replace('-', "_").as_str() {
+            "replace" => Ok(ImportMode::Replace),
+            "skip_existing" => Ok(ImportMode::SkipExisting),
+            "merge_statistics" | "merge" => Ok(ImportMode::MergeStatistics),
+            _ => Err(format!("Unknown import mode '{}' (expected replace, skip-existing or merge-statistics)", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImportReport {
+    /// Moves inserted or updated.
+    pub imported: usize,
+    /// Moves left alone because they already existed (skip-existing mode).
+    pub skipped: usize,
+    /// One message per entry that was not imported.
+    pub errors: Vec<String>,
+}
+
+/// Which row wins when both books have the same move. The other merge settings then override
+/// single fields of it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RowMerge {
+    #[default]
+    KeepLocal,
+    TakeIncoming,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CountMerge {
+    /// Take wins, draws and losses from the winning row.
+    #[default]
+    Row,
+    /// Add both books' wins, draws and losses.
+    Add,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PriorityMerge {
+    #[default]
+    Row,
+    Max,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AllowedMerge {
+    #[default]
+    Row,
+    /// Allowed if either book allows it.
+    Or,
+    /// Allowed only if both books allow it.
+    And,
+}
+
+/// How `merge_database` combines moves present in both books. The default changes nothing
+/// that is already in the local book and only adds what is new.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MergeOptions {
+    #[serde(default)]
+    pub rows: RowMerge,
+    #[serde(default)]
+    pub counts: CountMerge,
+    #[serde(default)]
+    pub priority: PriorityMerge,
+    #[serde(default)]
+    pub allowed: AllowedMerge,
+}
+
+/// A move both books have with different data. Moves are in the stored FEN's coordinates.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MergeConflict {
+    pub key: String,
+    /// Empty when neither book knows the position's FEN.
+    pub fen: String,
+    pub local: MoveData,
+    pub incoming: MoveData,
+    pub merged: MoveData,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MergeReport {
+    /// Moves only in the incoming book.
+    pub added: usize,
+    /// Moves in both books with identical data.
+    pub unchanged: usize,
+    pub conflicts: Vec<MergeConflict>,
+    /// Positions whose FEN came from the incoming book.
+    pub fens_added: usize,
+    /// False for a dry run: the report shows what a merge would do, but nothing was written.
+    pub committed: bool,
 }
 
 pub struct JieqiOpeningBook {
@@ -47,39 +194,75 @@ pub struct JieqiOpeningBook {
 }
 
 impl JieqiOpeningBook {
+    /// Open a book, upgrading its schema first. A book with data is copied to a backup file
+    /// before it is migrated.
     pub fn new<P: AsRef<Path>>(db_path: P) -> Result<Self> {
-        let conn = Connection::open(db_path)?;
+        Self::open(db_path, true)
+    }
+
+    /// Open a book, upgrading its schema first. With `backup`, a book that has data is copied to
+    /// `<file>.v<old version>-<timestamp>.bak` before any migration runs. Books written by a newer
+    /// version of the application are refused rather than modified.
+    pub fn open<P: AsRef<Path>>(db_path: P, backup: bool) -> Result<Self> {
+        let conn = Connection::open(&db_path)?;
         let book = JieqiOpeningBook { conn };
-        book.initialize_database()?;
+        let version = book.schema_version()?;
+        if version > SCHEMA_VERSION {
+            return Err(book_error(format!(
+                "Opening book schema version {} is newer than the supported version {}; update the application to open it",
+                version, SCHEMA_VERSION
+            )));
+        }
+        if version < SCHEMA_VERSION {
+            if backup && book.has_tables()? {
+                book.backup_before_migration(db_path.as_ref(), version)?;
+            }
+            book.migrate(version)?;
+        }
         Ok(book)
     }
 
-    fn initialize_database(&self) -> Result<()> {
-        self.conn.execute(
-            r#"
-            CREATE TABLE IF NOT EXISTS openings (
-                key      BLOB NOT NULL,
-                move     INTEGER NOT NULL,
-                priority INTEGER NOT NULL,
-                wins     INTEGER NOT NULL,
-                draws    INTEGER NOT NULL,
-                losses   INTEGER NOT NULL,
-                allowed  INTEGER NOT NULL,
-                comment  TEXT,
-                PRIMARY KEY (key, move)
-            );
-            "#,
-            [],
-        )?;
+    /// The schema version stored in the database (`PRAGMA user_version`).
+    pub fn schema_version(&self) -> Result<u32> {
+        self.conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+    }
+
+    // Apply the migrations after `from` in order, all or none
+    fn migrate(&self, from: u32) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        for migration in &MIGRATIONS[from as usize..] {
+            tx.execute_batch(migration)?;
+        }
+        tx.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        tx.commit()
+    }
+
+    fn has_tables(&self) -> Result<bool> {
+        self.conn
+            .query_row("SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table')", [], |row| row.get(0))
+    }
+
+    fn backup_before_migration(&self, db_path: &Path, version: u32) -> Result<()> {
+        let mut name = db_path.file_name().unwrap_or_default().to_os_string();
+        name.push(format!(".v{}-{}.bak", version, chrono::Local::now().format("%Y%m%d%H%M%S")));
+        let backup = db_path.with_file_name(name);
+        self.conn.execute("VACUUM INTO ?1", [backup.to_string_lossy()])?;
         Ok(())
     }
 
     pub fn add_entry(&self, request: &AddEntryRequest) -> Result<bool> {
-        let (key_blob, transform_idx) = compute_key_and_transform(&request.fen);
+        let (key_blob, transform_idx, canonical) = canonical_position(&request.fen);
         let transformed_uci = transform_uci_move(&request.uci_move, transform_idx);
         let move_int = uci_to_int(&transformed_uci) as i64;
 
-        self.conn.execute(
+        // The position and its move are written together or not at all
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "INSERT OR REPLACE INTO positions (key, fen) VALUES (?1, ?2)",
+            rusqlite::params![&key_blob, &canonical],
+        )?;
+
+        tx.execute(
             r#"
             INSERT INTO openings (key, move, priority, wins, draws, losses, allowed, comment)
             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
@@ -102,6 +285,7 @@ impl JieqiOpeningBook {
                 &request.comment,
             ],
         )?;
+        tx.commit()?;
 
         Ok(true)
     }
@@ -111,10 +295,16 @@ impl JieqiOpeningBook {
         let transformed_uci = transform_uci_move(uci_move, transform_idx);
         let move_int = uci_to_int(&transformed_uci) as i64;
 
-        let affected_rows = self.conn.execute(
+        let tx = self.conn.unchecked_transaction()?;
+        let affected_rows = tx.execute(
             "DELETE FROM openings WHERE key = ?1 AND move = ?2",
             rusqlite::params![&key_blob, move_int],
         )?;
+        tx.execute(
+            "DELETE FROM positions WHERE key = ?1 AND NOT EXISTS (SELECT 1 FROM openings WHERE key = ?1)",
+            rusqlite::params![&key_blob],
+        )?;
+        tx.commit()?;
 
         Ok(affected_rows > 0)
     }
@@ -153,7 +343,8 @@ impl JieqiOpeningBook {
 
     pub fn get_stats(&self) -> Result<OpeningBookStats> {
         let mut stmt = self.conn.prepare(
-            "SELECT COUNT(DISTINCT key), COUNT(*), COALESCE(SUM(CASE WHEN allowed = 1 THEN 1 ELSE 0 END), 0), COALESCE(SUM(CASE WHEN allowed = 0 THEN 1 ELSE 0 END), 0) FROM openings"
+            "SELECT COUNT(DISTINCT key), COUNT(*), COALESCE(SUM(CASE WHEN allowed = 1 THEN 1 ELSE 0 END), 0), COALESCE(SUM(CASE WHEN allowed = 0 THEN 1 ELSE 0 END), 0),
+             (SELECT COUNT(DISTINCT o.key) FROM openings o LEFT JOIN positions p ON p.key = o.key WHERE p.key IS NULL) FROM openings"
         )?;
 
         let stats = stmt.query_row([], |row| {
@@ -162,6 +353,7 @@ impl JieqiOpeningBook {
                 total_moves: row.get(1)?,
                 allowed_moves: row.get(2)?,
                 disallowed_moves: row.get(3)?,
+                positions_without_fen: row.get(4)?,
             })
         })?;
 
@@ -169,7 +361,7 @@ impl JieqiOpeningBook {
     }
 
     pub fn clear_all(&self) -> Result<()> {
-        self.conn.execute("DELETE FROM openings", [])?;
+        self.conn.execute_batch("DELETE FROM openings; DELETE FROM positions;")?;
         Ok(())
     }
 
@@ -177,7 +369,8 @@ impl JieqiOpeningBook {
         let mut entries: HashMap<String, OpeningBookEntry> = HashMap::new();
 
         let mut stmt = self.conn.prepare(
-            "SELECT key, move, priority, wins, draws, losses, allowed, comment FROM openings",
+            "SELECT o.key, o.move, o.priority, o.wins, o.draws, o.losses, o.allowed, o.comment, p.fen
+             FROM openings o LEFT JOIN positions p ON p.key = o.key",
         )?;
         let entry_iter = stmt.query_map([], |row| {
             let key_blob: Vec<u8> = row.get(0)?;
@@ -191,16 +384,17 @@ impl JieqiOpeningBook {
                 allowed: row.get::<_, i32>(6)? == 1,
                 comment: row.get::<_, Option<String>>(7)?.unwrap_or_default(),
             };
-            Ok((key_hex, move_data))
+            Ok((key_hex, row.get::<_, Option<String>>(8)?, move_data))
         })?;
 
         for entry_result in entry_iter {
-            let (key, move_data) = entry_result?;
+            let (key, fen, move_data) = entry_result?;
             let entry = entries
                 .entry(key.clone())
                 .or_insert_with(|| OpeningBookEntry {
                     key: key.clone(),
-                    fen: String::new(), // We don't store original FEN, only key
+                    // Empty for positions from books that predate the positions table
+                    fen: fen.unwrap_or_default(),
                     moves: Vec::new(),
                 });
             entry.moves.push(move_data);
@@ -208,11 +402,439 @@ impl JieqiOpeningBook {
 
         // Sort moves by priority for each entry
         for entry in entries.values_mut() {
-            entry.moves.sort_by(|a, b| b.priority.cmp(&a.priority));
+            entry.moves.sort_by_key(|m| std::cmp::Reverse(m.priority));
         }
 
         Ok(entries.into_values().collect())
     }
+
+    /// Insert entries produced by `export_all`. Keys and moves are already normalized, so they are
+    /// stored as-is. Entries that fail validation are left out and reported; everything else is
+    /// written in one transaction, with `mode` deciding what happens to (key, move) rows that
+    /// already exist.
+    pub fn import_entries(&self, entries: &[OpeningBookEntry], mode: ImportMode) -> Result<ImportReport> {
+        let located: Vec<(String, &OpeningBookEntry)> =
+            entries.iter().enumerate().map(|(i, entry)| (format!("entry {}", i + 1), entry)).collect();
+        self.write_entries(&located, mode, Vec::new())
+    }
+
+    /// Import a book given as exported JSON (an array of entries) or as JSON lines, one entry
+    /// per line. Entries that cannot be parsed are reported alongside the invalid ones.
+    pub fn import_data(&self, data: &str, mode: ImportMode) -> Result<ImportReport> {
+        let (entries, errors) = parse_entries(data);
+        let located: Vec<(String, &OpeningBookEntry)> = entries.iter().map(|(at, entry)| (at.clone(), entry)).collect();
+        self.write_entries(&located, mode, errors)
+    }
+
+    /// Merge the book at `source` into this one, combining moves by position key and move.
+    /// Moves only in the source are added; moves in both are combined as `options` say. With
+    /// `dry_run` the merge runs in a transaction that is rolled back, so the report lists the
+    /// conflicts exactly as a real merge would resolve them. The source file is only read.
+    pub fn merge_database(&self, source: &str, options: MergeOptions, dry_run: bool) -> Result<MergeReport> {
+        // ATTACH would create a missing file
+        if !Path::new(source).is_file() {
+            return Err(book_error(format!("No opening book at {}", source)));
+        }
+        self.conn.execute("ATTACH DATABASE ?1 AS incoming", [source])?;
+        let report = self.merge_attached(options, dry_run);
+        let detached = self.conn.execute("DETACH DATABASE incoming", []);
+        let report = report?;
+        detached?;
+        Ok(report)
+    }
+
+    fn merge_attached(&self, options: MergeOptions, dry_run: bool) -> Result<MergeReport> {
+        let version: u32 = self.conn.query_row("PRAGMA incoming.user_version", [], |row| row.get(0))?;
+        if version > SCHEMA_VERSION {
+            return Err(book_error(format!(
+                "The incoming book has schema version {}, newer than the supported version {}",
+                version, SCHEMA_VERSION
+            )));
+        }
+        let has_table = |name: &str| -> Result<bool> {
+            self.conn.query_row(
+                "SELECT EXISTS (SELECT 1 FROM incoming.sqlite_master WHERE type = 'table' AND name = ?1)",
+                [name],
+                |row| row.get(0),
+            )
+        };
+        if !has_table("openings")? {
+            return Err(book_error("The incoming file is not an opening book".to_string()));
+        }
+        // Books from before FENs were stored have no positions table
+        let incoming_positions = has_table("positions")?;
+
+        let tx = self.conn.unchecked_transaction()?;
+        let mut report = MergeReport::default();
+        // Read every shared move before writing any, so no update lands on the table being scanned
+        let shared = {
+            let mut stmt = tx.prepare(&format!(
+                "SELECT l.key, l.move, l.priority, l.wins, l.draws, l.losses, l.allowed, l.comment,
+                        i.priority, i.wins, i.draws, i.losses, i.allowed, i.comment, {}
+                 FROM main.openings l
+                 JOIN incoming.openings i ON i.key = l.key AND i.move = l.move
+                 LEFT JOIN main.positions lp ON lp.key = l.key
+                 {}
+                 ORDER BY l.key, l.move",
+                if incoming_positions { "COALESCE(lp.fen, ip.fen)" } else { "lp.fen" },
+                if incoming_positions { "LEFT JOIN incoming.positions ip ON ip.key = l.key" } else { "" },
+            ))?;
+            let rows = stmt.query_map([], |row| {
+                let uci_move = int_to_uci(row.get::<_, i32>(1)? as u16);
+                let move_data = |at: usize| -> Result<MoveData> {
+                    Ok(MoveData {
+                        uci_move: uci_move.clone(),
+                        priority: row.get(at)?,
+                        wins: row.get(at + 1)?,
+                        draws: row.get(at + 2)?,
+                        losses: row.get(at + 3)?,
+                        allowed: row.get::<_, i32>(at + 4)? == 1,
+                        comment: row.get::<_, Option<String>>(at + 5)?.unwrap_or_default(),
+                    })
+                };
+                Ok((
+                    row.get::<_, Vec<u8>>(0)?,
+                    row.get::<_, i64>(1)?,
+                    move_data(2)?,
+                    move_data(8)?,
+                    row.get::<_, Option<String>>(14)?,
+                ))
+            })?;
+            rows.collect::<Result<Vec<_>>>()?
+        };
+        {
+            let mut update = tx.prepare(
+                "UPDATE main.openings SET priority = ?3, wins = ?4, draws = ?5, losses = ?6, allowed = ?7, comment = ?8
+                 WHERE key = ?1 AND move = ?2",
+            )?;
+            for (key_blob, move_int, local, incoming, fen) in shared {
+                if same_move_data(&local, &incoming) {
+                    report.unchanged += 1;
+                    continue;
+                }
+                let merged = merge_move(&local, &incoming, options);
+                if !same_move_data(&merged, &local) {
+                    update.execute(rusqlite::params![
+                        &key_blob,
+                        move_int,
+                        merged.priority,
+                        merged.wins,
+                        merged.draws,
+                        merged.losses,
+                        if merged.allowed { 1 } else { 0 },
+                        &merged.comment,
+                    ])?;
+                }
+                report.conflicts.push(MergeConflict {
+                    key: hex::encode(&key_blob),
+                    fen: fen.unwrap_or_default(),
+                    local,
+                    incoming,
+                    merged,
+                });
+            }
+        }
+        // `WHERE true` keeps SQLite from reading ON CONFLICT as part of the SELECT
+        report.added = tx.execute(
+            "INSERT INTO main.openings (key, move, priority, wins, draws, losses, allowed, comment)
+             SELECT key, move, priority, wins, draws, losses, allowed, comment FROM incoming.openings
+             WHERE true ON CONFLICT(key, move) DO NOTHING",
+            [],
+        )?;
+        if incoming_positions {
+            report.fens_added = tx.execute(
+                "INSERT INTO main.positions (key, fen)
+                 SELECT key, fen FROM incoming.positions WHERE key IN (SELECT key FROM incoming.openings)
+                 ON CONFLICT(key) DO NOTHING",
+                [],
+            )?;
+        }
+        if dry_run {
+            tx.rollback()?;
+        } else {
+            tx.commit()?;
+            report.committed = true;
+        }
+        Ok(report)
+    }
+
+    /// A position by its key, with its stored FEN and its moves in that FEN's coordinates.
+    pub fn get_position(&self, key: &str) -> Result<Option<OpeningBookEntry>> {
+        let Ok(key_blob) = hex::decode(key) else { return Ok(None) };
+        let fen: Option<String> = self
+            .conn
+            .query_row("SELECT fen FROM positions WHERE key = ?1", [&key_blob], |row| row.get(0))
+            .optional()?;
+        let mut stmt = self.conn.prepare(
+            "SELECT move, priority, wins, draws, losses, allowed, comment FROM openings WHERE key = ?1 ORDER BY priority DESC",
+        )?;
+        let moves = stmt
+            .query_map([&key_blob], |row| {
+                Ok(MoveData {
+                    uci_move: int_to_uci(row.get::<_, i32>(0)? as u16),
+                    priority: row.get(1)?,
+                    wins: row.get(2)?,
+                    draws: row.get(3)?,
+                    losses: row.get(4)?,
+                    allowed: row.get::<_, i32>(5)? == 1,
+                    comment: row.get::<_, Option<String>>(6)?.unwrap_or_default(),
+                })
+            })?
+            .collect::<Result<Vec<_>>>()?;
+        if moves.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(OpeningBookEntry {
+            key: hex::encode(&key_blob),
+            fen: fen.unwrap_or_default(),
+            moves,
+        }))
+    }
+
+    /// Keys (hex) of positions with moves but no FEN, left by books written before FENs were stored.
+    pub fn keys_without_fen(&self) -> Result<Vec<String>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT DISTINCT o.key FROM openings o LEFT JOIN positions p ON p.key = o.key WHERE p.key IS NULL ORDER BY o.key",
+        )?;
+        let keys = stmt
+            .query_map([], |row| row.get::<_, Vec<u8>>(0))?
+            .map(|key| key.map(hex::encode))
+            .collect();
+        keys
+    }
+
+    /// Fill in missing FENs from known positions, e.g. those of saved games. FENs whose key is not
+    /// in the book, or already has a FEN, are ignored. Returns the number of positions filled in.
+    pub fn backfill_fens<S: AsRef<str>>(&self, fens: &[S]) -> Result<usize> {
+        let tx = self.conn.unchecked_transaction()?;
+        let mut filled = 0;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO positions (key, fen) SELECT ?1, ?2
+                 WHERE EXISTS (SELECT 1 FROM openings WHERE key = ?1) AND NOT EXISTS (SELECT 1 FROM positions WHERE key = ?1)",
+            )?;
+            for fen in fens {
+                let (key_blob, _, canonical) = canonical_position(fen.as_ref());
+                filled += stmt.execute(rusqlite::params![&key_blob, canonical])?;
+            }
+        }
+        tx.commit()?;
+        Ok(filled)
+    }
+
+    /// Set the priority of each (key, move) to `100 × (wins + ½ draws) / games`, where `games` is
+    /// the total of wins, draws and losses of every move in the position: the move's share of the
+    /// position's games times its score. Moves without results keep their priority. Keys are hex
+    /// and, like the moves, canonical. Returns the number of moves updated.
+    pub fn rescore_from_results(&self, moves: &[(String, String)]) -> Result<usize> {
+        let tx = self.conn.unchecked_transaction()?;
+        let mut updated = 0;
+        {
+            let mut stmt = tx.prepare(
+                "UPDATE openings SET priority = CAST(ROUND(100.0 * (wins + 0.5 * draws)
+                     / (SELECT SUM(o.wins + o.draws + o.losses) FROM openings o WHERE o.key = openings.key)) AS INTEGER)
+                 WHERE key = ?1 AND move = ?2 AND wins + draws + losses > 0",
+            )?;
+            for (key, uci_move) in moves {
+                let key_blob = hex::decode(key).map_err(|e| book_error(format!("Invalid key {}: {}", key, e)))?;
+                updated += stmt.execute(rusqlite::params![key_blob, uci_to_int(uci_move) as i64])?;
+            }
+        }
+        tx.commit()?;
+        Ok(updated)
+    }
+
+    // `entries` carry where they came from, for the error messages
+    fn write_entries(
+        &self,
+        entries: &[(String, &OpeningBookEntry)],
+        mode: ImportMode,
+        errors: Vec<String>,
+    ) -> Result<ImportReport> {
+        let mut report = ImportReport {
+            errors,
+            ..ImportReport::default()
+        };
+        let tx = self.conn.unchecked_transaction()?;
+        let sql = match mode {
+            ImportMode::Replace => {
+                r#"
+                INSERT INTO openings (key, move, priority, wins, draws, losses, allowed, comment)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+                ON CONFLICT(key, move) DO UPDATE SET
+                    priority=excluded.priority,
+                    wins=excluded.wins,
+                    draws=excluded.draws,
+                    losses=excluded.losses,
+                    allowed=excluded.allowed,
+                    comment=excluded.comment;
+                "#
+            }
+            ImportMode::SkipExisting => {
+                r#"
+                INSERT INTO openings (key, move, priority, wins, draws, losses, allowed, comment)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+                ON CONFLICT(key, move) DO NOTHING;
+                "#
+            }
+            // Results are added up; the local allowed flag is kept and its comment only filled in
+            ImportMode::MergeStatistics => {
+                r#"
+                INSERT INTO openings (key, move, priority, wins, draws, losses, allowed, comment)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+                ON CONFLICT(key, move) DO UPDATE SET
+                    priority=MAX(priority, excluded.priority),
+                    wins=wins + excluded.wins,
+                    draws=draws + excluded.draws,
+                    losses=losses + excluded.losses,
+                    comment=CASE WHEN COALESCE(comment, '') = '' THEN excluded.comment ELSE comment END;
+                "#
+            }
+        };
+        {
+            let mut stmt = tx.prepare(sql)?;
+            let mut position_stmt = tx.prepare("INSERT OR IGNORE INTO positions (key, fen) VALUES (?1, ?2)")?;
+            for (location, entry) in entries {
+                let (key_blob, canonical) = match validate_entry(entry) {
+                    Ok(validated) => validated,
+                    Err(error) => {
+                        report.errors.push(format!("{}: {}", location, error));
+                        continue;
+                    }
+                };
+                if let Some(fen) = canonical {
+                    position_stmt.execute(rusqlite::params![&key_blob, fen])?;
+                }
+                for m in &entry.moves {
+                    let written = stmt.execute(rusqlite::params![
+                        &key_blob,
+                        uci_to_int(&m.uci_move) as i64,
+                        m.priority,
+                        m.wins,
+                        m.draws,
+                        m.losses,
+                        if m.allowed { 1 } else { 0 },
+                        &m.comment,
+                    ])?;
+                    if written > 0 {
+                        report.imported += 1;
+                    } else {
+                        report.skipped += 1;
+                    }
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(report)
+    }
+}
+
+// Errors of our own (validation, not SQLite failures). The wrapped message is shown as is, with
+// no SQLite error code attached.
+fn book_error(message: String) -> rusqlite::Error {
+    rusqlite::Error::ToSqlConversionFailure(message.into())
+}
+
+fn same_move_data(a: &MoveData, b: &MoveData) -> bool {
+    (a.priority, a.wins, a.draws, a.losses, a.allowed, &a.comment) == (b.priority, b.wins, b.draws, b.losses, b.allowed, &b.comment)
+}
+
+fn merge_move(local: &MoveData, incoming: &MoveData, options: MergeOptions) -> MoveData {
+    let (base, other) = match options.rows {
+        RowMerge::KeepLocal => (local, incoming),
+        RowMerge::TakeIncoming => (incoming, local),
+    };
+    let mut merged = base.clone();
+    if merged.comment.is_empty() {
+        merged.comment = other.comment.clone();
+    }
+    if options.counts == CountMerge::Add {
+        merged.wins = local.wins.saturating_add(incoming.wins);
+        merged.draws = local.draws.saturating_add(incoming.draws);
+        merged.losses = local.losses.saturating_add(incoming.losses);
+    }
+    if options.priority == PriorityMerge::Max {
+        merged.priority = local.priority.max(incoming.priority);
+    }
+    match options.allowed {
+        AllowedMerge::Row => {}
+        AllowedMerge::Or => merged.allowed = local.allowed || incoming.allowed,
+        AllowedMerge::And => merged.allowed = local.allowed && incoming.allowed,
+    }
+    merged
+}
+
+// Entries from an exported JSON array or from JSON lines, each with where it was found.
+// Entries that do not parse are returned as errors instead, so one bad line does not stop the rest.
+fn parse_entries(data: &str) -> (Vec<(String, OpeningBookEntry)>, Vec<String>) {
+    let mut entries = Vec::new();
+    let mut errors = Vec::new();
+    if data.trim_start().starts_with('[') {
+        match serde_json::from_str::<Vec<serde_json::Value>>(data) {
+            Ok(values) => {
+                for (i, value) in values.into_iter().enumerate() {
+                    let location = format!("entry {}", i + 1);
+                    match serde_json::from_value(value) {
+                        Ok(entry) => entries.push((location, entry)),
+                        Err(e) => errors.push(format!("{}: {}", location, e)),
+                    }
+                }
+            }
+            Err(e) => errors.push(format!("invalid JSON: {}", e)),
+        }
+        return (entries, errors);
+    }
+    for (i, line) in data.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let location = format!("line {}", i + 1);
+        match serde_json::from_str(line) {
+            Ok(entry) => entries.push((location, entry)),
+            Err(e) => errors.push(format!("{}: {}", location, e)),
+        }
+    }
+    (entries, errors)
+}
+
+// Checks an imported entry and returns its key as stored in the database, with the canonical
+// FEN when the entry has one
+fn validate_entry(entry: &OpeningBookEntry) -> std::result::Result<(Vec<u8>, Option<String>), String> {
+    let key_blob = match hex::decode(&entry.key) {
+        Ok(blob) if blob.len() == 12 => blob,
+        _ => return Err(format!("invalid key '{}', expected 24 hex digits", entry.key)),
+    };
+    let canonical = if entry.fen.trim().is_empty() {
+        None
+    } else {
+        let (fen_key, _, canonical) = canonical_position(&entry.fen);
+        if fen_key != key_blob {
+            return Err(format!("key {} does not match its FEN '{}'", entry.key, entry.fen));
+        }
+        Some(canonical)
+    };
+    if entry.moves.is_empty() {
+        return Err(format!("key {} has no moves", entry.key));
+    }
+    for m in &entry.moves {
+        if !is_valid_uci(&m.uci_move) {
+            return Err(format!("key {}: invalid move '{}'", entry.key, m.uci_move));
+        }
+        if m.wins < 0 || m.draws < 0 || m.losses < 0 {
+            return Err(format!("key {}: move {} has negative results", entry.key, m.uci_move));
+        }
+    }
+    Ok((key_blob, canonical))
+}
+
+pub(crate) fn is_valid_uci(uci: &str) -> bool {
+    let b = uci.as_bytes();
+    b.len() == 4
+        && (b'a'..=b'i').contains(&b[0])
+        && b[1].is_ascii_digit()
+        && (b'a'..=b'i').contains(&b[2])
+        && b[3].is_ascii_digit()
+        && b[..2] != b[2..]
 }
 
 // FEN processing functions
@@ -441,7 +1063,14 @@ fn swap_colors_fen(normalized_fen: &str) -> String {
 // Compute key value, also return the transformation index used
 // Transformation index definitions:
 // 0 = original normalized FEN; 1 = horizontal mirror; 2 = color swap (with vertical flip); 3 = color swap then horizontal mirror
-fn compute_key_and_transform(fen: &str) -> (Vec<u8>, usize) {
+pub(crate) fn compute_key_and_transform(fen: &str) -> (Vec<u8>, usize) {
+    let (key_blob, transform_idx, _) = canonical_position(fen);
+    (key_blob, transform_idx)
+}
+
+// Like `compute_key_and_transform`, plus the transformed normalized FEN the key was hashed from.
+// Stored moves are in this FEN's coordinates.
+pub(crate) fn canonical_position(fen: &str) -> (Vec<u8>, usize, String) {
     let norm_fen = normalize_fen(fen);
     let swapped_fen = swap_colors_fen(&norm_fen);
 
@@ -475,11 +1104,11 @@ fn compute_key_and_transform(fen: &str) -> (Vec<u8>, usize) {
         let byte = u8::from_str_radix(&key_hex[i..i + 2], 16).unwrap_or(0);
         key_blob.push(byte);
     }
-    (key_blob, min_idx)
+    (key_blob, min_idx, fens[min_idx].clone())
 }
 
 // Transform UCI move coordinates according to transformation index. This function is its own inverse (repeated calls with same index restore original).
-fn transform_uci_move(uci: &str, transform_idx: usize) -> String {
+pub(crate) fn transform_uci_move(uci: &str, transform_idx: usize) -> String {
     if uci.len() != 4 {
         return uci.to_string();
     }
@@ -547,3 +1176,153 @@ fn int_to_uci(move_int: u16) -> String {
 
     format!("{}{}{}{}", from_x, from_y, to_x, to_y)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    const START: &str =
+        "xxxxkxxxx/9/1x5x1/x1x1x1x1x/9/9/X1X1X1X1X/1X5X1/9/XXXXKXXXX w A2B2N2R2C2P5a2b2n2r2c2p5 - 0 1";
+
+    // The table as books were written before schema versions existed
+    const V0_SCHEMA: &str = "CREATE TABLE openings (key BLOB NOT NULL, move INTEGER NOT NULL, \
+        priority INTEGER NOT NULL, wins INTEGER NOT NULL, draws INTEGER NOT NULL, losses INTEGER NOT NULL, \
+        allowed INTEGER NOT NULL, comment TEXT, PRIMARY KEY (key, move));";
+
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("jieqibox-book-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn entry(uci_move: &str, priority: i32, wins: i32, allowed: bool, comment: &str) -> AddEntryRequest {
+        AddEntryRequest {
+            fen: START.into(),
+            uci_move: uci_move.into(),
+            priority,
+            wins,
+            draws: 1,
+            losses: 0,
+            allowed,
+            comment: comment.into(),
+        }
+    }
+
+    fn find(book: &JieqiOpeningBook, uci_move: &str) -> MoveData {
+        book.query_moves(START).unwrap().into_iter().find(|m| m.uci_move == uci_move).unwrap()
+    }
+
+    #[test]
+    fn v0_books_are_backed_up_and_migrated() {
+        let dir = dir("migrate");
+        let path = dir.join("old.jb");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(V0_SCHEMA).unwrap();
+        conn.execute_batch("INSERT INTO openings VALUES (x'000102030405060708090a0b', 1, 1, 0, 0, 0, 1, NULL);").unwrap();
+        drop(conn);
+
+        let book = JieqiOpeningBook::new(&path).unwrap();
+        assert_eq!(book.schema_version().unwrap(), SCHEMA_VERSION);
+        assert_eq!(book.get_stats().unwrap().total_moves, 1);
+        assert_eq!(book.keys_without_fen().unwrap().len(), 1);
+        drop(book);
+
+        let backups: Vec<PathBuf> = fs::read_dir(&dir)
+            .unwrap()
+            .flatten()
+            .map(|e| e.path())
+            .filter(|p| p.to_string_lossy().contains("old.jb.v0-"))
+            .collect();
+        assert_eq!(backups.len(), 1);
+        let backup = Connection::open(&backups[0]).unwrap();
+        let version: u32 = backup.query_row("PRAGMA user_version", [], |row| row.get(0)).unwrap();
+        assert_eq!(version, 0);
+
+        // Already migrated: no second backup
+        JieqiOpeningBook::new(&path).unwrap();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        Connection::open(&path).unwrap().pragma_update(None, "user_version", SCHEMA_VERSION + 1).unwrap();
+        let Err(e) = JieqiOpeningBook::new(&path) else { panic!("a newer book was opened") };
+        assert!(e.sqlite_error_code().is_none(), "{:?}", e);
+        assert!(e.to_string().starts_with(&format!("Opening book schema version {} is newer", SCHEMA_VERSION + 1)), "{}", e);
+    }
+
+    #[test]
+    fn merge_dry_run_leaves_the_book_alone() {
+        let dir = dir("merge");
+        let local = JieqiOpeningBook::new(dir.join("local.jb")).unwrap();
+        let incoming_path = dir.join("incoming.jb");
+        let incoming = JieqiOpeningBook::new(&incoming_path).unwrap();
+        local.add_entry(&entry("h2e2", 5, 3, true, "mine")).unwrap();
+        local.add_entry(&entry("b2e2", 1, 1, true, "")).unwrap();
+        incoming.add_entry(&entry("h2e2", 9, 2, false, "theirs")).unwrap();
+        incoming.add_entry(&entry("b2e2", 1, 1, true, "")).unwrap();
+        incoming.add_entry(&entry("c3c4", 2, 0, true, "")).unwrap();
+        let source = incoming_path.to_str().unwrap();
+        let options = MergeOptions {
+            rows: RowMerge::KeepLocal,
+            counts: CountMerge::Add,
+            priority: PriorityMerge::Max,
+            allowed: AllowedMerge::And,
+        };
+
+        let report = local.merge_database(source, options, true).unwrap();
+        assert_eq!((report.added, report.unchanged, report.conflicts.len(), report.committed), (1, 1, 1, false));
+        let merged = &report.conflicts[0].merged;
+        assert_eq!((merged.priority, merged.wins, merged.draws, merged.allowed), (9, 5, 2, false));
+        assert_eq!(merged.comment, "mine");
+        assert_eq!(local.query_moves(START).unwrap().len(), 2);
+        let h2e2 = find(&local, "h2e2");
+        assert_eq!((h2e2.priority, h2e2.wins, h2e2.allowed), (5, 3, true));
+
+        let report = local.merge_database(source, options, false).unwrap();
+        assert!(report.committed);
+        assert_eq!(local.query_moves(START).unwrap().len(), 3);
+        let h2e2 = find(&local, "h2e2");
+        assert_eq!((h2e2.priority, h2e2.wins, h2e2.allowed), (9, 5, false));
+    }
+
+    #[test]
+    fn import_modes_resolve_existing_moves() {
+        let dir = dir("import");
+        let book = JieqiOpeningBook::new(dir.join("book.jb")).unwrap();
+        book.add_entry(&entry("h2e2", 5, 1, true, "")).unwrap();
+        let mut exported = book.export_all().unwrap();
+        let imported = &mut exported[0].moves[0];
+        imported.priority = 9;
+        imported.wins = 2;
+        imported.draws = 0;
+        imported.allowed = false;
+        imported.comment = "imported".into();
+
+        let report = book.import_entries(&exported, ImportMode::SkipExisting).unwrap();
+        assert_eq!((report.imported, report.skipped), (0, 1));
+        assert_eq!(find(&book, "h2e2").priority, 5);
+
+        let report = book.import_entries(&exported, ImportMode::MergeStatistics).unwrap();
+        assert_eq!((report.imported, report.skipped), (1, 0));
+        let h2e2 = find(&book, "h2e2");
+        assert_eq!((h2e2.priority, h2e2.wins, h2e2.draws, h2e2.allowed), (9, 3, 1, true));
+        assert_eq!(h2e2.comment, "imported");
+
+        let report = book.import_entries(&exported, ImportMode::Replace).unwrap();
+        assert_eq!(report.imported, 1);
+        let h2e2 = find(&book, "h2e2");
+        assert_eq!((h2e2.priority, h2e2.wins, h2e2.draws, h2e2.allowed), (9, 2, 0, false));
+    }
+
+    #[test]
+    fn malformed_import_lines_are_reported() {
+        let book = JieqiOpeningBook::new(dir("lines").join("book.jb")).unwrap();
+        let data = "not json\n\n{\"key\":\"zz\",\"moves\":[]}\n";
+        let report = book.import_data(data, ImportMode::Replace).unwrap();
+        assert_eq!(report.imported, 0);
+        assert_eq!(report.errors.len(), 2);
+        assert!(report.errors[0].starts_with("line 1"), "{:?}", report.errors);
+        assert!(report.errors[1].starts_with("line 3"), "{:?}", report.errors);
+    }
+}
@@ -0,0 +1,548 @@
+// Engine profiles: registered engine binaries with their arguments, protocol, encoding and
+// saved option values. Each binary is fingerprinted with SHA-256 so an engine that was replaced
+// or rebuilt behind the GUI's back is noticed before its results are trusted.
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use crate::engine::{EngineEncoding, EngineProtocol};
+
+/// A saved option value, typed like the option the engine declared.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum OptionValue {
+    Check(bool),
+    Spin(i64),
+    Combo(String),
+    String(String),
+    Button,
+}
+
+impl OptionValue {
+    fn kind(&self) -> &'static str {
+        match self {
+            OptionValue::Check(_) => "check",
+            OptionValue::Spin(_) => "spin",
+            OptionValue::Combo(_) => "combo",
+            OptionValue::String(_) => "string",
+            OptionValue::Button => "button",
+        }
+    }
+
+    fn text(&self) -> Option<String> {
+        match self {
+            OptionValue::Check(v) => Some(v.to_string()),
+            OptionValue::Spin(v) => Some(v.to_string()),
+            OptionValue::Combo(v) | OptionValue::String(v) => Some(v.clone()),
+            OptionValue::Button => None,
+        }
+    }
+
+    fn from_row(kind: &str, text: Option<String>) -> Result<Self, String> {
+        let text = text.unwrap_or_default();
+        match kind {
+            "check" => Ok(OptionValue::Check(text == "true")),
+            "spin" => text.parse().map(OptionValue::Spin).map_err(|_| format!("Invalid spin value '{}'", text)),
+            "combo" => Ok(OptionValue::Combo(text)),
+            "string" => Ok(OptionValue::String(text)),
+            "button" => Ok(OptionValue::Button),
+            _ => Err(format!("Unknown option type '{}'", kind)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EngineOptionSetting {
+    pub name: String,
+    pub value: OptionValue,
+}
+
+impl EngineOptionSetting {
+    /// The `setoption` command that applies this setting.
+    pub fn command(&self) -> String {
+        match self.value.text() {
+            Some(value) => format!("setoption name {} value {}", self.name, value),
+            None => format!("setoption name {}", self.name),
+        }
+    }
+}
+
+/// What the caller supplies when adding or updating a profile.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EngineProfileInput {
+    pub name: String,
+    pub path: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub protocol: EngineProtocol,
+    #[serde(default)]
+    pub encoding: EngineEncoding,
+    /// Applied in this order when the engine is loaded.
+    #[serde(default)]
+    pub options: Vec<EngineOptionSetting>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EngineProfile {
+    pub id: i64,
+    pub name: String,
+    pub path: String,
+    pub args: Vec<String>,
+    pub protocol: EngineProtocol,
+    pub encoding: EngineEncoding,
+    pub options: Vec<EngineOptionSetting>,
+    /// SHA-256 of the binary when the profile was last saved, as hex.
+    pub sha256: String,
+    /// Unix times in seconds.
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+impl EngineProfile {
+    /// True if `path` is this profile's binary, comparing resolved paths so `./x` and `/abs/x` match.
+    pub fn is_for(&self, path: &str) -> bool {
+        resolve(&self.path) == resolve(path)
+    }
+}
+
+/// How the binary on disk compares with the fingerprint taken when the profile was saved.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum BinaryStatus {
+    Unchanged,
+    Changed { expected: String, actual: String },
+    Missing,
+}
+
+impl BinaryStatus {
+    /// A warning for the user, or `None` if the binary is the one that was registered.
+    pub fn warning(&self, profile: &EngineProfile) -> Option<String> {
+        match self {
+            BinaryStatus::Unchanged => None,
+            BinaryStatus::Changed { .. } => Some(format!(
+                "The binary of engine '{}' has changed since it was registered: {}",
+                profile.name, profile.path
+            )),
+            BinaryStatus::Missing => Some(format!("The binary of engine '{}' is missing: {}", profile.name, profile.path)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EngineProfileStatus {
+    #[serde(flatten)]
+    pub profile: EngineProfile,
+    pub binary: BinaryStatus,
+}
+
+pub struct EngineProfileStore {
+    conn: Connection,
+}
+
+impl EngineProfileStore {
+    pub fn new<P: AsRef<Path>>(db_path: P) -> Result<Self, String> {
+        let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
+        let store = EngineProfileStore { conn };
+        store.initialize_database().map_err(|e| e.to_string())?;
+        Ok(store)
+    }
+
+    fn initialize_database(&self) -> rusqlite::Result<()> {
+        // File size and modification time let an unchanged binary skip rehashing
+        self.conn.execute_batch(
+            r#"
+            CREATE TABLE IF NOT EXISTS engine_profiles (
+                id         INTEGER PRIMARY KEY AUTOINCREMENT,
+                name       TEXT NOT NULL,
+                path       TEXT NOT NULL,
+                args       TEXT NOT NULL,
+                protocol   TEXT NOT NULL,
+                encoding   TEXT NOT NULL,
+                sha256     TEXT NOT NULL,
+                file_size  INTEGER NOT NULL,
+                file_mtime INTEGER NOT NULL,
+                created_at INTEGER NOT NULL,
+                updated_at INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS engine_options (
+                profile_id INTEGER NOT NULL,
+                position   INTEGER NOT NULL,
+                name       TEXT NOT NULL,
+                kind       TEXT NOT NULL,
+                value      TEXT,
+                PRIMARY KEY (profile_id, name)
+            );
+            "#,
+        )
+    }
+
+    /// All profiles with the state of their binaries, in the order they were added.
+    pub fn list(&self) -> Result<Vec<EngineProfileStatus>, String> {
+        let ids: Vec<i64> = {
+            let mut stmt = self.conn.prepare("SELECT id FROM engine_profiles ORDER BY id").map_err(|e| e.to_string())?;
+            let rows = stmt.query_map([], |row| row.get(0)).map_err(|e| e.to_string())?;
+            rows.collect::<rusqlite::Result<_>>().map_err(|e| e.to_string())?
+        };
+        let mut profiles = Vec::with_capacity(ids.len());
+        for id in ids {
+            let profile = self.get(id)?.ok_or_else(|| format!("Engine profile {} vanished", id))?;
+            let binary = self.check_binary(&profile)?;
+            profiles.push(EngineProfileStatus { profile, binary });
+        }
+        Ok(profiles)
+    }
+
+    pub fn get(&self, id: i64) -> Result<Option<EngineProfile>, String> {
+        let profile = self
+            .conn
+            .query_row(
+                "SELECT id, name, path, args, protocol, encoding, sha256, created_at, updated_at FROM engine_profiles WHERE id = ?1",
+                [id],
+                |row| {
+                    Ok((
+                        EngineProfile {
+                            id: row.get(0)?,
+                            name: row.get(1)?,
+                            path: row.get(2)?,
+                            args: Vec::new(),
+                            protocol: EngineProtocol::default(),
+                            encoding: EngineEncoding::default(),
+                            options: Vec::new(),
+                            sha256: row.get(6)?,
+                            created_at: row.get(7)?,
+                            updated_at: row.get(8)?,
+                        },
+                        row.get::<_, String>(3)?,
+                        row.get::<_, String>(4)?,
+                        row.get::<_, String>(5)?,
+                    ))
+                },
+            )
+            .optional()
+            .map_err(|e| e.to_string())?;
+        let Some((mut profile, args, protocol, encoding)) = profile else {
+            return Ok(None);
+        };
+        profile.args = serde_json::from_str(&args).map_err(|e| e.to_string())?;
+        profile.protocol = protocol.parse()?;
+        profile.encoding = encoding.parse()?;
+        profile.options = self.options(id)?;
+        Ok(Some(profile))
+    }
+
+    /// The profile registered for this binary, comparing resolved paths so `./x` and `/abs/x` match.
+    pub fn find_by_path(&self, path: &str) -> Result<Option<EngineProfile>, String> {
+        let wanted = resolve(path);
+        let candidates: Vec<(i64, String)> = {
+            let mut stmt = self.conn.prepare("SELECT id, path FROM engine_profiles ORDER BY id").map_err(|e| e.to_string())?;
+            let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?))).map_err(|e| e.to_string())?;
+            rows.collect::<rusqlite::Result<_>>().map_err(|e| e.to_string())?
+        };
+        match candidates.into_iter().find(|(_, p)| resolve(p) == wanted) {
+            Some((id, _)) => self.get(id),
+            None => Ok(None),
+        }
+    }
+
+    /// The profile registered for `input.path`, or a new one from `input` if there is none.
+    pub fn find_or_add(&mut self, input: &EngineProfileInput) -> Result<EngineProfile, String> {
+        match self.find_by_path(&input.path)? {
+            Some(profile) => Ok(profile),
+            None => self.add(input),
+        }
+    }
+
+    /// Register an engine. The binary must exist; it is fingerprinted now.
+    pub fn add(&mut self, input: &EngineProfileInput) -> Result<EngineProfile, String> {
+        let stamp = FileStamp::read(&input.path)?;
+        let now = chrono::Utc::now().timestamp();
+        let tx = self.conn.transaction().map_err(|e| e.to_string())?;
+        tx.execute(
+            "INSERT INTO engine_profiles (name, path, args, protocol, encoding, sha256, file_size, file_mtime, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?9)",
+            rusqlite::params![
+                input.name,
+                input.path,
+                serde_json::to_string(&input.args).map_err(|e| e.to_string())?,
+                enum_text(&input.protocol),
+                enum_text(&input.encoding),
+                stamp.sha256,
+                stamp.size,
+                stamp.mtime,
+                now,
+            ],
+        )
+        .map_err(|e| e.to_string())?;
+        let id = tx.last_insert_rowid();
+        write_options(&tx, id, &input.options)?;
+        tx.commit().map_err(|e| e.to_string())?;
+        self.get(id)?.ok_or_else(|| "Engine profile was not saved".to_string())
+    }
+
+    /// Replace a profile's settings. The binary is fingerprinted again, so saving a profile
+    /// also accepts a binary that has changed since it was registered.
+    pub fn update(&mut self, id: i64, input: &EngineProfileInput) -> Result<EngineProfile, String> {
+        let stamp = FileStamp::read(&input.path)?;
+        let tx = self.conn.transaction().map_err(|e| e.to_string())?;
+        let updated = tx
+            .execute(
+                "UPDATE engine_profiles SET name = ?2, path = ?3, args = ?4, protocol = ?5, encoding = ?6,
+                 sha256 = ?7, file_size = ?8, file_mtime = ?9, updated_at = ?10 WHERE id = ?1",
+                rusqlite::params![
+                    id,
+                    input.name,
+                    input.path,
+                    serde_json::to_string(&input.args).map_err(|e| e.to_string())?,
+                    enum_text(&input.protocol),
+                    enum_text(&input.encoding),
+                    stamp.sha256,
+                    stamp.size,
+                    stamp.mtime,
+                    chrono::Utc::now().timestamp(),
+                ],
+            )
+            .map_err(|e| e.to_string())?;
+        if updated == 0 {
+            return Err(format!("No engine profile with id {}", id));
+        }
+        tx.execute("DELETE FROM engine_options WHERE profile_id = ?1", [id]).map_err(|e| e.to_string())?;
+        write_options(&tx, id, &input.options)?;
+        tx.commit().map_err(|e| e.to_string())?;
+        self.get(id)?.ok_or_else(|| format!("No engine profile with id {}", id))
+    }
+
+    /// Returns false if there was no such profile.
+    pub fn remove(&mut self, id: i64) -> Result<bool, String> {
+        let tx = self.conn.transaction().map_err(|e| e.to_string())?;
+        tx.execute("DELETE FROM engine_options WHERE profile_id = ?1", [id]).map_err(|e| e.to_string())?;
+        let removed = tx.execute("DELETE FROM engine_profiles WHERE id = ?1", [id]).map_err(|e| e.to_string())?;
+        tx.commit().map_err(|e| e.to_string())?;
+        Ok(removed > 0)
+    }
+
+    /// Copy a profile, e.g. to try other options on the same binary. The copy is named
+    /// "<name> (copy)" unless a name is given.
+    pub fn duplicate(&mut self, id: i64, name: Option<&str>) -> Result<EngineProfile, String> {
+        let source = self.get(id)?.ok_or_else(|| format!("No engine profile with id {}", id))?;
+        let input = EngineProfileInput {
+            name: name.map(str::to_string).unwrap_or_else(|| format!("{} (copy)", source.name)),
+            path: source.path,
+            args: source.args,
+            protocol: source.protocol,
+            encoding: source.encoding,
+            options: source.options,
+        };
+        self.add(&input)
+    }
+
+    /// Compare the binary on disk with the profile's fingerprint. The file is only hashed
+    /// again when its size or modification time differ from when it was fingerprinted.
+    pub fn check_binary(&self, profile: &EngineProfile) -> Result<BinaryStatus, String> {
+        let Ok(metadata) = fs::metadata(&profile.path) else {
+            return Ok(BinaryStatus::Missing);
+        };
+        let (size, mtime): (i64, i64) = self
+            .conn
+            .query_row("SELECT file_size, file_mtime FROM engine_profiles WHERE id = ?1", [profile.id], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .map_err(|e| e.to_string())?;
+        if metadata.len() as i64 == size && modified_secs(&metadata) == mtime {
+            return Ok(BinaryStatus::Unchanged);
+        }
+        let stamp = FileStamp::read(&profile.path)?;
+        if stamp.sha256 != profile.sha256 {
+            return Ok(BinaryStatus::Changed {
+                expected: profile.sha256.clone(),
+                actual: stamp.sha256,
+            });
+        }
+        // Same content with a new timestamp (copied or touched): remember it to skip the hash next time
+        self.conn
+            .execute(
+                "UPDATE engine_profiles SET file_size = ?2, file_mtime = ?3 WHERE id = ?1",
+                rusqlite::params![profile.id, stamp.size, stamp.mtime],
+            )
+            .map_err(|e| e.to_string())?;
+        Ok(BinaryStatus::Unchanged)
+    }
+
+    fn options(&self, id: i64) -> Result<Vec<EngineOptionSetting>, String> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT name, kind, value FROM engine_options WHERE profile_id = ?1 ORDER BY position")
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map([id], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get(2)?)))
+            .map_err(|e| e.to_string())?;
+        let mut options = Vec::new();
+        for row in rows {
+            let (name, kind, value) = row.map_err(|e| e.to_string())?;
+            options.push(EngineOptionSetting {
+                value: OptionValue::from_row(&kind, value)?,
+                name,
+            });
+        }
+        Ok(options)
+    }
+}
+
+fn write_options(tx: &rusqlite::Transaction, id: i64, options: &[EngineOptionSetting]) -> Result<(), String> {
+    for (position, option) in options.iter().enumerate() {
+        tx.execute(
+            "INSERT OR REPLACE INTO engine_options (profile_id, position, name, kind, value) VALUES (?1, ?2, ?3, ?4, ?5)",
+            rusqlite::params![id, position as i64, option.name, option.value.kind(), option.value.text()],
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+// Protocols and encodings are stored under their serde names, which their FromStr impls accept
+fn enum_text<T: Serialize>(value: &T) -> String {
+    serde_json::to_value(value)
+        .ok()
+        .and_then(|v| v.as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+fn resolve(path: &str) -> std::path::PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| Path::new(path).to_path_buf())
+}
+
+pub(crate) fn modified_secs(metadata: &fs::Metadata) -> i64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+struct FileStamp {
+    sha256: String,
+    size: i64,
+    mtime: i64,
+}
+
+impl FileStamp {
+    fn read(path: &str) -> Result<Self, String> {
+        let metadata = fs::metadata(path).map_err(|_| format!("Engine file not found: {}", path))?;
+        if !metadata.is_file() {
+            return Err(format!("Path is not a file: {}", path));
+        }
+        Ok(FileStamp {
+            sha256: fingerprint(path)?,
+            size: metadata.len() as i64,
+            mtime: modified_secs(&metadata),
+        })
+    }
+}
+
+/// SHA-256 of a file's contents as lowercase hex.
+pub fn fingerprint<P: AsRef<Path>>(path: P) -> Result<String, String> {
+    let path = path.as_ref();
+    let mut file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::time::{Duration, SystemTime};
+
+    fn binary(name: &str, contents: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("jieqibox-profiles-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn input(path: &Path, options: Vec<EngineOptionSetting>) -> EngineProfileInput {
+        EngineProfileInput {
+            name: "Pikafish".into(),
+            path: path.to_string_lossy().into_owned(),
+            args: vec!["--uci".into()],
+            protocol: EngineProtocol::Uci,
+            encoding: EngineEncoding::Auto,
+            options,
+        }
+    }
+
+    fn setting(name: &str, value: OptionValue) -> EngineOptionSetting {
+        EngineOptionSetting {
+            name: name.into(),
+            value,
+        }
+    }
+
+    #[test]
+    fn options_keep_their_order_and_types() {
+        let path = binary("options", "engine");
+        let mut store = EngineProfileStore::new(":memory:").unwrap();
+        let options = vec![
+            setting("Threads", OptionValue::Spin(4)),
+            setting("Ponder", OptionValue::Check(false)),
+            setting("EvalFile", OptionValue::String("pikafish.nnue".into())),
+            setting("Clear Hash", OptionValue::Button),
+            setting("Style", OptionValue::Combo("Risky".into())),
+        ];
+        let profile = store.add(&input(&path, options.clone())).unwrap();
+        assert_eq!(profile.options, options);
+        assert_eq!(profile.args, ["--uci"]);
+        assert_eq!(profile.sha256, fingerprint(&path).unwrap());
+
+        let reordered = vec![
+            setting("Style", OptionValue::Combo("Solid".into())),
+            setting("Hash", OptionValue::Spin(-1)),
+            setting("Threads", OptionValue::Spin(8)),
+        ];
+        let updated = store.update(profile.id, &input(&path, reordered.clone())).unwrap();
+        assert_eq!(updated.options, reordered);
+        assert_eq!(store.get(profile.id).unwrap().unwrap().options, reordered);
+        assert_eq!(reordered[1].command(), "setoption name Hash value -1");
+        assert_eq!(options[3].command(), "setoption name Clear Hash");
+    }
+
+    #[test]
+    fn check_binary_notices_changed_and_missing_files() {
+        let path = binary("check", "engine v1");
+        let mut store = EngineProfileStore::new(":memory:").unwrap();
+        let profile = store.add(&input(&path, Vec::new())).unwrap();
+        assert_eq!(store.check_binary(&profile).unwrap(), BinaryStatus::Unchanged);
+
+        // Same contents with a new modification time
+        let later = SystemTime::now() + Duration::from_secs(120);
+        File::options().write(true).open(&path).unwrap().set_modified(later).unwrap();
+        assert_eq!(store.check_binary(&profile).unwrap(), BinaryStatus::Unchanged);
+
+        fs::write(&path, "engine v2, rebuilt").unwrap();
+        let status = store.check_binary(&profile).unwrap();
+        assert_eq!(
+            status,
+            BinaryStatus::Changed {
+                expected: profile.sha256.clone(),
+                actual: fingerprint(&path).unwrap(),
+            }
+        );
+        assert!(status.warning(&profile).unwrap().contains("has changed"));
+
+        fs::remove_file(&path).unwrap();
+        assert_eq!(store.check_binary(&profile).unwrap(), BinaryStatus::Missing);
+    }
+}
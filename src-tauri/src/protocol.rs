@@ -0,0 +1,395 @@
+// Protocol adapter between the UCI-style commands the GUI sends and engines speaking UCCI.
+// UCI and JAI engines are passed through untouched. For UCCI the adapter renames the
+// handshake, options and `ucinewgame`, rewrites FENs and clock parameters, and turns UCCI
+// scores and `nobestmove` back into their UCI forms.
+use crate::engine::EngineProtocol;
+
+// ElephantEye conventions: a mate in n plies scores MATE_VALUE - n, anything above WIN_VALUE is a forced win
+const UCCI_MATE_VALUE: i32 = 10000;
+const UCCI_WIN_VALUE: i32 = 9800;
+
+/// How long to wait for `uciok` before assuming the engine speaks UCCI.
+pub const PROTOCOL_DETECT_MS: u64 = 1500;
+
+#[derive(Debug, Clone, Default)]
+pub struct ProtocolAdapter {
+    // None until the engine's protocol is known
+    protocol: Option<EngineProtocol>,
+    awaiting_uciok: bool,
+    red_to_move: bool,
+    // UCCI engines take times in seconds unless they offer `usemillisec` and it is switched on
+    supports_millisec: bool,
+    millisec_enabled: bool,
+}
+
+impl ProtocolAdapter {
+    /// With `None`, the protocol is detected from the handshake: `uci` is answered with `uciok`
+    /// by UCI engines, and `handshake_timeout` switches to UCCI when it is not.
+    pub fn new(protocol: Option<EngineProtocol>) -> Self {
+        ProtocolAdapter {
+            protocol,
+            red_to_move: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn protocol(&self) -> Option<EngineProtocol> {
+        self.protocol
+    }
+
+    /// True while a `uci` handshake is out and the protocol is still unknown.
+    pub fn detecting(&self) -> bool {
+        self.protocol.is_none() && self.awaiting_uciok
+    }
+
+    /// Called once `PROTOCOL_DETECT_MS` has passed after `uci` without an answer. Returns the
+    /// UCCI handshake to send if the engine is now assumed to speak UCCI.
+    pub fn handshake_timeout(&mut self) -> Option<String> {
+        if !self.detecting() {
+            return None;
+        }
+        self.awaiting_uciok = false;
+        self.protocol = Some(EngineProtocol::Ucci);
+        Some("ucci".to_string())
+    }
+
+    /// Forget what the previous process was told; the protocol itself is kept.
+    pub fn restarted(&mut self) {
+        *self = ProtocolAdapter::new(self.protocol);
+    }
+
+    /// Translate one GUI command into the lines to write to the engine.
+    pub fn outgoing(&mut self, command: &str) -> Vec<String> {
+        let command = command.trim();
+        let keyword = command.split_whitespace().next().unwrap_or_default();
+        match self.protocol {
+            None => {
+                match keyword {
+                    "uci" => self.awaiting_uciok = true,
+                    "ucci" => self.protocol = Some(EngineProtocol::Ucci),
+                    "jai" => self.protocol = Some(EngineProtocol::Jai),
+                    _ => {}
+                }
+                vec![command.to_string()]
+            }
+            Some(EngineProtocol::Ucci) => self.translate_command(keyword, command),
+            Some(_) => vec![command.to_string()],
+        }
+    }
+
+    /// Translate one line of engine output into what the GUI expects.
+    pub fn incoming(&mut self, line: &str) -> String {
+        match self.protocol {
+            None if self.awaiting_uciok && line.trim() == "uciok" => {
+                self.awaiting_uciok = false;
+                self.protocol = Some(EngineProtocol::Uci);
+                line.to_string()
+            }
+            Some(EngineProtocol::Ucci) => self.translate_output(line),
+            _ => line.to_string(),
+        }
+    }
+
+    fn translate_command(&mut self, keyword: &str, command: &str) -> Vec<String> {
+        match keyword {
+            "uci" => vec!["ucci".to_string()],
+            "ucinewgame" => vec!["setoption newgame".to_string()],
+            "setoption" => vec![ucci_setoption(command)],
+            "position" => vec![self.ucci_position(command)],
+            "go" => {
+                let mut lines = Vec::new();
+                let go = self.ucci_go(command);
+                if self.supports_millisec && !self.millisec_enabled && go.contains(" time ") {
+                    self.millisec_enabled = true;
+                    lines.push("setoption usemillisec true".to_string());
+                }
+                lines.push(go);
+                lines
+            }
+            _ => vec![command.to_string()],
+        }
+    }
+
+    fn translate_output(&mut self, line: &str) -> String {
+        let trimmed = line.trim();
+        let keyword = trimmed.split_whitespace().next().unwrap_or_default();
+        match keyword {
+            "ucciok" => "uciok".to_string(),
+            // A UCI engine that was slow to answer and got mistaken for UCCI
+            "uciok" => {
+                self.protocol = Some(EngineProtocol::Uci);
+                line.to_string()
+            }
+            "nobestmove" => "bestmove (none)".to_string(),
+            "option" => {
+                let rest = trimmed["option".len()..].trim();
+                if rest.split_whitespace().next() == Some("usemillisec") {
+                    self.supports_millisec = true;
+                }
+                if rest.starts_with("name ") {
+                    trimmed.to_string()
+                } else {
+                    format!("option name {}", rest)
+                }
+            }
+            "info" => ucci_info(trimmed),
+            _ => line.to_string(),
+        }
+    }
+
+    // `position fen <fen> [moves ...]`: rewrite the FEN and note the side to move for `go`
+    fn ucci_position(&mut self, command: &str) -> String {
+        let (head, moves) = match command.split_once(" moves ") {
+            Some((head, moves)) => (head, Some(moves)),
+            None => (command, None),
+        };
+        let move_count = moves.map_or(0, |m| m.split_whitespace().count());
+        let mut tokens = head.split_whitespace().skip(1);
+        let position = match tokens.next() {
+            Some("fen") => {
+                let fen = tokens.collect::<Vec<_>>().join(" ");
+                self.red_to_move = fen_red_to_move(&fen);
+                format!("position fen {}", ucci_fen(&fen))
+            }
+            _ => {
+                self.red_to_move = true;
+                head.to_string()
+            }
+        };
+        if move_count % 2 == 1 {
+            self.red_to_move = !self.red_to_move;
+        }
+        match moves {
+            Some(moves) => format!("{} moves {}", position, moves),
+            None => position,
+        }
+    }
+
+    // UCCI: go [ponder] (depth d | nodes n | time t [movestogo m | increment i] [opptime ...])
+    fn ucci_go(&self, command: &str) -> String {
+        let tokens: Vec<&str> = command.split_whitespace().collect();
+        let value = |name: &str| {
+            tokens
+                .iter()
+                .position(|t| *t == name)
+                .and_then(|i| tokens.get(i + 1))
+                .and_then(|v| v.parse::<u64>().ok())
+        };
+        let mut go = String::from("go");
+        if tokens.contains(&"ponder") {
+            go.push_str(" ponder");
+        }
+
+        let (own, opp, own_inc, opp_inc) = if self.red_to_move {
+            (value("wtime"), value("btime"), value("winc"), value("binc"))
+        } else {
+            (value("btime"), value("wtime"), value("binc"), value("winc"))
+        };
+        if let Some(time) = own {
+            go.push_str(&format!(" time {}", self.ucci_time(time)));
+            match value("movestogo") {
+                Some(moves) => go.push_str(&format!(" movestogo {}", moves)),
+                None => go.push_str(&format!(" increment {}", self.ucci_time(own_inc.unwrap_or(0)))),
+            }
+            if let Some(opp) = opp {
+                go.push_str(&format!(" opptime {}", self.ucci_time(opp)));
+                match value("movestogo") {
+                    Some(moves) => go.push_str(&format!(" oppmovestogo {}", moves)),
+                    None => go.push_str(&format!(" oppincrement {}", self.ucci_time(opp_inc.unwrap_or(0)))),
+                }
+            }
+        } else if let Some(movetime) = value("movetime") {
+            // No movetime in UCCI: all the time for one move amounts to the same thing
+            go.push_str(&format!(" time {} movestogo 1", self.ucci_time(movetime)));
+        } else if let Some(depth) = value("depth") {
+            go.push_str(&format!(" depth {}", depth));
+        } else if let Some(nodes) = value("nodes") {
+            go.push_str(&format!(" nodes {}", nodes));
+        } else {
+            go.push_str(" depth infinite");
+        }
+        go
+    }
+
+    fn ucci_time(&self, ms: u64) -> u64 {
+        if self.supports_millisec {
+            ms
+        } else {
+            ms / 1000
+        }
+    }
+}
+
+// `setoption name Hash value 64` -> `setoption hashsize 64`; buttons have no value
+fn ucci_setoption(command: &str) -> String {
+    let rest = command["setoption".len()..].trim();
+    let Some(named) = rest.strip_prefix("name ") else {
+        return command.to_string();
+    };
+    let (name, value) = match named.split_once(" value ") {
+        Some((name, value)) => (name.trim(), Some(value.trim())),
+        None => (named.trim(), None),
+    };
+    let name = match name.to_ascii_lowercase().as_str() {
+        "hash" => "hashsize".to_string(),
+        "threads" => "threads".to_string(),
+        "ponder" => "ponder".to_string(),
+        "ownbook" => "usebook".to_string(),
+        _ => name.to_string(),
+    };
+    match value {
+        Some(value) => format!("setoption {} {}", name, value),
+        None => format!("setoption {}", name),
+    }
+}
+
+fn fen_red_to_move(fen: &str) -> bool {
+    let parts: Vec<&str> = fen.split_whitespace().collect();
+    let side = if matches!(parts.get(1), Some(&"w") | Some(&"b")) { parts.get(1) } else { parts.get(2) };
+    side != Some(&"b")
+}
+
+/// The FEN in the classic UCCI layout, `board side - - halfmove fullmove`. Both JieqiBox
+/// formats are accepted; the hidden and captured pools have no place in UCCI FENs and are
+/// dropped, since UCCI engines work them out from the board.
+pub fn ucci_fen(fen: &str) -> String {
+    let parts: Vec<&str> = fen.split_whitespace().collect();
+    let Some(board) = parts.first() else {
+        return fen.to_string();
+    };
+    let side = if fen_red_to_move(fen) { "w" } else { "b" };
+    let counters: Vec<&str> = parts.iter().skip(2).copied().filter(|p| p.parse::<u32>().is_ok()).collect();
+    let (halfmove, fullmove) = match counters.as_slice() {
+        [.., half, full] => (*half, *full),
+        _ => ("0", "1"),
+    };
+    format!("{} {} - - {} {}", board, side, halfmove, fullmove)
+}
+
+// `info depth 8 score 35 pv ...` -> `info depth 8 score cp 35 pv ...`, mates as `score mate N`
+fn ucci_info(line: &str) -> String {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    let Some(i) = tokens.iter().position(|t| *t == "score") else {
+        return line.to_string();
+    };
+    let Some(score) = tokens.get(i + 1).and_then(|v| v.parse::<i32>().ok()) else {
+        return line.to_string();
+    };
+    let converted = if score.abs() > UCCI_WIN_VALUE {
+        let moves = ((UCCI_MATE_VALUE - score.abs() + 1) / 2).max(1);
+        format!("mate {}", moves * score.signum())
+    } else {
+        format!("cp {}", score)
+    };
+    let mut out: Vec<String> = tokens[..=i].iter().map(|t| t.to_string()).collect();
+    out.push(converted);
+    out.extend(tokens[i + 2..].iter().map(|t| t.to_string()));
+    out.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FEN: &str =
+        "xxxxkxxxx/9/1x5x1/x1x1x1x1x/9/9/X1X1X1X1X/1X5X1/9/XXXXKXXXX w A2B2N2R2C2P5a2b2n2r2c2p5 - 0 1";
+
+    fn ucci() -> ProtocolAdapter {
+        ProtocolAdapter::new(Some(EngineProtocol::Ucci))
+    }
+
+    #[test]
+    fn detection_falls_back_to_ucci() {
+        let mut adapter = ProtocolAdapter::new(None);
+        assert_eq!(adapter.outgoing("uci"), ["uci"]);
+        assert!(adapter.detecting());
+        assert_eq!(adapter.handshake_timeout(), Some("ucci".into()));
+        assert_eq!(adapter.protocol(), Some(EngineProtocol::Ucci));
+        assert_eq!(adapter.incoming("ucciok"), "uciok");
+
+        let mut adapter = ProtocolAdapter::new(None);
+        adapter.outgoing("uci");
+        assert_eq!(adapter.incoming("uciok"), "uciok");
+        assert_eq!(adapter.protocol(), Some(EngineProtocol::Uci));
+        assert_eq!(adapter.handshake_timeout(), None);
+        assert_eq!(adapter.outgoing("go movetime 500"), ["go movetime 500"]);
+
+        // A slow UCI engine that was taken for UCCI switches back on its `uciok`
+        let mut adapter = ProtocolAdapter::new(None);
+        adapter.outgoing("uci");
+        adapter.handshake_timeout();
+        adapter.incoming("uciok");
+        assert_eq!(adapter.protocol(), Some(EngineProtocol::Uci));
+    }
+
+    #[test]
+    fn commands_are_translated() {
+        let mut adapter = ucci();
+        assert_eq!(adapter.outgoing("uci"), ["ucci"]);
+        assert_eq!(adapter.outgoing("ucinewgame"), ["setoption newgame"]);
+        assert_eq!(adapter.outgoing("setoption name Hash value 64"), ["setoption hashsize 64"]);
+        assert_eq!(adapter.outgoing("setoption name OwnBook value false"), ["setoption usebook false"]);
+        assert_eq!(adapter.outgoing("setoption name Clear Hash"), ["setoption Clear Hash"]);
+        assert_eq!(
+            adapter.outgoing(&format!("position fen {} moves h2e2", FEN)),
+            ["position fen xxxxkxxxx/9/1x5x1/x1x1x1x1x/9/9/X1X1X1X1X/1X5X1/9/XXXXKXXXX w - - 0 1 moves h2e2"]
+        );
+        assert_eq!(adapter.outgoing("go movetime 2500"), ["go time 2 movestogo 1"]);
+        assert_eq!(adapter.outgoing("go depth 12"), ["go depth 12"]);
+        assert_eq!(adapter.outgoing("go infinite"), ["go depth infinite"]);
+    }
+
+    #[test]
+    fn clock_follows_the_side_to_move() {
+        let mut adapter = ucci();
+        adapter.outgoing("position startpos moves h2e2 h9g7 b0c2");
+        assert_eq!(
+            adapter.outgoing("go wtime 5500 btime 3000 movestogo 10"),
+            ["go time 3 movestogo 10 opptime 5 oppmovestogo 10"]
+        );
+        adapter.outgoing("position startpos moves h2e2 h9g7");
+        assert_eq!(
+            adapter.outgoing("go wtime 5500 btime 3000 winc 1000 binc 0"),
+            ["go time 5 increment 1 opptime 3 oppincrement 0"]
+        );
+    }
+
+    #[test]
+    fn millisec_is_switched_on_before_the_first_timed_go() {
+        let mut adapter = ucci();
+        assert_eq!(
+            adapter.incoming("option usemillisec type check default false"),
+            "option name usemillisec type check default false"
+        );
+        assert_eq!(adapter.outgoing("go depth 5"), ["go depth 5"]);
+        adapter.outgoing(&format!("position fen {} moves h2e2", FEN));
+        assert_eq!(
+            adapter.outgoing("go wtime 60000 btime 30000 winc 1000 binc 2000"),
+            ["setoption usemillisec true", "go time 30000 increment 2000 opptime 60000 oppincrement 1000"]
+        );
+        assert_eq!(adapter.outgoing("go movetime 500"), ["go time 500 movestogo 1"]);
+        // A restarted engine has to be told again
+        adapter.restarted();
+        adapter.incoming("option usemillisec type check");
+        assert_eq!(adapter.outgoing("go movetime 500")[0], "setoption usemillisec true");
+    }
+
+    #[test]
+    fn output_is_translated() {
+        let mut adapter = ucci();
+        assert_eq!(adapter.incoming("info depth 8 score 35 pv h2e2"), "info depth 8 score cp 35 pv h2e2");
+        assert_eq!(adapter.incoming("info depth 5 score 9995 pv h2e2"), "info depth 5 score mate 3 pv h2e2");
+        assert_eq!(adapter.incoming("info depth 5 score -9998"), "info depth 5 score mate -1");
+        assert_eq!(adapter.incoming("info time 10 nodes 500"), "info time 10 nodes 500");
+        assert_eq!(adapter.incoming("nobestmove"), "bestmove (none)");
+        assert_eq!(adapter.incoming("bestmove h2e2"), "bestmove h2e2");
+    }
+
+    #[test]
+    fn fens_are_written_in_ucci_layout() {
+        assert_eq!(ucci_fen("board a2 b - - 3 9"), "board b - - 3 9");
+        assert_eq!(ucci_fen("board w - - 5 10"), "board w - - 5 10");
+        assert_eq!(ucci_fen("board b"), "board b - - 0 1");
+        assert_eq!(ucci_fen(""), "");
+    }
+}
@@ -0,0 +1,443 @@
+// Engines over TCP: a server that runs a local engine for each authenticated connection, and
+// the client side that talks to it like a local process.
+//
+// The client opens with `auth <token>` and gets `ok` or `denied`. After that everything the
+// client sends goes straight to the engine's stdin, and every line the engine prints comes
+// back as `out <line>` or `err <line>`. When the engine ends the server sends
+// `exit <code|-> <signal|->` and closes. Closing the connection kills the engine.
+use serde::{Deserialize, Serialize};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::resources::{self, EngineResources};
+
+// The whole `auth` line has to arrive within AUTH_TIMEOUT and fit in MAX_AUTH_LINE bytes
+const AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_AUTH_LINE: usize = 4096;
+// How long a killed remote engine has to report its exit before it is taken as gone
+const KILL_GRACE: Duration = Duration::from_secs(2);
+// How often blocked reads wake up to check for shutdown and engine exit
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+static NEXT_CONNECTION: AtomicU64 = AtomicU64::new(1);
+
+fn default_bind() -> String {
+    "127.0.0.1:0".to_string()
+}
+
+fn default_max_connections() -> usize {
+    4
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EngineServerConfig {
+    /// Address to listen on, e.g. `0.0.0.0:9100`. The default is loopback on a free port.
+    #[serde(default = "default_bind")]
+    pub bind: String,
+    /// Clients must present this token before the engine is started.
+    pub token: String,
+    pub path: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub resources: EngineResources,
+    /// Each connection runs its own engine process; further connections are turned away.
+    #[serde(default = "default_max_connections")]
+    pub max_connections: usize,
+}
+
+/// Where a remote engine lives and the token to present.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RemoteEndpoint {
+    pub address: String,
+    pub token: String,
+}
+
+#[derive(Debug, Clone)]
+pub enum RemoteEvent {
+    /// One output line including its newline, exactly as the engine wrote it.
+    Stdout(Vec<u8>),
+    Stderr(Vec<u8>),
+    Exited { code: Option<i32>, signal: Option<i32> },
+}
+
+pub struct EngineServer {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl EngineServer {
+    /// Bind the listener and start accepting connections in the background.
+    pub fn start(config: EngineServerConfig) -> Result<Self, String> {
+        if config.token.is_empty() {
+            return Err("The engine server needs a token".into());
+        }
+        if !Path::new(&config.path).is_file() {
+            return Err(format!("Engine file not found: {}", config.path));
+        }
+        config.resources.validate()?;
+        let listener = TcpListener::bind(&config.bind).map_err(|e| format!("Failed to listen on {}: {}", config.bind, e))?;
+        let addr = listener.local_addr().map_err(|e| e.to_string())?;
+        listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_clone = stop.clone();
+        let thread = thread::spawn(move || accept_loop(listener, Arc::new(config), stop_clone));
+        Ok(EngineServer {
+            addr,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Stop accepting and kill the engines of all open connections.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for EngineServer {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+fn accept_loop(listener: TcpListener, config: Arc<EngineServerConfig>, stop: Arc<AtomicBool>) {
+    let active = Arc::new(AtomicUsize::new(0));
+    let mut handlers = Vec::new();
+    while !stop.load(Ordering::SeqCst) {
+        match listener.accept() {
+            Ok((mut stream, _)) => {
+                if active.load(Ordering::SeqCst) >= config.max_connections {
+                    let _ = stream.write_all(b"denied too many connections\n");
+                    continue;
+                }
+                active.fetch_add(1, Ordering::SeqCst);
+                let (config, stop, active) = (config.clone(), stop.clone(), active.clone());
+                handlers.push(thread::spawn(move || {
+                    let _ = serve_connection(stream, &config, &stop);
+                    active.fetch_sub(1, Ordering::SeqCst);
+                }));
+                handlers.retain(|h| !h.is_finished());
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(50)),
+            Err(_) => thread::sleep(Duration::from_millis(50)),
+        }
+    }
+    for handler in handlers {
+        let _ = handler.join();
+    }
+}
+
+fn serve_connection(mut stream: TcpStream, config: &EngineServerConfig, stop: &AtomicBool) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    let auth = read_auth_line(&mut stream)?;
+    let writer = Arc::new(Mutex::new(stream.try_clone()?));
+    let mut reader = BufReader::new(stream);
+
+    let token = auth.trim().strip_prefix("auth ").unwrap_or_default();
+    if !tokens_match(token, &config.token) {
+        writer.lock().unwrap().write_all(b"denied\n")?;
+        return Ok(());
+    }
+
+    let mut child = match spawn_engine_process(config) {
+        Ok(child) => child,
+        Err(e) => {
+            writer.lock().unwrap().write_all(format!("denied {}\n", e).as_bytes())?;
+            return Ok(());
+        }
+    };
+    writer.lock().unwrap().write_all(b"ok\n")?;
+
+    let mut stdin = child.stdin.take().ok_or_else(|| io::Error::other("engine stdin unavailable"))?;
+    let outputs = [
+        child.stdout.take().map(|pipe| forward_output(pipe, b"out ", writer.clone())),
+        child.stderr.take().map(|pipe| forward_output(pipe, b"err ", writer.clone())),
+    ];
+
+    // Client input goes to the engine until either side goes away
+    reader.get_ref().set_read_timeout(Some(POLL_INTERVAL))?;
+    let mut buf = [0u8; 4096];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                if stdin.write_all(&buf[..n]).and_then(|_| stdin.flush()).is_err() {
+                    break;
+                }
+            }
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                if stop.load(Ordering::SeqCst) || !matches!(child.try_wait(), Ok(None)) {
+                    break;
+                }
+            }
+            Err(_) => break,
+        }
+    }
+
+    if matches!(child.try_wait(), Ok(None)) {
+        let _ = child.kill();
+    }
+    let status = child.wait().ok();
+    // All output has to be on the wire before the exit line
+    for output in outputs.into_iter().flatten() {
+        let _ = output.join();
+    }
+    let code = status.and_then(|s| s.code());
+    let signal = status.and_then(exit_signal);
+    let field = |v: Option<i32>| v.map_or("-".to_string(), |v| v.to_string());
+    let mut writer = writer.lock().unwrap();
+    let _ = writer.write_all(format!("exit {} {}\n", field(code), field(signal)).as_bytes());
+    let _ = writer.shutdown(Shutdown::Both);
+    Ok(())
+}
+
+// Read a byte at a time so nothing after the line is consumed
+fn read_auth_line(stream: &mut TcpStream) -> io::Result<String> {
+    let deadline = Instant::now() + AUTH_TIMEOUT;
+    let mut line = Vec::new();
+    let mut byte = [0u8; 1];
+    while line.len() < MAX_AUTH_LINE {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        stream.set_read_timeout(Some(remaining))?;
+        match stream.read(&mut byte)? {
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            _ if byte[0] == b'\n' => return Ok(String::from_utf8_lossy(&line).into_owned()),
+            _ => line.push(byte[0]),
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::InvalidData, "auth line too long"))
+}
+
+fn spawn_engine_process(config: &EngineServerConfig) -> Result<Child, String> {
+    let engine_path = Path::new(&config.path);
+    let mut command = Command::new(engine_path);
+    command
+        .args(&config.args)
+        .envs(config.resources.env.iter().map(|(k, v)| (k, v)))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if let Some(parent) = engine_path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        command.current_dir(parent);
+    }
+    let child = command.spawn().map_err(|e| format!("Failed to start {}: {}", config.path, e))?;
+    resources::apply(child.id(), &config.resources);
+    Ok(child)
+}
+
+fn forward_output<R>(pipe: R, prefix: &'static [u8], writer: Arc<Mutex<TcpStream>>) -> JoinHandle<()>
+where
+    R: Read + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut reader = BufReader::new(pipe);
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            match reader.read_until(b'\n', &mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {
+                    if line.last() != Some(&b'\n') {
+                        line.push(b'\n');
+                    }
+                    let mut frame = prefix.to_vec();
+                    frame.extend_from_slice(&line);
+                    if writer.lock().unwrap().write_all(&frame).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    })
+}
+
+// Compare without returning early, so response times say nothing about the token
+fn tokens_match(given: &str, expected: &str) -> bool {
+    given.len() == expected.len() && given.bytes().zip(expected.bytes()).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+#[cfg(unix)]
+fn exit_signal(status: std::process::ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn exit_signal(_status: std::process::ExitStatus) -> Option<i32> {
+    None
+}
+
+/// The client end of a connection to an engine server.
+pub struct RemoteEngine {
+    id: u64,
+    stream: TcpStream,
+}
+
+impl RemoteEngine {
+    /// Connect and authenticate. Output is delivered to `on_event` from a background thread
+    /// until the connection ends, which is always reported as `Exited`; returning false from
+    /// `on_event` stops delivery.
+    pub fn connect<F>(endpoint: &RemoteEndpoint, mut on_event: F) -> Result<Self, String>
+    where
+        F: FnMut(RemoteEvent) -> bool + Send + 'static,
+    {
+        let addr = endpoint
+            .address
+            .to_socket_addrs()
+            .map_err(|e| format!("Invalid address {}: {}", endpoint.address, e))?
+            .next()
+            .ok_or_else(|| format!("Invalid address {}", endpoint.address))?;
+        let mut stream = TcpStream::connect_timeout(&addr, AUTH_TIMEOUT)
+            .map_err(|e| format!("Failed to connect to {}: {}", endpoint.address, e))?;
+        let _ = stream.set_nodelay(true);
+        stream
+            .write_all(format!("auth {}\n", endpoint.token).as_bytes())
+            .map_err(|e| e.to_string())?;
+
+        stream.set_read_timeout(Some(AUTH_TIMEOUT)).map_err(|e| e.to_string())?;
+        let mut reader = BufReader::new(stream.try_clone().map_err(|e| e.to_string())?);
+        let mut answer = String::new();
+        reader.read_line(&mut answer).map_err(|e| format!("No answer from {}: {}", endpoint.address, e))?;
+        match answer.trim() {
+            "ok" => {}
+            "denied" => return Err(format!("{} refused the token", endpoint.address)),
+            other => {
+                let reason = other.strip_prefix("denied ").unwrap_or(other);
+                return Err(format!("{} refused the connection: {}", endpoint.address, reason));
+            }
+        }
+        stream.set_read_timeout(None).map_err(|e| e.to_string())?;
+
+        thread::spawn(move || {
+            let mut exited = None;
+            let mut line = Vec::new();
+            loop {
+                line.clear();
+                match reader.read_until(b'\n', &mut line) {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => {}
+                }
+                let event = if let Some(rest) = line.strip_prefix(b"out ") {
+                    RemoteEvent::Stdout(rest.to_vec())
+                } else if let Some(rest) = line.strip_prefix(b"err ") {
+                    RemoteEvent::Stderr(rest.to_vec())
+                } else if let Some(rest) = line.strip_prefix(b"exit ") {
+                    let text = String::from_utf8_lossy(rest);
+                    let mut fields = text.split_whitespace().map(|f| f.parse().ok());
+                    exited = Some((fields.next().flatten(), fields.next().flatten()));
+                    break;
+                } else {
+                    continue;
+                };
+                if !on_event(event) {
+                    return;
+                }
+            }
+            // Without an exit line (connection lost, or cut off by `kill`) there is no code or signal
+            let (code, signal) = exited.unwrap_or((None, None));
+            on_event(RemoteEvent::Exited { code, signal });
+        });
+
+        Ok(RemoteEngine {
+            id: NEXT_CONNECTION.fetch_add(1, Ordering::Relaxed),
+            stream,
+        })
+    }
+
+    /// Number of this connection, unique within the process.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self.stream.write_all(bytes).map_err(|e| e.to_string())
+    }
+
+    /// Close our side of the connection. The server kills the engine and still reports its
+    /// exit, so a killed remote engine ends with the same `Exited` as a local one. A server that
+    /// has not reported it within KILL_GRACE is disconnected and the exit is reported locally.
+    pub fn kill(self) {
+        let _ = self.stream.shutdown(Shutdown::Write);
+        // Shutting down reads ends the output thread's blocked read, which then reports `Exited`
+        thread::spawn(move || {
+            thread::sleep(KILL_GRACE);
+            let _ = self.stream.shutdown(Shutdown::Both);
+        });
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn killed_engine_exits_when_the_server_stays_silent() {
+        // A server that accepts the token and then never answers or closes
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            read_auth_line(&mut stream).unwrap();
+            stream.write_all(b"ok\n").unwrap();
+            thread::sleep(KILL_GRACE * 3);
+        });
+        let (tx, rx) = mpsc::channel();
+        let endpoint = RemoteEndpoint { address, token: "secret".into() };
+        let engine = RemoteEngine::connect(&endpoint, move |event| tx.send(event).is_ok()).unwrap();
+        let killed = Instant::now();
+        engine.kill();
+        match rx.recv_timeout(KILL_GRACE * 2).unwrap() {
+            RemoteEvent::Exited { code, signal } => assert_eq!((code, signal), (None, None)),
+            event => panic!("{:?}", event),
+        }
+        assert!(killed.elapsed() >= KILL_GRACE);
+        drop(server);
+    }
+
+    #[test]
+    fn auth_line_is_bounded() {
+        let server = EngineServer::start(EngineServerConfig {
+            bind: default_bind(),
+            token: "secret".into(),
+            path: "/bin/cat".into(),
+            args: Vec::new(),
+            resources: EngineResources::default(),
+            max_connections: default_max_connections(),
+        })
+        .unwrap();
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        stream.set_read_timeout(Some(AUTH_TIMEOUT)).unwrap();
+        let started = Instant::now();
+        // The server stops reading after MAX_AUTH_LINE bytes and drops the connection
+        let _ = stream.write_all(&vec![b'a'; MAX_AUTH_LINE * 4]);
+        let mut answer = Vec::new();
+        let _ = stream.read_to_end(&mut answer);
+        assert!(answer.is_empty());
+        assert!(started.elapsed() < AUTH_TIMEOUT);
+        server.stop();
+    }
+}
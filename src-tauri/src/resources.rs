@@ -0,0 +1,241 @@
+// Per-engine process settings: niceness, CPU affinity, address-space limit and environment.
+// Scheduling and limits are only implemented on Linux; elsewhere they are reported as unsupported.
+use serde::{Deserialize, Serialize};
+
+/// The number of CPUs an affinity mask (a Linux `cpu_set_t`) can hold.
+#[cfg(target_os = "linux")]
+pub const MAX_CPUS: usize = libc::CPU_SETSIZE as usize;
+#[cfg(not(target_os = "linux"))]
+pub const MAX_CPUS: usize = 1024;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EngineResources {
+    /// Nice value, -20 (highest priority) to 19 (lowest). Lowering it usually needs privileges.
+    #[serde(default)]
+    pub niceness: Option<i32>,
+    /// CPUs the engine may run on, as CPU indices (the affinity mask written out).
+    #[serde(default)]
+    pub cpu_affinity: Option<Vec<usize>>,
+    /// RLIMIT_AS cap in MiB.
+    #[serde(default)]
+    pub memory_limit_mb: Option<u64>,
+    /// Extra environment variables for the engine process.
+    #[serde(default)]
+    pub env: Vec<(String, String)>,
+}
+
+impl EngineResources {
+    /// Check the settings before spawning, so a bad value is an error rather than a warning
+    /// (or worse) once the process is running.
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(niceness) = self.niceness {
+            if !(-20..=19).contains(&niceness) {
+                return Err(format!("Niceness {} is outside -20..19", niceness));
+            }
+        }
+        if let Some(cpus) = &self.cpu_affinity {
+            if cpus.is_empty() {
+                return Err("CPU affinity needs at least one CPU".into());
+            }
+            if let Some(cpu) = cpus.iter().find(|&&cpu| cpu >= MAX_CPUS) {
+                return Err(format!("CPU {} is beyond the {} CPUs an affinity mask can hold", cpu, MAX_CPUS));
+            }
+        }
+        if let Some(limit_mb) = self.memory_limit_mb {
+            if limit_mb == 0 || limit_mb.checked_mul(1024 * 1024).is_none() {
+                return Err(format!("Memory limit of {} MiB is out of range", limit_mb));
+            }
+        }
+        for (name, value) in &self.env {
+            if name.is_empty() || name.contains(['=', '\0']) || value.contains('\0') {
+                return Err(format!("Invalid environment variable '{}'", name));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What the engine process actually got, read back from the OS after spawning.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AppliedResources {
+    pub niceness: Option<i32>,
+    pub cpu_affinity: Option<Vec<usize>>,
+    pub memory_limit_mb: Option<u64>,
+    /// Names of the environment variables that were set.
+    pub env: Vec<String>,
+    /// Settings that could not be applied, and why.
+    pub warnings: Vec<String>,
+}
+
+/// Apply the scheduling settings and memory cap to a freshly started process. Niceness and
+/// affinity are per thread on Linux, so they are set on every thread the process has so far;
+/// threads it starts later inherit them. Environment variables must be passed to the command
+/// before spawning; they are only recorded here.
+pub fn apply(pid: u32, resources: &EngineResources) -> AppliedResources {
+    let mut applied = AppliedResources {
+        env: resources.env.iter().map(|(name, _)| name.clone()).collect(),
+        ..Default::default()
+    };
+    platform::apply(pid, resources, &mut applied);
+    applied
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use super::{AppliedResources, EngineResources, MAX_CPUS};
+    use std::io;
+
+    fn threads(pid: u32) -> Vec<libc::pid_t> {
+        let mut tids: Vec<libc::pid_t> = std::fs::read_dir(format!("/proc/{}/task", pid))
+            .map(|dir| {
+                dir.filter_map(|e| e.ok())
+                    .filter_map(|e| e.file_name().to_str().and_then(|s| s.parse().ok()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        if tids.is_empty() {
+            tids.push(pid as libc::pid_t);
+        }
+        tids
+    }
+
+    pub fn apply(pid: u32, resources: &EngineResources, applied: &mut AppliedResources) {
+        let tids = threads(pid);
+
+        if let Some(niceness) = resources.niceness {
+            for &tid in &tids {
+                // SAFETY: plain syscall on a thread id, no memory is shared
+                if unsafe { libc::setpriority(libc::PRIO_PROCESS, tid as libc::id_t, niceness) } != 0 {
+                    applied.warnings.push(format!("niceness {}: {}", niceness, io::Error::last_os_error()));
+                    break;
+                }
+            }
+            applied.niceness = read_niceness(pid);
+        }
+
+        if let Some(cpus) = &resources.cpu_affinity {
+            // SAFETY: cpu_set_t is plain data and all-zero is a valid empty set
+            let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+            for &cpu in cpus {
+                // Normally ruled out by `validate`; CPU_SET would panic on it
+                if cpu >= MAX_CPUS {
+                    applied.warnings.push(format!("CPU {} is beyond the {} CPUs of an affinity mask", cpu, MAX_CPUS));
+                    continue;
+                }
+                // SAFETY: `cpu` was checked above to be below CPU_SETSIZE, the size of `set`
+                unsafe { libc::CPU_SET(cpu, &mut set) };
+            }
+            for &tid in &tids {
+                // SAFETY: `set` lives for the duration of the call and its size is passed along
+                if unsafe { libc::sched_setaffinity(tid, std::mem::size_of::<libc::cpu_set_t>(), &set) } != 0 {
+                    applied.warnings.push(format!("CPU affinity {:?}: {}", cpus, io::Error::last_os_error()));
+                    break;
+                }
+            }
+            applied.cpu_affinity = read_affinity(pid);
+        }
+
+        if let Some(limit_mb) = resources.memory_limit_mb {
+            let bytes = limit_mb.saturating_mul(1024 * 1024) as libc::rlim_t;
+            let limit = libc::rlimit {
+                rlim_cur: bytes,
+                rlim_max: bytes,
+            };
+            // SAFETY: `limit` is a valid rlimit and the old-limit pointer may be null
+            if unsafe { libc::prlimit(pid as libc::pid_t, libc::RLIMIT_AS, &limit, std::ptr::null_mut()) } != 0 {
+                applied.warnings.push(format!("memory limit {} MiB: {}", limit_mb, io::Error::last_os_error()));
+            }
+            applied.memory_limit_mb = read_memory_limit(pid);
+        }
+    }
+
+    fn read_niceness(pid: u32) -> Option<i32> {
+        // getpriority can legitimately return -1, so errno has to be cleared and checked
+        // SAFETY: errno is thread-local and writable
+        unsafe { *libc::__errno_location() = 0 };
+        // SAFETY: plain syscall
+        let value = unsafe { libc::getpriority(libc::PRIO_PROCESS, pid as libc::id_t) };
+        (io::Error::last_os_error().raw_os_error() == Some(0)).then_some(value)
+    }
+
+    fn read_affinity(pid: u32) -> Option<Vec<usize>> {
+        // SAFETY: all-zero is a valid cpu_set_t and the kernel fills it in
+        let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+        // SAFETY: `set` is writable for its full size
+        if unsafe { libc::sched_getaffinity(pid as libc::pid_t, std::mem::size_of::<libc::cpu_set_t>(), &mut set) } != 0 {
+            return None;
+        }
+        let max = 8 * std::mem::size_of::<libc::cpu_set_t>();
+        // SAFETY: CPU_ISSET only reads the set
+        Some((0..max).filter(|&cpu| unsafe { libc::CPU_ISSET(cpu, &set) }).collect())
+    }
+
+    fn read_memory_limit(pid: u32) -> Option<u64> {
+        let mut limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        // SAFETY: a null new limit only reads the current one into `limit`
+        if unsafe { libc::prlimit(pid as libc::pid_t, libc::RLIMIT_AS, std::ptr::null(), &mut limit) } != 0 {
+            return None;
+        }
+        (limit.rlim_cur != libc::RLIM_INFINITY).then_some(limit.rlim_cur / (1024 * 1024))
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod platform {
+    use super::{AppliedResources, EngineResources};
+
+    pub fn apply(_pid: u32, resources: &EngineResources, applied: &mut AppliedResources) {
+        if resources.niceness.is_some() {
+            applied.warnings.push("niceness is only supported on Linux".into());
+        }
+        if resources.cpu_affinity.is_some() {
+            applied.warnings.push("CPU affinity is only supported on Linux".into());
+        }
+        if resources.memory_limit_mb.is_some() {
+            applied.warnings.push("memory limits are only supported on Linux".into());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_rejects_out_of_range_settings() {
+        assert!(EngineResources::default().validate().is_ok());
+        let valid = EngineResources {
+            niceness: Some(10),
+            cpu_affinity: Some(vec![0, MAX_CPUS - 1]),
+            memory_limit_mb: Some(512),
+            env: vec![("EVAL_FILE".into(), "nn.nnue".into())],
+        };
+        assert!(valid.validate().is_ok());
+        let invalid = [
+            EngineResources { niceness: Some(20), ..Default::default() },
+            EngineResources { cpu_affinity: Some(vec![]), ..Default::default() },
+            EngineResources { cpu_affinity: Some(vec![0, MAX_CPUS]), ..Default::default() },
+            EngineResources { memory_limit_mb: Some(0), ..Default::default() },
+            EngineResources { memory_limit_mb: Some(u64::MAX), ..Default::default() },
+            EngineResources { env: vec![("A=B".into(), "1".into())], ..Default::default() },
+            EngineResources { env: vec![("A".into(), "1\0".into())], ..Default::default() },
+        ];
+        for resources in invalid {
+            assert!(resources.validate().is_err(), "{:?}", resources);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn apply_skips_cpus_beyond_the_mask() {
+        let mut child = std::process::Command::new("sleep").arg("5").spawn().unwrap();
+        let resources = EngineResources { cpu_affinity: Some(vec![0, MAX_CPUS + 7]), ..Default::default() };
+        let applied = apply(child.id(), &resources);
+        let _ = child.kill();
+        let _ = child.wait();
+        assert!(applied.warnings.iter().any(|w| w.contains(&(MAX_CPUS + 7).to_string())));
+    }
+}
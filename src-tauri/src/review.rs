@@ -0,0 +1,375 @@
+// Game review: evaluate every position of a game, annotate each move from the score it
+// gives away, and summarise accuracy per side. Thresholds follow ReviewAnalysisDialog.vue.
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use crate::analysis::{Analyser, SearchLimit, SearchResult};
+use crate::jieqi::{Position, Side};
+use crate::match_runner::EngineSpec;
+use crate::notation::{self, GameNotation, MATE_SCORE_BASE};
+
+// Scores are clamped to this before computing centipawn loss, so lost mates do not dominate the average
+const CPL_CAP: i32 = 1000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReviewConfig {
+    pub engine: EngineSpec,
+    pub limit: SearchLimit,
+    /// Give up on a position after this long, for depth and node limits that take too long.
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SideReview {
+    pub moves: u32,
+    pub average_centipawn_loss: f64,
+    pub blunders: u32,
+    pub mistakes: u32,
+    pub inaccuracies: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReviewOutput {
+    pub notation: GameNotation,
+    pub red: SideReview,
+    pub black: SideReview,
+    /// Indices of moves that could not be evaluated because a search failed.
+    pub failed_moves: Vec<usize>,
+    pub aborted: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ReviewEvent {
+    Progress {
+        completed: usize,
+        total: usize,
+    },
+    MoveAnnotated {
+        index: usize,
+        data: String,
+        engine_score: Option<i32>,
+        annotation: Option<String>,
+        /// Why the move could not be evaluated, if a search failed.
+        error: Option<String>,
+    },
+    Finished {
+        aborted: bool,
+    },
+}
+
+/// Annotation for a move that changed the mover's evaluation by `delta`.
+/// `before` and `after` are both from the mover's point of view, in notation encoding.
+pub fn pick_annotation(delta: i32, before: i32, after: i32) -> Option<&'static str> {
+    let mate = MATE_SCORE_BASE - 100;
+    let mate_before = before.abs() > mate;
+    let mate_after = after.abs() > mate;
+
+    if mate_before || mate_after {
+        // Had a forced mate and threw it away
+        if before > mate && after <= mate {
+            return Some("??");
+        }
+        // Was getting mated and the opponent handed the mate back
+        if before < -mate && after > mate {
+            return Some("!!");
+        }
+        // Still mating, but by a noticeably longer route
+        if before > mate && after > mate {
+            let (ply_before, ply_after) = (MATE_SCORE_BASE - before, MATE_SCORE_BASE - after);
+            return (ply_after > ply_before + 2).then_some("?");
+        }
+        return match delta {
+            d if d <= -500 => Some("??"),
+            d if d >= 500 => Some("!!"),
+            _ => None,
+        };
+    }
+
+    match delta {
+        d if d <= -300 => Some("??"),
+        d if d <= -150 => Some("?"),
+        d if d <= -60 => Some("?!"),
+        d if d >= 300 => Some("!!"),
+        d if d >= 150 => Some("!"),
+        d if d >= 60 => Some("!?"),
+        _ => None,
+    }
+}
+
+/// Review every move of `game`. Each position is searched once; its score serves both as the
+/// "after" score of the move leading to it and the "before" score of the move played from it.
+pub fn review_game<F>(
+    mut game: GameNotation,
+    config: &ReviewConfig,
+    stop: &AtomicBool,
+    mut on_event: F,
+) -> Result<ReviewOutput, String>
+where
+    F: FnMut(&ReviewEvent),
+{
+    let move_indices: Vec<usize> = (0..game.moves.len()).filter(|&i| game.moves[i].kind == "move").collect();
+    let initial_fen = game.metadata.initial_fen.clone();
+    if initial_fen.is_empty() && !move_indices.is_empty() {
+        return Err("The game has no initial FEN".into());
+    }
+
+    let timeout = config.timeout_ms.map(Duration::from_millis);
+    let mut analyser = Analyser::new(config.engine.clone());
+    analyser.new_game()?;
+
+    let total = move_indices.len();
+    let mut red = Totals::default();
+    let mut black = Totals::default();
+    let mut failed_moves = Vec::new();
+    let mut aborted = false;
+    // Search result for the position before the current move
+    let mut previous: Option<(String, Result<SearchResult, String>)> = None;
+
+    for (completed, &index) in move_indices.iter().enumerate() {
+        if stop.load(Ordering::Relaxed) {
+            aborted = true;
+            break;
+        }
+        let fen_before = match index {
+            0 => initial_fen.clone(),
+            _ => game.moves[index - 1].fen.clone(),
+        };
+        let before = match previous.take() {
+            Some((fen, result)) if fen == fen_before => result,
+            _ => analyser.search(&fen_before, config.limit, timeout),
+        };
+        let fen_after = game.moves[index].fen.clone();
+        let after = analyser.search(&fen_after, config.limit, timeout);
+        let error = before.as_ref().err().or(after.as_ref().err()).cloned();
+        if error.is_some() {
+            failed_moves.push(index);
+        }
+
+        let score_before = before.as_ref().ok().and_then(|r| r.score.as_ref()).map(notation::encode_score);
+        // The position after the move is scored for the opponent; negate for the mover
+        let score_after = after
+            .as_ref()
+            .ok()
+            .and_then(|r| r.score.as_ref())
+            .map(|s| -notation::encode_score(s));
+
+        let entry = &mut game.moves[index];
+        let annotation = match (score_before, score_after) {
+            (Some(b), Some(a)) => {
+                let mover = Position::from_fen(&fen_before)
+                    .map(|p| p.side_to_move())
+                    .unwrap_or(if completed % 2 == 0 { Side::Red } else { Side::Black });
+                let totals = if mover == Side::Red { &mut red } else { &mut black };
+                let annotation = pick_annotation(a - b, b, a);
+                totals.record(b, a, annotation);
+                annotation
+            }
+            _ => None,
+        };
+        if let Ok(result) = &before {
+            entry.engine_score = score_before.map(f64::from);
+            entry.engine_time = Some(result.time_ms);
+            if let Some(depth) = result.depth {
+                entry.extra.insert("engineDepth".into(), depth.into());
+            }
+            if let Some(nodes) = result.nodes {
+                entry.extra.insert("engineNodes".into(), nodes.into());
+            }
+        }
+        // Without a new annotation, keep whatever the notation already had (possibly typed in by the user)
+        if let Some(annotation) = annotation {
+            entry.annotation = Some(annotation.to_string());
+        }
+
+        on_event(&ReviewEvent::MoveAnnotated {
+            index,
+            data: entry.data.clone(),
+            engine_score: score_before,
+            annotation: entry.annotation.clone(),
+            error,
+        });
+        on_event(&ReviewEvent::Progress {
+            completed: completed + 1,
+            total,
+        });
+        previous = Some((fen_after, after));
+    }
+
+    analyser.shutdown();
+    on_event(&ReviewEvent::Finished { aborted });
+    Ok(ReviewOutput {
+        notation: game,
+        red: red.summary(),
+        black: black.summary(),
+        failed_moves,
+        aborted,
+    })
+}
+
+#[derive(Default)]
+struct Totals {
+    moves: u32,
+    loss: i64,
+    blunders: u32,
+    mistakes: u32,
+    inaccuracies: u32,
+}
+
+impl Totals {
+    fn record(&mut self, before: i32, after: i32, annotation: Option<&str>) {
+        self.moves += 1;
+        let before = before.clamp(-CPL_CAP, CPL_CAP);
+        let after = after.clamp(-CPL_CAP, CPL_CAP);
+        self.loss += (before - after).max(0) as i64;
+        match annotation {
+            Some("??") => self.blunders += 1,
+            Some("?") => self.mistakes += 1,
+            Some("?!") => self.inaccuracies += 1,
+            _ => {}
+        }
+    }
+
+    fn summary(&self) -> SideReview {
+        SideReview {
+            moves: self.moves,
+            average_centipawn_loss: if self.moves > 0 { self.loss as f64 / self.moves as f64 } else { 0.0 },
+            blunders: self.blunders,
+            mistakes: self.mistakes,
+            inaccuracies: self.inaccuracies,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn annotation_thresholds_match_the_review_dialog() {
+        let cases = [
+            (-300, Some("??")),
+            (-299, Some("?")),
+            (-150, Some("?")),
+            (-149, Some("?!")),
+            (-60, Some("?!")),
+            (-59, None),
+            (0, None),
+            (59, None),
+            (60, Some("!?")),
+            (149, Some("!?")),
+            (150, Some("!")),
+            (299, Some("!")),
+            (300, Some("!!")),
+        ];
+        for (delta, expected) in cases {
+            assert_eq!(pick_annotation(delta, 100, 100 + delta), expected, "delta {}", delta);
+        }
+    }
+
+    #[test]
+    fn mate_annotations() {
+        let mate_in = |ply: i32| MATE_SCORE_BASE - ply;
+        let cases = [
+            // Threw away a forced mate
+            (mate_in(5), 200, Some("??")),
+            (mate_in(5), -mate_in(4), Some("??")),
+            // Got the mate handed back
+            (-mate_in(4), mate_in(3), Some("!!")),
+            // Still mating: only a much longer route is a mistake
+            (mate_in(5), mate_in(7), None),
+            (mate_in(5), mate_in(8), Some("?")),
+            (mate_in(5), mate_in(3), None),
+            // Getting mated either way: only large swings count
+            (-200, -mate_in(9), Some("??")),
+            (-mate_in(9), -mate_in(20), None),
+            (-mate_in(9), -200, Some("!!")),
+        ];
+        for (before, after, expected) in cases {
+            assert_eq!(pick_annotation(after - before, before, after), expected, "{} -> {}", before, after);
+        }
+    }
+
+    #[cfg(unix)]
+    fn engine(name: &str, on_go: &str) -> EngineSpec {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("jieqibox-review-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        let script = format!(
+            "#!/bin/sh\nwhile read -r line; do\n  case \"$line\" in\n    uci) echo uciok ;;\n    isready) echo readyok ;;\n    go*) {} ;;\n    quit) exit 0 ;;\n  esac\ndone\n",
+            on_go
+        );
+        std::fs::write(&path, script).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        EngineSpec {
+            name: name.to_string(),
+            path: path.to_string_lossy().into_owned(),
+            ..Default::default()
+        }
+    }
+
+    #[cfg(unix)]
+    fn game(moves: &[&str]) -> GameNotation {
+        let mut position = Position::from_fen(crate::jieqi::START_FEN).unwrap();
+        let mut rng = crate::jieqi::Rng::new(1);
+        let moves = moves
+            .iter()
+            .map(|m| {
+                let applied = position.apply_move(m, &mut rng).unwrap();
+                notation::NotationMove {
+                    kind: "move".into(),
+                    data: applied.notation,
+                    fen: position.to_fen(),
+                    ..Default::default()
+                }
+            })
+            .collect();
+        GameNotation {
+            metadata: notation::NotationMetadata {
+                initial_fen: crate::jieqi::START_FEN.into(),
+                ..Default::default()
+            },
+            moves,
+        }
+    }
+
+    #[cfg(unix)]
+    fn review(engine: EngineSpec, game: GameNotation) -> (ReviewOutput, Vec<ReviewEvent>) {
+        let config = ReviewConfig {
+            engine,
+            limit: SearchLimit::Depth(1),
+            timeout_ms: Some(2000),
+        };
+        let mut events = Vec::new();
+        let output = review_game(game, &config, &AtomicBool::new(false), |e| events.push(e.clone())).unwrap();
+        (output, events)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn failed_searches_are_reported_per_move() {
+        let (output, events) = review(engine("crash", "exit 1"), game(&["h2e2", "h9g7"]));
+        assert_eq!(output.failed_moves, [0, 1]);
+        assert_eq!((output.red.moves, output.black.moves), (0, 0));
+        let errors = events
+            .iter()
+            .filter(|e| matches!(e, ReviewEvent::MoveAnnotated { error: Some(_), .. }))
+            .count();
+        assert_eq!(errors, 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn existing_annotations_survive_a_quiet_review() {
+        let mut game = game(&["h2e2", "h9g7"]);
+        game.moves[0].annotation = Some("!".into());
+        let (output, _) = review(engine("level", "echo 'info depth 1 score cp 0'; echo 'bestmove a0a1'"), game);
+        assert!(output.failed_moves.is_empty());
+        assert_eq!((output.red.moves, output.black.moves), (1, 1));
+        assert_eq!(output.notation.moves[0].annotation.as_deref(), Some("!"));
+        assert_eq!(output.notation.moves[1].annotation, None);
+    }
+}
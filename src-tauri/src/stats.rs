@@ -0,0 +1,254 @@
+// Match statistics: Elo estimate, confidence interval, LOS and SPRT,
+// from either game (WDL) counts or game-pair (pentanomial) counts.
+use serde::{Deserialize, Serialize};
+
+// Two-sided 95% quantile of the standard normal distribution
+const Z_95: f64 = 1.959963984540054;
+
+// Scores are kept this far from 0 and 1 so a shut-out reports a finite Elo (about ±1200)
+const SCORE_EPSILON: f64 = 1e-3;
+
+// Added to every outcome's count for the variance of degenerate results only (identical
+// results, or one side never scoring), so they still have some uncertainty and the SPRT
+// keeps moving instead of stalling at 0
+const PSEUDO_COUNT: f64 = 0.5;
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct WdlCounts {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+/// Game-pair counts by pair score: [LL, LD+DL, LW+DD+WL, DW+WD, WW], as in eloCalculator.ts.
+pub type Pentanomial = [u32; 5];
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct SprtConfig {
+    pub elo0: f64,
+    pub elo1: f64,
+    pub alpha: f64,
+    pub beta: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SprtDecision {
+    Continue,
+    AcceptH0,
+    AcceptH1,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SprtResult {
+    pub llr: f64,
+    pub lower_bound: f64,
+    pub upper_bound: f64,
+    pub decision: SprtDecision,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MatchStatistics {
+    pub games: u32,
+    pub score_rate: f64,
+    pub elo: f64,
+    pub elo_error: f64,
+    pub confidence_interval: (f64, f64),
+    pub los: f64,
+    pub sprt: Option<SprtResult>,
+}
+
+/// Outcomes with their probability mass: (score, count). Scores are per game for WDL and
+/// per pair, normalised to 0..1, for pentanomial counts. The mean and variance are the
+/// observed ones, except that the variance of a shut-out (no outcome on one side of an even
+/// score) or of zero spread is regularised with `PSEUDO_COUNT` per outcome, so it is never zero.
+fn distribution(outcomes: &[(f64, u32)]) -> Option<(f64, f64, f64)> {
+    let n: u32 = outcomes.iter().map(|(_, c)| c).sum();
+    if n == 0 {
+        return None;
+    }
+    let n = n as f64;
+    let mean = outcomes.iter().map(|&(s, c)| s * c as f64).sum::<f64>() / n;
+    let variance = outcomes.iter().map(|&(s, c)| (s - mean).powi(2) * c as f64).sum::<f64>() / n;
+    let one_sided = |side: fn(f64) -> bool| outcomes.iter().all(|&(s, c)| c == 0 || side(s));
+    let shut_out = one_sided(|s| s >= 0.5) || one_sided(|s| s <= 0.5);
+    if variance > 0.0 && !shut_out {
+        return Some((n, mean, variance));
+    }
+    let variance = outcomes
+        .iter()
+        .map(|&(s, c)| (s - mean).powi(2) * (c as f64 + PSEUDO_COUNT))
+        .sum::<f64>()
+        / (n + PSEUDO_COUNT * outcomes.len() as f64);
+    Some((n, mean, variance))
+}
+
+pub fn from_wdl(counts: WdlCounts, sprt: Option<&SprtConfig>) -> Option<MatchStatistics> {
+    let outcomes = [(1.0, counts.wins), (0.5, counts.draws), (0.0, counts.losses)];
+    let (n, mean, variance) = distribution(&outcomes)?;
+    Some(build(n as u32, n, mean, variance, sprt))
+}
+
+pub fn from_pentanomial(pairs: Pentanomial, sprt: Option<&SprtConfig>) -> Option<MatchStatistics> {
+    let outcomes: Vec<(f64, u32)> = pairs
+        .iter()
+        .enumerate()
+        .map(|(i, &c)| (i as f64 / 4.0, c))
+        .collect();
+    let (n, mean, variance) = distribution(&outcomes)?;
+    Some(build(2 * n as u32, n, mean, variance, sprt))
+}
+
+// `samples` is the number of independent observations (games or pairs) behind `mean` and `variance`
+fn build(games: u32, samples: f64, mean: f64, variance: f64, sprt: Option<&SprtConfig>) -> MatchStatistics {
+    let std_error = (variance / samples).sqrt();
+    let low = elo_from_score(mean - Z_95 * std_error);
+    let high = elo_from_score(mean + Z_95 * std_error);
+    let los = normal_cdf((mean - 0.5) / std_error);
+
+    MatchStatistics {
+        games,
+        score_rate: mean,
+        elo: elo_from_score(mean),
+        elo_error: (high - low) / 2.0,
+        confidence_interval: (low, high),
+        los,
+        sprt: sprt.map(|config| sprt_llr(samples, mean, variance, config)),
+    }
+}
+
+/// Generalised SPRT log-likelihood ratio using the normal approximation for logistic Elo bounds.
+fn sprt_llr(samples: f64, mean: f64, variance: f64, config: &SprtConfig) -> SprtResult {
+    let lower_bound = (config.beta / (1.0 - config.alpha)).ln();
+    let upper_bound = ((1.0 - config.beta) / config.alpha).ln();
+    let s0 = score_from_elo(config.elo0);
+    let s1 = score_from_elo(config.elo1);
+    let llr = samples * (s1 - s0) * (2.0 * mean - s0 - s1) / (2.0 * variance);
+    let decision = if llr >= upper_bound {
+        SprtDecision::AcceptH1
+    } else if llr <= lower_bound {
+        SprtDecision::AcceptH0
+    } else {
+        SprtDecision::Continue
+    };
+    SprtResult {
+        llr,
+        lower_bound,
+        upper_bound,
+        decision,
+    }
+}
+
+/// Logistic Elo difference for a score rate, clamped to `SCORE_EPSILON` from 0% and 100%.
+pub fn elo_from_score(score: f64) -> f64 {
+    let score = score.clamp(SCORE_EPSILON, 1.0 - SCORE_EPSILON);
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+pub fn score_from_elo(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+fn normal_cdf(x: f64) -> f64 {
+    0.5 * (1.0 + erf(x / std::f64::consts::SQRT_2))
+}
+
+// Abramowitz & Stegun 7.1.26, accurate to about 1.5e-7 which is plenty for LOS
+fn erf(x: f64) -> f64 {
+    let sign = if x < 0.0 { -1.0 } else { 1.0 };
+    let x = x.abs();
+    let t = 1.0 / (1.0 + 0.3275911 * x);
+    let poly = t * (0.254829592 + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    sign * (1.0 - poly * (-x * x).exp())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPRT: SprtConfig = SprtConfig {
+        elo0: 0.0,
+        elo1: 5.0,
+        alpha: 0.05,
+        beta: 0.05,
+    };
+
+    fn wdl(wins: u32, draws: u32, losses: u32) -> MatchStatistics {
+        from_wdl(WdlCounts { wins, draws, losses }, Some(&SPRT)).unwrap()
+    }
+
+    #[test]
+    fn elo_matches_the_logistic_curve() {
+        assert!(elo_from_score(0.5).abs() < 1e-9);
+        assert!((elo_from_score(0.75) - 190.848).abs() < 1e-3);
+        assert!((score_from_elo(elo_from_score(0.3)) - 0.3).abs() < 1e-12);
+        let stats = wdl(30, 40, 30);
+        assert_eq!(stats.games, 100);
+        assert!(stats.elo.abs() < 1e-9);
+        assert!((stats.los - 0.5).abs() < 1e-6);
+        assert!(stats.confidence_interval.0 < 0.0 && stats.confidence_interval.1 > 0.0);
+    }
+
+    #[test]
+    fn shut_outs_have_a_finite_elo_and_error() {
+        for stats in [wdl(10, 0, 0), wdl(0, 0, 10)] {
+            assert!(stats.elo.is_finite());
+            assert!(stats.elo_error.is_finite() && stats.elo_error > 0.0);
+            assert!(stats.confidence_interval.0.is_finite() && stats.confidence_interval.1.is_finite());
+            assert!(serde_json::to_value(&stats).unwrap()["elo"].is_f64());
+        }
+        assert!(wdl(10, 0, 0).elo > 1000.0);
+        assert!(wdl(0, 0, 10).elo < -1000.0);
+        assert!(wdl(10, 0, 0).los > 0.99);
+    }
+
+    #[test]
+    fn sprt_moves_on_results_without_variance() {
+        let wins = wdl(200, 0, 0).sprt.unwrap();
+        assert!(wins.llr > 0.0);
+        assert_eq!(wins.decision, SprtDecision::AcceptH1);
+        let losses = wdl(0, 0, 200).sprt.unwrap();
+        assert_eq!(losses.decision, SprtDecision::AcceptH0);
+        // All draws: slightly below the midpoint of the hypotheses, so it leans to H0
+        let draws = wdl(0, 400, 0).sprt.unwrap();
+        assert!(draws.llr < 0.0 && draws.llr.is_finite());
+    }
+
+    #[test]
+    fn sprt_bounds_and_decisions() {
+        let result = wdl(60, 80, 60).sprt.unwrap();
+        assert!((result.lower_bound + 2.944).abs() < 1e-3);
+        assert!((result.upper_bound - 2.944).abs() < 1e-3);
+        assert_eq!(result.decision, SprtDecision::Continue);
+        assert_eq!(wdl(1500, 1000, 1000).sprt.unwrap().decision, SprtDecision::AcceptH1);
+        assert_eq!(wdl(1000, 1000, 1500).sprt.unwrap().decision, SprtDecision::AcceptH0);
+    }
+
+    #[test]
+    fn only_shut_outs_are_regularised() {
+        // An ordinary result keeps its sample variance: 60 decisive games of 100, each 0.5 off
+        let (n, mean, variance) = distribution(&[(1.0, 30), (0.5, 40), (0.0, 30)]).unwrap();
+        assert_eq!((n, mean), (100.0, 0.5));
+        assert!((variance - 0.15).abs() < 1e-12);
+        // No losses at all: the observed spread is widened
+        let (_, mean, variance) = distribution(&[(1.0, 10), (0.5, 2), (0.0, 0)]).unwrap();
+        let observed = (10.0 * (1.0 - mean).powi(2) + 2.0 * (0.5 - mean).powi(2)) / 12.0;
+        assert!(variance > observed);
+    }
+
+    #[test]
+    fn pentanomial_counts_pairs() {
+        assert!(from_pentanomial([0; 5], None).is_none());
+        let stats = from_pentanomial([5, 10, 20, 10, 5], Some(&SPRT)).unwrap();
+        assert_eq!(stats.games, 100);
+        assert!((stats.score_rate - 0.5).abs() < 1e-12);
+        // Pairs that mostly cancel out are tighter than the same games counted one by one
+        let games = wdl(30, 40, 30);
+        let pairs = from_pentanomial([0, 10, 30, 10, 0], None).unwrap();
+        assert_eq!(pairs.games, games.games);
+        assert!(pairs.elo_error < games.elo_error);
+        let sweep = from_pentanomial([0, 0, 0, 0, 50], Some(&SPRT)).unwrap();
+        assert!(sweep.elo.is_finite());
+        assert_eq!(sweep.sprt.unwrap().decision, SprtDecision::AcceptH1);
+    }
+}
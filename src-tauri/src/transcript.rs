@@ -0,0 +1,118 @@
+// Engine transcripts: every line sent to and received from an engine, timestamped to the
+// millisecond, so an exact log can be attached to bug reports for engine authors.
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Transcript of a GUI engine session, shared by the output reader and `send_to_engine`.
+pub type SharedTranscript = Arc<Mutex<Option<Transcript>>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Written to the engine's stdin.
+    Sent,
+    Stdout,
+    Stderr,
+    /// Lines from the GUI itself: session start, exits and restarts.
+    Note,
+}
+
+impl Direction {
+    fn marker(self) -> &'static str {
+        match self {
+            Direction::Sent => ">>",
+            Direction::Stdout => "<<",
+            Direction::Stderr => "!!",
+            Direction::Note => "--",
+        }
+    }
+}
+
+pub struct Transcript {
+    path: PathBuf,
+    writer: BufWriter<File>,
+}
+
+impl Transcript {
+    /// Create (or truncate) the transcript file and write a header naming the engine.
+    pub fn create<P: AsRef<Path>>(path: P, engine_path: &str, args: &[String]) -> Result<Self, String> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        }
+        let file = File::create(&path).map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+        let mut transcript = Transcript {
+            path,
+            writer: BufWriter::new(file),
+        };
+        let command_line = std::iter::once(engine_path.to_string()).chain(args.iter().cloned()).collect::<Vec<_>>();
+        transcript.record(Direction::Note, &format!("engine {}", command_line.join(" ")));
+        Ok(transcript)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append one line as `<date> <time.ms> <marker> <line>`. Each line is flushed straight
+    /// away so the file is complete even if the app or the engine crashes. Write errors are
+    /// ignored: a broken transcript must never take the engine down with it.
+    pub fn record(&mut self, direction: Direction, line: &str) {
+        let timestamp = chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f");
+        let _ = writeln!(self.writer, "{} {} {}", timestamp, direction.marker(), line.trim_end_matches(['\r', '\n']));
+        let _ = self.writer.flush();
+    }
+}
+
+/// Record a line if the session has a transcript open.
+pub fn record(transcript: &SharedTranscript, direction: Direction, line: &str) {
+    if let Some(transcript) = transcript.lock().unwrap().as_mut() {
+        transcript.record(direction, line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Split a transcript line into its date, time, marker and text.
+    fn fields(line: &str) -> (&str, &str, &str, &str) {
+        let mut parts = line.splitn(4, ' ');
+        let mut next = || parts.next().unwrap_or_default();
+        (next(), next(), next(), next())
+    }
+
+    #[test]
+    fn lines_are_timestamped_and_tagged_by_direction() {
+        let dir = std::env::temp_dir().join(format!("jieqibox-transcript-{}", std::process::id()));
+        let path = dir.join("logs").join("engine.log");
+        let shared: SharedTranscript = Arc::new(Mutex::new(Some(
+            Transcript::create(&path, "/opt/engine", &["--threads".to_string(), "2".to_string()]).unwrap(),
+        )));
+        record(&shared, Direction::Sent, "uci\n");
+        record(&shared, Direction::Stdout, "id name Test\r\n");
+        record(&shared, Direction::Stderr, "warning: no book");
+        record(&shared, Direction::Note, "exited with code 0");
+
+        let text = fs::read_to_string(&path).unwrap();
+        let lines: Vec<_> = text.lines().map(fields).collect();
+        let tagged: Vec<_> = lines.iter().map(|&(_, _, marker, line)| (marker, line)).collect();
+        assert_eq!(
+            tagged,
+            [
+                ("--", "engine /opt/engine --threads 2"),
+                (">>", "uci"),
+                ("<<", "id name Test"),
+                ("!!", "warning: no book"),
+                ("--", "exited with code 0"),
+            ]
+        );
+        for (date, time, _, _) in lines {
+            assert!(chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").is_ok(), "date {:?}", date);
+            assert!(chrono::NaiveTime::parse_from_str(time, "%H:%M:%S%.3f").is_ok(), "time {:?}", time);
+            assert_eq!(time.len(), "00:00:00.000".len(), "time {:?}", time);
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,318 @@
+use serde::{Deserialize, Serialize};
+
+/// Assembles complete lines out of arbitrary output chunks.
+/// A chunk may hold half a line or several lines; only finished lines are returned.
+#[derive(Debug, Default)]
+pub struct LineBuffer {
+    pending: Vec<u8>,
+}
+
+impl LineBuffer {
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<Vec<u8>> {
+        let mut lines = Vec::new();
+        for &byte in chunk {
+            if byte == b'\n' {
+                lines.push(self.take_pending());
+            } else {
+                self.pending.push(byte);
+            }
+        }
+        lines
+    }
+
+    /// Return whatever is left once the stream has ended, if anything.
+    pub fn flush(&mut self) -> Option<Vec<u8>> {
+        if self.pending.is_empty() {
+            None
+        } else {
+            Some(self.take_pending())
+        }
+    }
+
+    fn take_pending(&mut self) -> Vec<u8> {
+        let mut line = std::mem::take(&mut self.pending);
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        line
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "value", rename_all = "snake_case")]
+pub enum Score {
+    Cp(i32),
+    Mate(i32),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Wdl {
+    pub win: u32,
+    pub draw: u32,
+    pub loss: u32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct InfoLine {
+    pub depth: Option<u32>,
+    pub seldepth: Option<u32>,
+    pub multipv: Option<u32>,
+    pub score: Option<Score>,
+    pub lowerbound: bool,
+    pub upperbound: bool,
+    pub wdl: Option<Wdl>,
+    pub nodes: Option<u64>,
+    pub nps: Option<u64>,
+    pub time: Option<u64>,
+    pub hashfull: Option<u32>,
+    pub currmove: Option<String>,
+    pub pv: Vec<String>,
+    pub string: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct UciOption {
+    pub name: String,
+    pub option_type: String,
+    pub default: Option<String>,
+    pub min: Option<i64>,
+    pub max: Option<i64>,
+    pub vars: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum UciMessage {
+    Id { field: String, value: String },
+    UciOk,
+    ReadyOk,
+    BestMove { best_move: String, ponder: Option<String> },
+    Info(InfoLine),
+    Option(UciOption),
+}
+
+/// Parse one complete engine output line. Lines that are not part of the
+/// UCI vocabulary we care about return `None` and only appear in the raw transcript.
+pub fn parse_line(line: &str) -> Option<UciMessage> {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    let (&command, rest) = tokens.split_first()?;
+
+    match command {
+        "uciok" | "ucciok" | "jaiok" => Some(UciMessage::UciOk),
+        "readyok" => Some(UciMessage::ReadyOk),
+        "id" => {
+            let (&field, value) = rest.split_first()?;
+            Some(UciMessage::Id {
+                field: field.to_string(),
+                value: value.join(" "),
+            })
+        }
+        "bestmove" => {
+            let best_move = rest.first()?.to_string();
+            let ponder = match rest.get(1) {
+                Some(&"ponder") => rest.get(2).map(|m| m.to_string()),
+                _ => None,
+            };
+            Some(UciMessage::BestMove { best_move, ponder })
+        }
+        "info" => Some(UciMessage::Info(parse_info(rest))),
+        "option" => parse_option(rest).map(UciMessage::Option),
+        _ => None,
+    }
+}
+
+fn parse_info(tokens: &[&str]) -> InfoLine {
+    let mut info = InfoLine::default();
+    let mut i = 0;
+
+    while i < tokens.len() {
+        let value = tokens.get(i + 1).copied();
+        match tokens[i] {
+            "depth" => info.depth = value.and_then(|v| v.parse().ok()),
+            "seldepth" => info.seldepth = value.and_then(|v| v.parse().ok()),
+            "multipv" => info.multipv = value.and_then(|v| v.parse().ok()),
+            "nodes" => info.nodes = value.and_then(|v| v.parse().ok()),
+            "nps" => info.nps = value.and_then(|v| v.parse().ok()),
+            "time" => info.time = value.and_then(|v| v.parse().ok()),
+            "hashfull" => info.hashfull = value.and_then(|v| v.parse().ok()),
+            "currmove" => info.currmove = value.map(|v| v.to_string()),
+            "score" => {
+                let amount = tokens.get(i + 2).and_then(|v| v.parse().ok());
+                info.score = match (value, amount) {
+                    (Some("cp"), Some(cp)) => Some(Score::Cp(cp)),
+                    (Some("mate"), Some(mate)) => Some(Score::Mate(mate)),
+                    _ => None,
+                };
+                i += 3;
+                continue;
+            }
+            "lowerbound" => {
+                info.lowerbound = true;
+                i += 1;
+                continue;
+            }
+            "upperbound" => {
+                info.upperbound = true;
+                i += 1;
+                continue;
+            }
+            "wdl" => {
+                let values: Vec<u32> = tokens[i + 1..]
+                    .iter()
+                    .take(3)
+                    .filter_map(|v| v.parse().ok())
+                    .collect();
+                if values.len() == 3 {
+                    info.wdl = Some(Wdl {
+                        win: values[0],
+                        draw: values[1],
+                        loss: values[2],
+                    });
+                }
+                i += 4;
+                continue;
+            }
+            // `pv` and `string` consume the rest of the line
+            "pv" => {
+                info.pv = tokens[i + 1..].iter().map(|m| m.to_string()).collect();
+                break;
+            }
+            "string" => {
+                info.string = Some(tokens[i + 1..].join(" "));
+                break;
+            }
+            _ => {
+                i += 1;
+                continue;
+            }
+        }
+        i += 2;
+    }
+
+    info
+}
+
+fn parse_option(tokens: &[&str]) -> Option<UciOption> {
+    const KEYWORDS: [&str; 6] = ["name", "type", "default", "min", "max", "var"];
+
+    let mut option = UciOption::default();
+    let mut i = 0;
+
+    // UCCI omits the `name` keyword: `option usemillisec type check default true`
+    while i < tokens.len() && !KEYWORDS.contains(&tokens[i]) {
+        i += 1;
+    }
+    option.name = tokens[..i].join(" ");
+
+    while i < tokens.len() {
+        let keyword = tokens[i];
+        if !KEYWORDS.contains(&keyword) {
+            i += 1;
+            continue;
+        }
+        // Values (option names in particular) may contain spaces, so read up to the next keyword
+        let mut end = i + 1;
+        while end < tokens.len() && !KEYWORDS.contains(&tokens[end]) {
+            end += 1;
+        }
+        let value = tokens[i + 1..end].join(" ");
+        match keyword {
+            "name" => option.name = value,
+            "type" => option.option_type = value,
+            "default" => option.default = Some(value),
+            "min" => option.min = value.parse().ok(),
+            "max" => option.max = value.parse().ok(),
+            "var" => option.vars.push(value),
+            _ => {}
+        }
+        i = end;
+    }
+
+    if option.name.is_empty() {
+        None
+    } else {
+        Some(option)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn option(line: &str) -> UciOption {
+        match parse_line(line) {
+            Some(UciMessage::Option(option)) => option,
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn options_with_spaces_in_names_and_values() {
+        let hash = option("option name Hash Size type spin default 16 min 1 max 1024");
+        assert_eq!(hash.name, "Hash Size");
+        assert_eq!(hash.option_type, "spin");
+        assert_eq!(hash.default.as_deref(), Some("16"));
+        assert_eq!((hash.min, hash.max), (Some(1), Some(1024)));
+
+        let file = option("option name EvalFile type string default nn/pikafish v2.nnue");
+        assert_eq!(file.default.as_deref(), Some("nn/pikafish v2.nnue"));
+
+        let style = option("option name Style type combo default Normal var Solid var Normal var Risky");
+        assert_eq!(style.vars, ["Solid", "Normal", "Risky"]);
+
+        let empty = option("option name Book File type string default");
+        assert_eq!(empty.default.as_deref(), Some(""));
+        assert_eq!(option("option name Clear Hash type button").default, None);
+    }
+
+    #[test]
+    fn ucci_options_without_the_name_keyword() {
+        let millisec = option("option usemillisec type check default true");
+        assert_eq!(millisec.name, "usemillisec");
+        assert_eq!(millisec.option_type, "check");
+        assert_eq!(millisec.default.as_deref(), Some("true"));
+        assert_eq!(parse_line("option type spin default 1"), None);
+    }
+
+    #[test]
+    fn info_lines() {
+        let line = "info depth 12 seldepth 20 multipv 2 score cp -35 lowerbound wdl 100 800 100 \
+                    nodes 12345 nps 99 time 10 hashfull 5 pv h2e2 h9g7";
+        let Some(UciMessage::Info(info)) = parse_line(line) else { panic!() };
+        assert_eq!((info.depth, info.seldepth, info.multipv), (Some(12), Some(20), Some(2)));
+        assert_eq!(info.score, Some(Score::Cp(-35)));
+        assert!(info.lowerbound && !info.upperbound);
+        assert_eq!(info.wdl, Some(Wdl { win: 100, draw: 800, loss: 100 }));
+        assert_eq!((info.nodes, info.nps, info.time, info.hashfull), (Some(12345), Some(99), Some(10), Some(5)));
+        assert_eq!(info.pv, ["h2e2", "h9g7"]);
+
+        let Some(UciMessage::Info(info)) = parse_line("info score mate -3 string mate in 3 found") else { panic!() };
+        assert_eq!(info.score, Some(Score::Mate(-3)));
+        assert_eq!(info.string.as_deref(), Some("mate in 3 found"));
+    }
+
+    #[test]
+    fn handshake_and_bestmove() {
+        assert_eq!(parse_line("ucciok"), Some(UciMessage::UciOk));
+        assert_eq!(parse_line("readyok"), Some(UciMessage::ReadyOk));
+        assert_eq!(
+            parse_line("id name Pikafish 2024"),
+            Some(UciMessage::Id { field: "name".into(), value: "Pikafish 2024".into() })
+        );
+        assert_eq!(
+            parse_line("bestmove h2e2 ponder h9g7"),
+            Some(UciMessage::BestMove { best_move: "h2e2".into(), ponder: Some("h9g7".into()) })
+        );
+        assert_eq!(parse_line("bestmove"), None);
+        assert_eq!(parse_line("copyprotection ok"), None);
+    }
+
+    #[test]
+    fn line_buffer_joins_chunks_and_strips_cr() {
+        let mut buffer = LineBuffer::default();
+        assert_eq!(buffer.push(b"abc\r\nde"), [b"abc".to_vec()]);
+        assert_eq!(buffer.push(b"f\n\ng"), [b"def".to_vec(), Vec::new()]);
+        assert_eq!(buffer.flush(), Some(b"g".to_vec()));
+        assert_eq!(buffer.flush(), None);
+    }
+}
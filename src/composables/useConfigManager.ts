@@ -4,11 +4,22 @@ import Ini from 'ini'
 import { isAndroidPlatform as checkAndroidPlatform } from '../utils/platform'
 
 // Add this new interface and export it
+export type EngineEncoding = 'auto' | 'utf-8' | 'gbk' | 'gb18030' | 'big5'
+
 export interface ManagedEngine {
   id: string
   name: string
   path: string
   args: string
+  // Text encoding used in both directions; auto-detected when unset
+  encoding?: EngineEncoding
+  // Engine served by another machine's engine server; `path` is then only a label
+  remote?: RemoteEndpoint
+}
+
+export interface RemoteEndpoint {
+  address: string
+  token: string
 }
 
 // Configuration data structure
@@ -352,6 +363,24 @@ export function useConfigManager() {
     }
   }
 
+  // Engines are defined here rather than as backend profiles; each is registered as a profile
+  // (by path) the first time it is loaded, and the profile ID is what it is spawned with
+  const registerEngineProfile = async (
+    engine: ManagedEngine,
+    protocol: 'uci' | 'jai'
+  ): Promise<number> => {
+    const profile = await invoke<{ id: number }>('engine_profile_register', {
+      profile: {
+        name: engine.name,
+        path: engine.path,
+        args: engine.args.split(' ').filter(Boolean),
+        protocol,
+        encoding: engine.encoding,
+      },
+    })
+    return profile.id
+  }
+
   const getLastSelectedEngineId = (): string | null => {
     return configData.value.Settings?.lastSelectedEngineId || null
   }
@@ -509,6 +538,7 @@ export function useConfigManager() {
     updateHumanVsAiSettings,
     getEngines,
     saveEngines,
+    registerEngineProfile,
     getLastSelectedEngineId,
     saveLastSelectedEngineId,
     clearLastSelectedEngineId,
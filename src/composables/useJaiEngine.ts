@@ -8,7 +8,7 @@ import { useSoundEffects } from './useSoundEffects'
 
 export interface JaiEngineLine {
   text: string
-  kind: 'sent' | 'recv'
+  kind: 'sent' | 'recv' | 'stderr'
 }
 
 // JAI option interface definition
@@ -22,6 +22,30 @@ export interface JaiOption {
   vars?: string[]
 }
 
+// Engine instance ID used for this composable's process in the backend registry
+const ENGINE_INSTANCE_ID = 'jai'
+
+interface EngineOutputPayload {
+  engine_id: string
+  data: string
+  stream: 'stdout' | 'stderr'
+}
+
+// Info and stderr lines coalesced by the backend; superseded info lines are already dropped
+interface EngineOutputBatchPayload {
+  engine_id: string
+  lines: { data: string; stream: 'stdout' | 'stderr' }[]
+}
+
+interface EngineExitedPayload {
+  engine_id: string
+  code: number | null
+  signal: number | null
+  crashed: boolean
+  restarting: boolean
+  last_lines: string[]
+}
+
 export function useJaiEngine(_generateFen: () => string, gameState: any) {
   const { t } = useI18n()
   const { validationTimeout } = useInterfaceSettings()
@@ -59,6 +83,8 @@ export function useJaiEngine(_generateFen: () => string, gameState: any) {
   const OUTPUT_THROTTLE_DELAY = 50 // Process output every 50ms maximum
 
   let unlisten: (() => void) | null = null
+  let unlistenBatch: (() => void) | null = null
+  let unlistenExited: (() => void) | null = null
 
   /* ---------- Output Throttling Functions ---------- */
   // Process pending output lines with throttling
@@ -369,7 +395,7 @@ export function useJaiEngine(_generateFen: () => string, gameState: any) {
 
     // Teardown previous engine if any
     if (isMatchRunning.value) stopMatch()
-    await invoke('kill_engine').catch(e =>
+    await invoke('kill_engine', { engineId: ENGINE_INSTANCE_ID }).catch(e =>
       console.warn('Failed to kill previous engine:', e)
     )
 
@@ -387,8 +413,9 @@ export function useJaiEngine(_generateFen: () => string, gameState: any) {
       }, validationTimeout.value)
 
       // Listen specifically for the jaiok signal
-      listen<string>('engine-output', event => {
-        if (event.payload.trim() === 'jaiok') {
+      listen<EngineOutputPayload>('engine-output', event => {
+        if (event.payload.engine_id !== ENGINE_INSTANCE_ID) return
+        if (event.payload.data.trim() === 'jaiok') {
           console.log(
             `[DEBUG] Received jaiok for ${engine.name}. Validation successful.`
           )
@@ -409,8 +436,16 @@ export function useJaiEngine(_generateFen: () => string, gameState: any) {
         `[DEBUG] Spawning JAI engine: ${engine.name}, Path: ${engine.path}, Args: ${engine.args}`
       )
       await invoke('spawn_engine', {
-        path: engine.path,
-        args: engine.args.split(' ').filter(Boolean),
+        options: {
+          engineId: ENGINE_INSTANCE_ID,
+          path: engine.path,
+          args: engine.args.split(' ').filter(Boolean),
+          encoding: engine.encoding,
+          remote: engine.remote,
+          profileId: engine.remote
+            ? undefined
+            : await useConfigManager().registerEngineProfile(engine, 'jai'),
+        },
       })
 
       // Send 'jai' to start validation
@@ -461,7 +496,7 @@ export function useJaiEngine(_generateFen: () => string, gameState: any) {
       // Clear the last selected engine ID if loading fails
       const configManager = useConfigManager()
       await configManager.clearLastSelectedEngineId()
-      await invoke('kill_engine').catch(err =>
+      await invoke('kill_engine', { engineId: ENGINE_INSTANCE_ID }).catch(err =>
         console.warn('Failed to kill invalid JAI engine:', err)
       )
     } finally {
@@ -473,7 +508,10 @@ export function useJaiEngine(_generateFen: () => string, gameState: any) {
   const send = (cmd: string) => {
     engineOutput.value.push({ text: cmd, kind: 'sent' })
 
-    invoke('send_to_engine', { command: cmd }).catch(e => {
+    invoke('send_to_engine', {
+      engineId: ENGINE_INSTANCE_ID,
+      command: cmd,
+    }).catch(e => {
       console.warn('Failed to send to JAI engine:', e)
     })
   }
@@ -605,7 +643,7 @@ export function useJaiEngine(_generateFen: () => string, gameState: any) {
       await new Promise(resolve => setTimeout(resolve, 100))
 
       // As a fallback, also kill the engine process
-      await invoke('kill_engine')
+      await invoke('kill_engine', { engineId: ENGINE_INSTANCE_ID })
       console.log(
         '[DEBUG] UNLOAD_JAI_ENGINE: Engine process terminated successfully'
       )
@@ -652,10 +690,41 @@ export function useJaiEngine(_generateFen: () => string, gameState: any) {
   /* ---------- Listen to Output ---------- */
   onMounted(async () => {
     // Central listener for all engine output for logging/display
-    unlisten = await listen<string>('engine-output', ev => {
-      const raw_ln = ev.payload
+    const handleOutputLine = (raw_ln: string, stream: 'stdout' | 'stderr') => {
+      // stderr is shown in the terminal but never parsed as JAI output
+      if (stream === 'stderr') {
+        engineOutput.value.push({ text: raw_ln, kind: 'stderr' })
+        return
+      }
       console.log(`[DEBUG] JAI_ENGINE_RAW_OUTPUT: ${raw_ln}`)
       queueOutputLine(raw_ln)
+    }
+    unlisten = await listen<EngineOutputPayload>('engine-output', ev => {
+      if (ev.payload.engine_id !== ENGINE_INSTANCE_ID) return
+      handleOutputLine(ev.payload.data, ev.payload.stream)
+    })
+    unlistenBatch = await listen<EngineOutputBatchPayload>(
+      'engine-output-batch',
+      ev => {
+        if (ev.payload.engine_id !== ENGINE_INSTANCE_ID) return
+        ev.payload.lines.forEach(line => handleOutputLine(line.data, line.stream))
+      }
+    )
+
+    // An engine that dies mid-match never reports the match as over, so reset its state here
+    unlistenExited = await listen<EngineExitedPayload>('engine-exited', ev => {
+      const { engine_id, code, signal, crashed, restarting } = ev.payload
+      if (engine_id !== ENGINE_INSTANCE_ID || !crashed) return
+      console.warn(
+        `[DEBUG] JAI_ENGINE_EXITED: code=${code} signal=${signal} restarting=${restarting}`
+      )
+      resetThrottling()
+      isMatchRunning.value = false
+      isMatchStopping.value = false
+      if (!restarting) {
+        isEngineLoaded.value = false
+        currentEngine.value = null
+      }
     })
 
     // Set up periodic cleanup for match mode
@@ -688,7 +757,9 @@ export function useJaiEngine(_generateFen: () => string, gameState: any) {
 
   onUnmounted(() => {
     unlisten?.()
-    invoke('kill_engine') // Kill engine on component unmount
+    unlistenBatch?.()
+    unlistenExited?.()
+    invoke('kill_engine', { engineId: ENGINE_INSTANCE_ID }) // Kill engine on component unmount
     resetThrottling()
 
     // Clean up periodic cleanup interval
@@ -6,6 +6,8 @@ import type {
   OpeningBookEntry,
   OpeningBookStats,
   OpeningBookImportResult,
+  OpeningBookImportReport,
+  OpeningBookImportMode,
   JieqiOpeningBookConfig,
 } from '@/types/openingBook'
 import { useInterfaceSettings } from './useInterfaceSettings'
@@ -168,31 +170,32 @@ export function useOpeningBook() {
 
   // Import opening book data
   const importData = async (
-    data: OpeningBookEntry[]
+    data: OpeningBookEntry[] | string,
+    mode: OpeningBookImportMode = 'replace'
   ): Promise<OpeningBookImportResult> => {
     try {
-      const jsonData = JSON.stringify(data)
-      const [imported, errors] = await invoke<[number, string[]]>(
+      // A string is passed through as-is, so JSON lines files need not be parsed here
+      const jsonData = typeof data === 'string' ? data : JSON.stringify(data)
+      const report = await invoke<OpeningBookImportReport>(
         'opening_book_import_entries',
         {
           jsonData,
+          mode,
         }
       )
 
       await updateStats()
 
       return {
-        success: errors.length === 0,
-        imported,
-        errors,
-        duplicates: 0, // SQLite handles duplicates automatically
+        ...report,
+        success: report.errors.length === 0,
       }
     } catch (err) {
       return {
         success: false,
         imported: 0,
+        skipped: 0,
         errors: [err instanceof Error ? err.message : 'Import failed'],
-        duplicates: 0,
       }
     }
   }
@@ -221,6 +224,9 @@ export function useOpeningBook() {
         allowedMoves: (raw && (raw.allowedMoves ?? raw.allowed_moves)) ?? 0,
         disallowedMoves:
           (raw && (raw.disallowedMoves ?? raw.disallowed_moves)) ?? 0,
+        positionsWithoutFen:
+          (raw && (raw.positionsWithoutFen ?? raw.positions_without_fen)) ??
+          0,
       }
       stats.value = mapped
     } catch (err) {
@@ -13,7 +13,7 @@ import {
 
 export interface EngineLine {
   text: string
-  kind: 'sent' | 'recv'
+  kind: 'sent' | 'recv' | 'stderr'
 }
 
 // Định nghĩa interface cho Option
@@ -23,6 +23,30 @@ interface UciOptionObj {
   defaultValue?: string | number | boolean
 }
 
+// Engine instance ID used for this composable's process in the backend registry
+const ENGINE_INSTANCE_ID = 'uci'
+
+interface EngineOutputPayload {
+  engine_id: string
+  data: string
+  stream: 'stdout' | 'stderr'
+}
+
+// Info and stderr lines coalesced by the backend; superseded info lines are already dropped
+interface EngineOutputBatchPayload {
+  engine_id: string
+  lines: { data: string; stream: 'stdout' | 'stderr' }[]
+}
+
+interface EngineExitedPayload {
+  engine_id: string
+  code: number | null
+  signal: number | null
+  crashed: boolean
+  restarting: boolean
+  last_lines: string[]
+}
+
 export function useUciEngine(generateFen: () => string, gameState: any) {
   const { t } = useI18n()
   const { useNewFenFormat, validationTimeout } = useInterfaceSettings()
@@ -85,6 +109,8 @@ export function useUciEngine(generateFen: () => string, gameState: any) {
   const MATE_OUTPUT_THROTTLE_DELAY = 300 // Slower processing for mate situations
 
   let unlisten: (() => void) | null = null
+  let unlistenBatch: (() => void) | null = null
+  let unlistenExited: (() => void) | null = null
 
   /* ---------- Helper Functions ---------- */
   const isDarkPieceMove = (uciMove: string): boolean => {
@@ -424,7 +450,7 @@ export function useUciEngine(generateFen: () => string, gameState: any) {
     // Teardown previous engine if any
     if (isThinking.value) stopAnalysis({ playBestMoveOnStop: false })
     if (isPondering.value) stopPonder({ playBestMoveOnStop: false })
-    await invoke('kill_engine').catch(e =>
+    await invoke('kill_engine', { engineId: ENGINE_INSTANCE_ID }).catch(e =>
       console.warn('Failed to kill previous engine:', e)
     )
 
@@ -442,8 +468,9 @@ export function useUciEngine(generateFen: () => string, gameState: any) {
       }, validationTimeout.value)
 
       // Listen specifically for the uciok signal
-      listen<string>('engine-output', event => {
-        if (event.payload.trim() === 'uciok') {
+      listen<EngineOutputPayload>('engine-output', event => {
+        if (event.payload.engine_id !== ENGINE_INSTANCE_ID) return
+        if (event.payload.data.trim() === 'uciok') {
           console.log(
             `[DEBUG] Received uciok for ${engine.name}. Validation successful.`
           )
@@ -464,8 +491,16 @@ export function useUciEngine(generateFen: () => string, gameState: any) {
         `[DEBUG] Spawning engine: ${engine.name}, Path: ${engine.path}, Args: ${engine.args}`
       )
       await invoke('spawn_engine', {
-        path: engine.path,
-        args: engine.args.split(' ').filter(Boolean),
+        options: {
+          engineId: ENGINE_INSTANCE_ID,
+          path: engine.path,
+          args: engine.args.split(' ').filter(Boolean),
+          encoding: engine.encoding,
+          remote: engine.remote,
+          profileId: engine.remote
+            ? undefined
+            : await useConfigManager().registerEngineProfile(engine, 'uci'),
+        },
       })
 
       // Send 'uci' to start validation
@@ -531,7 +566,7 @@ export function useUciEngine(generateFen: () => string, gameState: any) {
       // Clear the last selected engine ID if loading fails
       const configManager = useConfigManager()
       await configManager.clearLastSelectedEngineId()
-      await invoke('kill_engine').catch(err =>
+      await invoke('kill_engine', { engineId: ENGINE_INSTANCE_ID }).catch(err =>
         console.warn('Failed to kill invalid engine:', err)
       )
     } finally {
@@ -590,7 +625,10 @@ export function useUciEngine(generateFen: () => string, gameState: any) {
       )
     }
 
-    invoke('send_to_engine', { command: cmd }).catch(e => {
+    invoke('send_to_engine', {
+      engineId: ENGINE_INSTANCE_ID,
+      command: cmd,
+    }).catch(e => {
       // Don't alert here, it can be noisy during initial load failure
       console.warn('Failed to send to engine:', e)
     })
@@ -622,8 +660,9 @@ export function useUciEngine(generateFen: () => string, gameState: any) {
       }, 5000)
 
       // Listen for readyok response
-      listen<string>('engine-output', event => {
-        if (event.payload.trim() === 'readyok') {
+      listen<EngineOutputPayload>('engine-output', event => {
+        if (event.payload.engine_id !== ENGINE_INSTANCE_ID) return
+        if (event.payload.data.trim() === 'readyok') {
           console.log(
             '[DEBUG] UCI_NEWGAME: Received readyok, new game initialized'
           )
@@ -1094,7 +1133,7 @@ export function useUciEngine(generateFen: () => string, gameState: any) {
       await new Promise(resolve => setTimeout(resolve, 100))
 
       // As a fallback, also kill the engine process
-      await invoke('kill_engine')
+      await invoke('kill_engine', { engineId: ENGINE_INSTANCE_ID })
       console.log(
         '[DEBUG] UNLOAD_ENGINE: Engine process terminated successfully'
       )
@@ -1173,10 +1212,40 @@ export function useUciEngine(generateFen: () => string, gameState: any) {
   /* ---------- Listen to Output ---------- */
   onMounted(async () => {
     // Central listener for all engine output for logging/display
-    unlisten = await listen<string>('engine-output', ev => {
-      const raw_ln = ev.payload
+    const handleOutputLine = (raw_ln: string, stream: 'stdout' | 'stderr') => {
+      // stderr is shown in the terminal but never parsed as UCI output
+      if (stream === 'stderr') {
+        engineOutput.value.push({ text: raw_ln, kind: 'stderr' })
+        return
+      }
       // console.log(`[DEBUG] ENGINE_RAW_OUTPUT: ${raw_ln}`) // Comment bớt log cho đỡ spam
       queueOutputLine(raw_ln)
+    }
+    unlisten = await listen<EngineOutputPayload>('engine-output', ev => {
+      if (ev.payload.engine_id !== ENGINE_INSTANCE_ID) return
+      handleOutputLine(ev.payload.data, ev.payload.stream)
+    })
+    unlistenBatch = await listen<EngineOutputBatchPayload>(
+      'engine-output-batch',
+      ev => {
+        if (ev.payload.engine_id !== ENGINE_INSTANCE_ID) return
+        ev.payload.lines.forEach(line => handleOutputLine(line.data, line.stream))
+      }
+    )
+
+    // An engine that dies mid-search never sends bestmove, so reset the search state here
+    unlistenExited = await listen<EngineExitedPayload>('engine-exited', ev => {
+      const { engine_id, code, signal, crashed, restarting } = ev.payload
+      if (engine_id !== ENGINE_INSTANCE_ID || !crashed) return
+      console.warn(
+        `[DEBUG] ENGINE_EXITED: code=${code} signal=${signal} restarting=${restarting}`
+      )
+      isThinking.value = false
+      isPondering.value = false
+      if (!restarting) {
+        isEngineLoaded.value = false
+        currentEngine.value = null
+      }
     })
 
     // Check if engine list is empty and clear last selected engine ID if needed
@@ -1225,7 +1294,9 @@ export function useUciEngine(generateFen: () => string, gameState: any) {
   })
   onUnmounted(() => {
     unlisten?.()
-    invoke('kill_engine') // Kill engine on component unmount
+    unlistenBatch?.()
+    unlistenExited?.()
+    invoke('kill_engine', { engineId: ENGINE_INSTANCE_ID }) // Kill engine on component unmount
     resetThrottling()
   })
 
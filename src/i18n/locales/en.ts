@@ -385,6 +385,8 @@ prev.prev - Previous-previous move (supports infinite nesting)`,
     engineName: 'Engine Name',
     enginePath: 'Engine Path',
     arguments: 'Command-line Arguments',
+    encoding: 'Text Encoding',
+    encodingAuto: 'Auto-detect',
     actions: 'Actions',
     confirmDeleteTitle: 'Confirm Deletion',
     confirmDeleteMessage:
@@ -682,6 +684,8 @@ prev.prev - Previous-previous move (supports infinite nesting)`,
     draws: 'Draws',
     losses: 'Losses',
     import: 'Import',
+    confirmMerge:
+      'Merge into the current book? {added} new move(s) will be added; {conflicts} move(s) that differ keep their local data.',
     export: 'Export',
     selectFile: 'Select File',
     format: 'Format',
@@ -384,6 +384,8 @@ prev.prev - 前々の手（無限ネスト対応）`,
     engineName: 'エンジン名',
     enginePath: 'エンジンパス',
     arguments: 'コマンドライン引数',
+    encoding: '文字コード',
+    encodingAuto: '自動検出',
     actions: '操作',
     confirmDeleteTitle: '削除の確認',
     confirmDeleteMessage:
@@ -676,6 +678,8 @@ prev.prev - 前々の手（無限ネスト対応）`,
     draws: '引き分け',
     losses: '負け',
     import: 'インポート',
+    confirmMerge:
+      '現在の定跡に統合しますか？新しい手 {added} 件を追加し、内容が異なる {conflicts} 件はローカルのデータを保持します。',
     export: 'エクスポート',
     selectFile: 'ファイルを選択',
     format: 'フォーマット',
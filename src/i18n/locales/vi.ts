@@ -385,6 +385,8 @@ prev.prev - Nước đi trước-trước (hỗ trợ lồng nhau vô hạn)`,
     engineName: 'Tên động cơ',
     enginePath: 'Đường dẫn động cơ',
     arguments: 'Tham số dòng lệnh',
+    encoding: 'Mã hóa ký tự',
+    encodingAuto: 'Tự động nhận diện',
     actions: 'Hành động',
     confirmDeleteTitle: 'Xác nhận xóa',
     confirmDeleteMessage:
@@ -678,6 +680,8 @@ prev.prev - Nước đi trước-trước (hỗ trợ lồng nhau vô hạn)`,
     draws: 'Hòa',
     losses: 'Bại',
     import: 'Nhập',
+    confirmMerge:
+      'Gộp vào sách khai cuộc hiện tại? Sẽ thêm {added} nước mới; {conflicts} nước khác biệt giữ dữ liệu cục bộ.',
     export: 'Xuất',
     selectFile: 'Chọn tệp',
     format: 'Định dạng',
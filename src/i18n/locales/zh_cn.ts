@@ -380,6 +380,8 @@ prev.prev - 上上步（支持无限嵌套）`,
     engineName: '引擎名称',
     enginePath: '引擎路径',
     arguments: '命令行参数',
+    encoding: '字符编码',
+    encodingAuto: '自动检测',
     actions: '操作',
     confirmDeleteTitle: '确认删除',
     confirmDeleteMessage: '您确定要删除引擎“{name}”吗？此操作无法撤销。',
@@ -663,6 +665,8 @@ prev.prev - 上上步（支持无限嵌套）`,
     draws: '和',
     losses: '负',
     import: '导入',
+    confirmMerge:
+      '合并到当前开局库？将新增 {added} 步；{conflicts} 个不同的着法保留本地数据。',
     export: '导出',
     selectFile: '选择文件',
     format: '格式',
@@ -378,6 +378,8 @@ prev.prev - 上上步（支援無限巢狀）`,
     engineName: '引擎名稱',
     enginePath: '引擎路徑',
     arguments: '命令列參數',
+    encoding: '字元編碼',
+    encodingAuto: '自動偵測',
     actions: '操作',
     confirmDeleteTitle: '確認刪除',
     confirmDeleteMessage: '您確定要刪除引擎「{name}」嗎？此操作無法復原。',
@@ -661,6 +663,8 @@ prev.prev - 上上步（支援無限巢狀）`,
     draws: '和',
     losses: '負',
     import: '匯入',
+    confirmMerge:
+      '合併到目前開局庫？將新增 {added} 步；{conflicts} 個不同的著法保留本地資料。',
     export: '匯出',
     selectFile: '選擇檔案',
     format: '格式',
@@ -20,13 +20,23 @@ export interface OpeningBookStats {
   totalMoves: number
   allowedMoves: number
   disallowedMoves: number
+  // Positions from older books whose FEN is not known
+  positionsWithoutFen?: number
 }
 
-export interface OpeningBookImportResult {
-  success: boolean
+// What an import does with moves the book already has
+export type OpeningBookImportMode = 'replace' | 'skip_existing' | 'merge_statistics'
+
+// What the backend reports for an import
+export interface OpeningBookImportReport {
   imported: number
+  // Moves already in the book, left alone in skip_existing mode
+  skipped: number
   errors: string[]
-  duplicates: number
+}
+
+export interface OpeningBookImportResult extends OpeningBookImportReport {
+  success: boolean
 }
 
 export interface OpeningBookExportOptions {